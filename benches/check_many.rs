@@ -0,0 +1,36 @@
+//! Looping `check` vs `check_many` over a 500-document folder reached through a group.
+//! Run with `cargo bench --bench check_many`.
+
+use capbit::*;
+use std::time::Instant;
+
+fn main() {
+    init("target/bench_db").unwrap();
+    clear().unwrap();
+    let (sys, root) = bootstrap().unwrap();
+    let (folder, team, alice) = (100, 200, 10);
+    let docs: Vec<u64> = (10_000..10_500).collect();
+    let mut b = WriteBatch::new(root).set_parent(folder, sys, ALL_BITS).set_parent(team, sys, ALL_BITS)
+        .create(folder, _VIEWER, VIEWER_BITS).grant(team, folder, _VIEWER).add_member(team, alice);
+    for (i, &d) in docs.iter().enumerate() {
+        b = b.set_parent(d, folder, ALL_BITS).create(d, _EDITOR, EDITOR_BITS);
+        if i % 3 == 0 { b = b.grant(alice, d, _EDITOR); }
+    }
+    b.commit().unwrap();
+    let reqs: Vec<(u64, u64)> = docs.iter().map(|&d| (d, VIEWER_BITS)).collect();
+
+    let rounds = 20;
+    let t = Instant::now();
+    let mut looped = Vec::new();
+    for _ in 0..rounds { looped = reqs.iter().map(|&(o, r)| check(alice, o, r).unwrap()).collect(); }
+    let loop_time = t.elapsed() / rounds;
+
+    let t = Instant::now();
+    let mut batched = Vec::new();
+    for _ in 0..rounds { batched = check_many(alice, &reqs).unwrap(); }
+    let many_time = t.elapsed() / rounds;
+
+    assert_eq!(looped, batched);
+    println!("{} checks: loop {:?}, check_many {:?} ({:.1}x)", reqs.len(), loop_time, many_time,
+             loop_time.as_secs_f64() / many_time.as_secs_f64());
+}
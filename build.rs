@@ -0,0 +1,9 @@
+// Generates the gRPC service from proto/ with a pure-Rust protobuf compiler, so no protoc is needed
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/capbit.proto");
+        let fds = protox::compile(["proto/capbit.proto"], ["proto"]).expect("proto/capbit.proto");
+        tonic_build::configure().compile_fds(fds).expect("generating gRPC code");
+    }
+}
@@ -0,0 +1,117 @@
+//! Async handle for tokio services. Every call runs on the blocking pool, and at most
+//! `max_in_flight` calls run at once; further callers wait for a slot instead of queueing
+//! unbounded blocking work. A handle from `tenant` runs every call in that tenant, one from
+//! `durability` commits every call with that durability.
+
+use super::*;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+#[derive(Clone)]
+pub struct Handle { permits: Arc<Semaphore>, tenant: Option<Arc<str>>, durability: Option<Durability> }
+
+macro_rules! forward {
+    ($($name:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*) => {
+        $(pub async fn $name(&self, $($arg: $ty),*) -> Result<$ret> { self.run(move || super::$name($($arg),*)).await })*
+    };
+}
+
+impl Handle {
+    pub fn new(max_in_flight: usize) -> Self { Self { permits: Arc::new(Semaphore::new(max_in_flight.max(1))), tenant: None, durability: None } }
+
+    // Same in-flight limit, calls scoped to tenant name
+    pub fn tenant(&self, name: &str) -> Self { Self { tenant: Some(name.into()), ..self.clone() } }
+
+    // Same in-flight limit, commits made with d, e.g. handle.durability(Durability::SyncAll).revoke(...)
+    pub fn durability(&self, d: Durability) -> Self { Self { durability: Some(d), ..self.clone() } }
+
+    // Calls that may start right now without waiting
+    pub fn available(&self) -> usize { self.permits.available_permits() }
+
+    pub async fn run<T: Send + 'static>(&self, f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+        let permit = self.permits.clone().acquire_owned().await.map_err(err)?;
+        // A durable() override around the call is thread-local, so it is carried over explicitly
+        let (tenant, d) = (self.tenant.clone(), self.durability.or_else(durability::overridden));
+        tokio::task::spawn_blocking(move || {
+            let _p = permit;
+            let f = move || match tenant { Some(t) => super::tenant(&t, f), None => f() };
+            match d { Some(d) => durable(d, f), None => f() }
+        }).await.map_err(err)?
+    }
+
+    forward! {
+        bootstrap() -> (u64, u64);
+        clear() -> ();
+        get_mask(sub: u64, obj: u64) -> u64;
+        check(sub: u64, obj: u64, req: u64) -> bool;
+        explain(sub: u64, obj: u64) -> Explanation;
+        create(actor: u64, obj: u64, role: u64, mask: u64) -> ();
+        delete(actor: u64, obj: u64, role: u64) -> ();
+        update(actor: u64, obj: u64, role: u64, mask: u64) -> ();
+        get_object(actor: u64, obj: u64, role: u64) -> Option<u64>;
+        check_object(actor: u64, obj: u64, role: u64) -> bool;
+        list_roles(actor: u64, obj: u64) -> Vec<(u64, u64)>;
+        list_roles_page(actor: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Page<(u64, u64)>;
+        grant(actor: u64, sub: u64, obj: u64, role: u64) -> ();
+        revoke(actor: u64, sub: u64, obj: u64, role: u64) -> ();
+        check_subject(sub: u64, obj: u64, role: u64) -> bool;
+        list_roles_for(actor: u64, sub: u64, obj: u64) -> Vec<u64>;
+        list_roles_for_page(actor: u64, sub: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Page<u64>;
+        list_grants(actor: u64, sub: u64) -> Vec<(u64, u64)>;
+        list_grants_page(actor: u64, sub: u64, after: Option<Cursor>, limit: usize) -> Page<(u64, u64)>;
+        list_subjects(actor: u64, obj: u64) -> Vec<(u64, u64)>;
+        list_subjects_page(actor: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Page<(u64, u64)>;
+        inherit(actor: u64, sub: u64, obj: u64, role: u64, parent: u64) -> ();
+        remove_inherit(actor: u64, sub: u64, obj: u64, role: u64) -> ();
+        get_inherit(actor: u64, sub: u64, obj: u64, role: u64) -> Option<u64>;
+        check_inherit(actor: u64, sub: u64, obj: u64, role: u64) -> bool;
+        list_inherits(actor: u64, sub: u64, obj: u64) -> Vec<(u64, u64)>;
+        list_inherits_page(actor: u64, sub: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Page<(u64, u64)>;
+        list_inherits_on_obj(actor: u64, obj: u64) -> Vec<(u64, u64, u64)>;
+        list_inherits_on_obj_page(actor: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Page<(u64, u64, u64)>;
+        list_inherits_on_obj_role(actor: u64, obj: u64, role: u64) -> Vec<(u64, u64)>;
+        list_inherits_on_obj_role_page(actor: u64, obj: u64, role: u64, after: Option<Cursor>, limit: usize) -> Page<(u64, u64)>;
+        list_inherits_from_parent(actor: u64, parent: u64) -> Vec<(u64, u64, u64)>;
+        list_inherits_from_parent_page(actor: u64, parent: u64, after: Option<Cursor>, limit: usize) -> Page<(u64, u64, u64)>;
+        list_inherits_from_parent_on_obj(actor: u64, parent: u64, obj: u64) -> Vec<(u64, u64)>;
+        list_inherits_from_parent_on_obj_page(actor: u64, parent: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Page<(u64, u64)>;
+        set_parent(actor: u64, obj: u64, parent: u64, mask: u64) -> ();
+        remove_parent(actor: u64, obj: u64, parent: u64) -> ();
+        get_parent(actor: u64, obj: u64, parent: u64) -> Option<u64>;
+        list_parents(actor: u64, obj: u64) -> Vec<(u64, u64)>;
+        list_parents_page(actor: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Page<(u64, u64)>;
+        list_children(actor: u64, parent: u64) -> Vec<(u64, u64)>;
+        list_children_page(actor: u64, parent: u64, after: Option<Cursor>, limit: usize) -> Page<(u64, u64)>;
+        add_member(actor: u64, group: u64, member: u64) -> ();
+        remove_member(actor: u64, group: u64, member: u64) -> ();
+        check_member(group: u64, member: u64) -> bool;
+        list_members(actor: u64, group: u64) -> Vec<u64>;
+        list_members_page(actor: u64, group: u64, after: Option<Cursor>, limit: usize) -> Page<u64>;
+        list_groups(actor: u64, sub: u64) -> Vec<u64>;
+        list_groups_page(actor: u64, sub: u64, after: Option<Cursor>, limit: usize) -> Page<u64>;
+        undefine_bit(actor: u64, scope: u64, bit: u8) -> ();
+        list_bits(actor: u64, scope: u64) -> Vec<(u8, String, String)>;
+        list_bits_page(actor: u64, scope: u64, after: Option<Cursor>, limit: usize) -> Page<(u8, String, String)>;
+        names_from_mask(obj: u64, mask: u64) -> Vec<String>;
+        name_of(id: u64) -> Option<String>;
+        role_name(id: u64) -> Option<String>;
+    }
+
+    pub async fn get_masks(&self, sub: u64, objs: Vec<u64>) -> Result<Vec<u64>> { self.run(move || get_masks(sub, &objs)).await }
+    pub async fn check_many(&self, sub: u64, reqs: Vec<(u64, u64)>) -> Result<Vec<bool>> { self.run(move || check_many(sub, &reqs)).await }
+    pub async fn filter_authorized(&self, sub: u64, req: u64, objs: Vec<u64>) -> Result<Vec<u64>> { self.run(move || filter_authorized(sub, req, objs)).await }
+    pub async fn define_bit(&self, actor: u64, scope: u64, bit: u8, name: String, desc: String) -> Result<()> { self.run(move || define_bit(actor, scope, bit, &name, &desc)).await }
+    pub async fn mask_from_names(&self, obj: u64, names: Vec<String>) -> Result<u64> {
+        self.run(move || mask_from_names(obj, &names.iter().map(String::as_str).collect::<Vec<_>>())).await
+    }
+    pub async fn parse_mask(&self, obj: u64, s: String) -> Result<u64> { self.run(move || parse_mask(obj, &s)).await }
+    pub async fn intern(&self, name: String) -> Result<u64> { self.run(move || intern(&name)).await }
+    pub async fn lookup(&self, name: String) -> Result<Option<u64>> { self.run(move || lookup(&name)).await }
+    pub async fn intern_role(&self, name: String) -> Result<u64> { self.run(move || intern_role(&name)).await }
+    pub async fn lookup_role(&self, name: String) -> Result<Option<u64>> { self.run(move || lookup_role(&name)).await }
+
+    // The outer Err means the handle itself failed; the inner one carries per-operation results
+    pub async fn commit(&self, batch: WriteBatch) -> Result<std::result::Result<(), Vec<Result<()>>>> {
+        self.run(move || Ok(batch.commit())).await
+    }
+}
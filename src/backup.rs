@@ -0,0 +1,151 @@
+//! Online backup into a single-file archive and restore into an empty directory.
+//!
+//! ```text
+//! magic    b"CAPBITBK"
+//! version  u32
+//! count    u32                                  partitions that follow
+//! per partition:
+//!   name   u16 length + bytes
+//!   entries u32 key length + key, u32 value length + value, ended by a key length of u32::MAX
+//! crc      u32                                  CRC-32 (IEEE) of every byte before it
+//! ```
+//!
+//! Integers are big-endian. All partitions are read at one instant, so a backup taken while
+//! writes continue holds exactly the commits made before it started. Outside a tenant scope the
+//! archive holds every tenant; inside one, only that tenant's partitions.
+
+use super::*;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::{fs::File, io::{BufReader, BufWriter, Read, Write}};
+
+pub const BACKUP_VERSION: u32 = 1;
+const MAGIC: &[u8; 8] = b"CAPBITBK";
+const END: u32 = u32::MAX;
+
+// Writes the archive next to path and renames it into place once complete.
+// Returns the number of entries written.
+pub fn backup_to(path: &str) -> Result<u64> {
+    let tmp = format!("{path}.tmp");
+    let out = archive(&tmp).and_then(|n| std::fs::rename(&tmp, path).map(|()| n).map_err(err));
+    // A failed backup leaves nothing behind next to the destination
+    if out.is_err() { let _ = std::fs::remove_file(&tmp); }
+    out
+}
+
+// Writes and syncs the archive at tmp, returning the number of entries
+fn archive(tmp: &str) -> Result<u64> {
+    let at = ks().instant();
+    let mut w = Crc(BufWriter::new(File::create(tmp).map_err(err)?), !0);
+    let mut n = 0;
+    w.write_all(MAGIC).map_err(err)?;
+    w.write_u32::<BigEndian>(BACKUP_VERSION).map_err(err)?;
+    let ps = match current_tenant() {
+        Some(_) => [&parts()[..], &[LOG.get().unwrap()]].concat(),
+        None => {
+            let mut names: Vec<String> = ks().list_partitions().iter().map(|n| n.to_string()).collect();
+            names.sort();
+            names.iter().map(|n| ks().open_partition(n, PartitionCreateOptions::default()).map_err(err)).collect::<Result<Vec<_>>>()?
+        }
+    };
+    w.write_u32::<BigEndian>(ps.len() as u32).map_err(err)?;
+    for p in ps {
+        w.write_u16::<BigEndian>(p.name.len() as u16).map_err(err)?;
+        w.write_all(p.name.as_bytes()).map_err(err)?;
+        for kv in p.snapshot_at(at).iter() {
+            let (k, v) = kv.map_err(err)?;
+            for b in [&k, &v] {
+                w.write_u32::<BigEndian>(b.len() as u32).map_err(err)?;
+                w.write_all(b).map_err(err)?;
+            }
+            n += 1;
+        }
+        w.write_u32::<BigEndian>(END).map_err(err)?;
+    }
+    let crc = !w.1;
+    let mut f = w.0.into_inner().map_err(|e| err(e.into_error()))?;
+    f.write_u32::<BigEndian>(crc).map_err(err)?;
+    f.sync_all().map_err(err)?;
+    Ok(n)
+}
+
+// Verifies the whole archive before writing anything, then loads it into a new store at dir.
+// Returns the number of entries restored; dir can then be opened with init.
+pub fn restore_from(archive: &str, dir: &str) -> Result<u64> {
+    if std::fs::read_dir(dir).is_ok_and(|mut d| d.next().is_some()) { return Err(Error("Not empty".into())); }
+    verify(archive)?;
+    let mut r = BufReader::new(File::open(archive).map_err(err)?);
+    r.read_exact(&mut [0; 12]).map_err(err)?;
+    let ks = Config::new(Path::new(dir)).open().map_err(err)?;
+    let mut n = 0;
+    for _ in 0..r.read_u32::<BigEndian>().map_err(err)? {
+        let len = r.read_u16::<BigEndian>().map_err(err)?;
+        let name = String::from_utf8(bytes(&mut r, len as u32)?).map_err(err)?;
+        let p = ks.open_partition(&name, PartitionCreateOptions::default()).map_err(err)?;
+        let mut batch = ks.batch();
+        loop {
+            let len = r.read_u32::<BigEndian>().map_err(err)?;
+            if len == END { break; }
+            let k = bytes(&mut r, len)?;
+            let vlen = r.read_u32::<BigEndian>().map_err(err)?;
+            batch.insert(&p, k, bytes(&mut r, vlen)?);
+            n += 1;
+            if batch.len() >= 10_000 { std::mem::replace(&mut batch, ks.batch()).commit().map_err(err)?; }
+        }
+        batch.commit().map_err(err)?;
+    }
+    ks.persist(fjall::PersistMode::SyncAll).map_err(err)?;
+    Ok(n)
+}
+
+fn verify(archive: &str) -> Result<()> {
+    let f = File::open(archive).map_err(err)?;
+    let len = f.metadata().map_err(err)?.len();
+    let mut r = BufReader::new(f);
+    let mut head = [0; 12];
+    if len < 16 || r.read_exact(&mut head).is_err() || &head[..8] != MAGIC { return Err(Error("Invalid".into())); }
+    let version = u32::from_be_bytes(head[8..].try_into().unwrap());
+    if version != BACKUP_VERSION { return Err(Error(format!("Unsupported version: {version}"))); }
+    let (mut crc, mut buf, mut body) = (crc32(!0, &head), vec![0; 1 << 16], (&mut r).take(len - 16));
+    loop {
+        let n = body.read(&mut buf).map_err(err)?;
+        if n == 0 { break; }
+        crc = crc32(crc, &buf[..n]);
+    }
+    if r.read_u32::<BigEndian>().map_err(err)? != !crc { return Err(Error("Corrupt".into())); }
+    Ok(())
+}
+
+fn bytes(r: &mut impl Read, len: u32) -> Result<Vec<u8>> {
+    let mut b = vec![0; len as usize];
+    r.read_exact(&mut b).map_err(err)?;
+    Ok(b)
+}
+
+// Writer that keeps a running CRC-32 of everything written through it
+struct Crc<W>(W, u32);
+impl<W: Write> Write for Crc<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.0.write(buf)?;
+        self.1 = crc32(self.1, &buf[..n]);
+        Ok(n)
+    }
+    fn flush(&mut self) -> std::io::Result<()> { self.0.flush() }
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut t = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut j = 0;
+        while j < 8 { c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 }; j += 1; }
+        t[i] = c;
+        i += 1;
+    }
+    t
+};
+
+fn crc32(mut c: u32, buf: &[u8]) -> u32 {
+    for &b in buf { c = CRC_TABLE[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8); }
+    c
+}
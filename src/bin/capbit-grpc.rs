@@ -0,0 +1,50 @@
+//! capbit-grpc - gRPC service for a capbit store
+//!
+//!   capbit-grpc [--listen ADDR] [--db PATH] [--max-in-flight N]
+//!
+//! Flags fall back to CAPBIT_GRPC_LISTEN, CAPBIT_DB and CAPBIT_MAX_IN_FLIGHT; the defaults are
+//! 127.0.0.1:50051, capbit_data and 64. On SIGINT or SIGTERM the server stops accepting calls,
+//! lets in-flight ones finish and fsyncs the journal.
+
+use capbit::{aio::Handle, grpc::service, *};
+use std::process::exit;
+
+const USAGE: &str = "usage: capbit-grpc [--listen ADDR] [--db PATH] [--max-in-flight N]";
+
+#[tokio::main]
+async fn main() {
+    let env = |k: &str, d: &str| std::env::var(k).unwrap_or_else(|_| d.into());
+    let (mut listen, mut db, mut max) = (env("CAPBIT_GRPC_LISTEN", "127.0.0.1:50051"), env("CAPBIT_DB", "capbit_data"), env("CAPBIT_MAX_IN_FLIGHT", "64"));
+    let mut args = std::env::args().skip(1);
+    while let Some(a) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+        match a.as_str() {
+            "--listen" => listen = value(),
+            "--db" => db = value(),
+            "--max-in-flight" => max = value(),
+            "-h" | "--help" => { println!("{USAGE}"); return; }
+            _ => fail(USAGE),
+        }
+    }
+    let max = max.parse().unwrap_or_else(|_| fail(USAGE));
+    let addr = listen.parse().unwrap_or_else(|_| fail(USAGE));
+    init(&db).unwrap_or_else(|e| fail(&e.0));
+    eprintln!("capbit-grpc listening on {listen} (db {db})");
+    tonic::transport::Server::builder().add_service(service(Handle::new(max)))
+        .serve_with_shutdown(addr, shutdown()).await.unwrap_or_else(|e| fail(&e.to_string()));
+    flush().unwrap_or_else(|e| fail(&e.0));
+}
+
+async fn shutdown() {
+    let term = async {
+        #[cfg(unix)]
+        if let Ok(mut s) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) { s.recv().await; return }
+        std::future::pending::<()>().await
+    };
+    tokio::select! { _ = tokio::signal::ctrl_c() => {}, _ = term => {} }
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{msg}");
+    exit(1)
+}
@@ -0,0 +1,127 @@
+//! capbit-server - REST API for a capbit store
+//!
+//!   capbit-server [--listen ADDR | unix:PATH] [--socket-mode OCTAL] [--db PATH] [--max-in-flight N] [--admin-routes]
+//!                 [--replicate ADDR | --follow ADDR]
+//!
+//! Flags fall back to CAPBIT_LISTEN, CAPBIT_SOCKET_MODE, CAPBIT_DB, CAPBIT_MAX_IN_FLIGHT,
+//! CAPBIT_ADMIN_ROUTES=1, CAPBIT_REPLICATE and CAPBIT_FOLLOW; the defaults are 127.0.0.1:8080, 660,
+//! capbit_data and 64. --replicate makes the default tenant a leader shipping its log to followers
+//! on ADDR; --follow makes it a read-only follower pulling from the leader at ADDR. Both need the
+//! shared secret in CAPBIT_REPLICATION_SECRET, kept out of flags so it stays out of process
+//! listings. The role is stored, so a leader restarted without --replicate keeps logging. With
+//! unix:PATH the server listens on a Unix socket created with the given mode instead of TCP, and
+//! callers that can open it may act as any subject. On SIGINT or SIGTERM the server stops
+//! accepting connections, lets in-flight requests finish and fsyncs the journal.
+
+use capbit::{aio::Handle, http::router, replication::{Follower, Role}, *};
+use std::process::exit;
+
+const USAGE: &str = "usage: capbit-server [--listen ADDR | unix:PATH] [--socket-mode OCTAL] [--db PATH] [--max-in-flight N] [--admin-routes] [--replicate ADDR | --follow ADDR]";
+
+#[tokio::main]
+async fn main() {
+    let env = |k: &str, d: &str| std::env::var(k).unwrap_or_else(|_| d.into());
+    let (mut listen, mut db, mut max) = (env("CAPBIT_LISTEN", "127.0.0.1:8080"), env("CAPBIT_DB", "capbit_data"), env("CAPBIT_MAX_IN_FLIGHT", "64"));
+    let (mut mode, mut admin) = (env("CAPBIT_SOCKET_MODE", "660"), env("CAPBIT_ADMIN_ROUTES", "") == "1");
+    let (mut replicate, mut follow) = (std::env::var("CAPBIT_REPLICATE").ok(), std::env::var("CAPBIT_FOLLOW").ok());
+    let mut args = std::env::args().skip(1);
+    while let Some(a) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+        match a.as_str() {
+            "--listen" => listen = value(),
+            "--socket-mode" => mode = value(),
+            "--db" => db = value(),
+            "--max-in-flight" => max = value(),
+            "--admin-routes" => admin = true,
+            "--replicate" => replicate = Some(value()),
+            "--follow" => follow = Some(value()),
+            "-h" | "--help" => { println!("{USAGE}"); return; }
+            _ => fail(USAGE),
+        }
+    }
+    let max = max.parse().unwrap_or_else(|_| fail(USAGE));
+    let mode = u32::from_str_radix(&mode, 8).unwrap_or_else(|_| fail(USAGE));
+    if replicate.is_some() && follow.is_some() { fail(USAGE) }
+    let secret = || std::env::var("CAPBIT_REPLICATION_SECRET").ok().filter(|s| !s.is_empty()).unwrap_or_else(|| fail("replication needs CAPBIT_REPLICATION_SECRET"));
+    init(&db).unwrap_or_else(|e| fail(&e.0));
+    if let Some(addr) = replicate {
+        let (secret, listener) = (secret(), std::net::TcpListener::bind(&addr).unwrap_or_else(|e| fail(&e.to_string())));
+        replication::set_role(Role::Leader).unwrap_or_else(|e| fail(&e.0));
+        std::thread::spawn(move || replication::serve(listener, &secret));
+        eprintln!("capbit-server shipping its log on {addr}");
+    }
+    if let Some(addr) = follow {
+        let (secret, name) = (secret(), listen.clone());
+        replication::set_role(Role::Follower).unwrap_or_else(|e| fail(&e.0));
+        std::thread::spawn(move || follow_leader(&addr, &name, &secret));
+    }
+    let app = router(Handle::new(max), admin);
+    eprintln!("capbit-server listening on {listen} (db {db})");
+    if let Some(path) = listen.strip_prefix("unix:") {
+        serve_unix(path, mode, app).await.unwrap_or_else(|e| fail(&e.to_string()));
+    } else {
+        let listener = tokio::net::TcpListener::bind(&listen).await.unwrap_or_else(|e| fail(&e.to_string()));
+        axum::serve(listener, app).with_graceful_shutdown(shutdown()).await.unwrap_or_else(|e| fail(&e.to_string()));
+    }
+    flush().unwrap_or_else(|e| fail(&e.0));
+}
+
+// Replaces a stale socket from an earlier run and removes it again on shutdown. The socket is
+// bound in a private directory and only moved to path once it has its mode, so nobody outside
+// the mode can connect in between.
+#[cfg(unix)]
+async fn serve_unix(path: &str, mode: u32, app: axum::Router) -> std::io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) { std::fs::remove_file(path)?; }
+    let dir = format!("{path}.{}", std::process::id());
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let tmp = format!("{dir}/sock");
+    let bound = tokio::net::UnixListener::bind(&tmp).and_then(|l| {
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(&tmp, path)?;
+        Ok(l)
+    });
+    let _ = std::fs::remove_file(&tmp);
+    std::fs::remove_dir(&dir)?;
+    let listener = bound?;
+    let served = capbit::http::serve_unix(listener, app, shutdown()).await;
+    std::fs::remove_file(path)?;
+    served
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_: &str, _: u32, _: axum::Router) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "unix sockets need a unix platform"))
+}
+
+// Pulls from the leader forever, reconnecting after errors and idling while caught up
+fn follow_leader(addr: &str, name: &str, secret: &str) {
+    let pause = std::time::Duration::from_millis(200);
+    loop {
+        let mut f = match Follower::connect(addr, name, secret) {
+            Ok(f) => f,
+            Err(e) => { eprintln!("follow {addr}: {e}"); std::thread::sleep(pause * 5); continue }
+        };
+        loop {
+            match f.pull(1000) {
+                Ok(0) => std::thread::sleep(pause),
+                Ok(_) => {}
+                Err(e) => { eprintln!("follow {addr}: {e}"); std::thread::sleep(pause * 5); break }
+            }
+        }
+    }
+}
+
+async fn shutdown() {
+    let term = async {
+        #[cfg(unix)]
+        if let Ok(mut s) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) { s.recv().await; return }
+        std::future::pending::<()>().await
+    };
+    tokio::select! { _ = tokio::signal::ctrl_c() => {}, _ = term => {} }
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{msg}");
+    exit(1)
+}
@@ -0,0 +1,227 @@
+//! Capbit command line - administer a store from a shell
+//!
+//!   capbit [--db PATH] [--actor ID] [--json] COMMAND ARGS...
+//!
+//! Subjects and objects are numbers or names (writes intern new names), roles are numbers or
+//! role names, masks are symbolic like VIEWER_BITS|0x400000|read. The actor defaults to
+//! $CAPBIT_ACTOR and the store to capbit_data. `apply` prints the plan for a policy file and
+//! commits it as one batch. Exit status is 0 on success, 1 on error and 2 when fsck leaves
+//! issues unfixed.
+
+use capbit::{io::{export, import, ImportMode}, keys, policy::{self, Policy}, *};
+use serde_json::{json, Map, Value};
+use std::{fs::File, io::BufReader, process::exit};
+
+const USAGE: &str = "usage: capbit [--db PATH] [--actor ID] [--json] COMMAND ARGS...
+
+store      init | bootstrap
+objects    create OBJ ROLE MASK | update OBJ ROLE MASK | delete OBJ ROLE
+grants     grant SUB OBJ ROLE | revoke SUB OBJ ROLE
+inherits   inherit SUB OBJ ROLE PARENT | remove-inherit SUB OBJ ROLE
+hierarchy  set-parent OBJ PARENT MASK | remove-parent OBJ PARENT
+groups     add-member GROUP MEMBER | remove-member GROUP MEMBER
+resolve    check SUB OBJ MASK | mask SUB OBJ | explain SUB OBJ
+lists      list-roles OBJ | list-roles-for SUB OBJ | list-grants SUB | list-subjects OBJ
+           list-inherits SUB OBJ | list-inherits-on-obj OBJ | list-inherits-on-obj-role OBJ ROLE
+           list-inherits-from-parent PARENT | list-inherits-from-parent-on-obj PARENT OBJ
+           list-parents OBJ | list-children OBJ | list-members GROUP | list-groups SUB | list-bits SCOPE
+data       export [FILE] | import [--replace | --dry-run] [FILE] | backup ARCHIVE | restore ARCHIVE DIR
+           fsck [--repair]   (import --replace keeps API keys)
+policy     apply [--prune] [--dry-run] FILE   (--prune only touches objects the file declares)
+keys       issue-key SUB | revoke-key KEY_ID | list-keys SUB";
+
+// One output value, printed as a number/name for humans and as JSON with --json
+enum Cell { Id(u64), Role(u64), Mask(u64, u64), Bit(u8), Text(String), Bool(bool), Count(u64), Path(Vec<u64>) }
+type Row = Vec<(&'static str, Cell)>;
+enum Out { Done, One(Row), Many(Vec<Row>), Nested(Row, &'static str, Vec<Row>) }
+
+struct Opts { db: String, actor: Option<String>, json: bool, mode: ImportMode, repair: bool, prune: bool }
+
+fn main() {
+    let mut o = Opts { db: "capbit_data".into(), actor: std::env::var("CAPBIT_ACTOR").ok(), json: false, mode: ImportMode::Merge, repair: false, prune: false };
+    let mut cmd = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--db" => o.db = args.next().unwrap_or_else(|| fail(USAGE)),
+            "--actor" => o.actor = Some(args.next().unwrap_or_else(|| fail(USAGE))),
+            "--json" => o.json = true,
+            "--replace" => o.mode = ImportMode::Replace,
+            "--dry-run" => o.mode = ImportMode::DryRun,
+            "--repair" => o.repair = true,
+            "--prune" => o.prune = true,
+            "-h" | "--help" => { println!("{USAGE}"); return; }
+            _ if a.starts_with("--") => fail(USAGE),
+            _ if cmd.is_empty() => cmd.push(a.replace('-', "_")),
+            _ => cmd.push(a),
+        }
+    }
+    let cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
+    if cmd.is_empty() { fail(USAGE) }
+    let out = if let ["restore", archive, dir] = cmd[..] {
+        restore_from(archive, dir).map(|n| Out::One(vec![("restored", Cell::Count(n))]))
+    } else {
+        init(&o.db).and_then(|()| run(&o, &cmd))
+    };
+    match out {
+        Ok(out) => print(&o, out),
+        Err(e) => fail(&e.0),
+    }
+}
+
+fn run(o: &Opts, cmd: &[&str]) -> Result<Out> {
+    let actor = || o.actor.as_deref().ok_or_else(|| Error("No actor: pass --actor or set CAPBIT_ACTOR".into())).and_then(id);
+    let many = |rows: Vec<Row>| Ok(Out::Many(rows));
+    Ok(match *cmd {
+        ["init"] => Out::One(vec![("db", Cell::Text(o.db.clone()))]),
+        ["bootstrap"] => { let (sys, root) = bootstrap()?; Out::One(vec![("system", Cell::Id(sys)), ("root", Cell::Id(root))]) }
+
+        ["create", obj, role, mask] => { let a = actor()?; transaction(|| { let obj = new_id(obj)?; create(a, obj, new_role(role)?, parse_mask(obj, mask)?) })?; Out::Done }
+        ["update", obj, role, mask] => { let obj = id(obj)?; update(actor()?, obj, role_id(role)?, parse_mask(obj, mask)?)?; Out::Done }
+        ["delete", obj, role] => { delete(actor()?, id(obj)?, role_id(role)?)?; Out::Done }
+        ["grant", sub, obj, role] => { let (a, obj) = (actor()?, id(obj)?); transaction(|| grant(a, new_id(sub)?, obj, new_role(role)?))?; Out::Done }
+        ["revoke", sub, obj, role] => { revoke(actor()?, id(sub)?, id(obj)?, role_id(role)?)?; Out::Done }
+        ["inherit", sub, obj, role, parent] => { let (a, obj, role) = (actor()?, id(obj)?, role_id(role)?); transaction(|| inherit(a, new_id(sub)?, obj, role, new_id(parent)?))?; Out::Done }
+        ["remove_inherit", sub, obj, role] => { remove_inherit(actor()?, id(sub)?, id(obj)?, role_id(role)?)?; Out::Done }
+        ["set_parent", obj, parent, mask] => { let (a, p) = (actor()?, id(parent)?); let mask = parse_mask(p, mask)?; transaction(|| set_parent(a, new_id(obj)?, p, mask))?; Out::Done }
+        ["remove_parent", obj, parent] => { remove_parent(actor()?, id(obj)?, id(parent)?)?; Out::Done }
+        ["add_member", group, member] => { let (a, g) = (actor()?, id(group)?); transaction(|| add_member(a, g, new_id(member)?))?; Out::Done }
+        ["remove_member", group, member] => { remove_member(actor()?, id(group)?, id(member)?)?; Out::Done }
+
+        ["check", sub, obj, mask] => { let obj = id(obj)?; Out::One(vec![("allowed", Cell::Bool(check(id(sub)?, obj, parse_mask(obj, mask)?)?))]) }
+        ["mask", sub, obj] => { let obj = id(obj)?; Out::One(vec![("mask", Cell::Mask(get_mask(id(sub)?, obj)?, obj))]) }
+        ["explain", sub, obj] => {
+            let obj = id(obj)?;
+            let e = explain(id(sub)?, obj)?;
+            let sources = e.sources.into_iter().map(|s| vec![
+                ("principal", Cell::Id(s.principal)), ("holder", Cell::Id(s.holder)), ("obj", Cell::Id(s.obj)),
+                ("role", Cell::Role(s.role)), ("mask", Cell::Mask(s.mask, obj)), ("path", Cell::Path(s.path)),
+            ]).collect();
+            Out::Nested(vec![("mask", Cell::Mask(e.mask, obj))], "sources", sources)
+        }
+
+        ["list_roles", obj] => { let obj = id(obj)?; many(list_roles(actor()?, obj)?.into_iter().map(|(r, m)| vec![("role", Cell::Role(r)), ("mask", Cell::Mask(m, obj))]).collect())? }
+        ["list_roles_for", sub, obj] => many(list_roles_for(actor()?, id(sub)?, id(obj)?)?.into_iter().map(|r| vec![("role", Cell::Role(r))]).collect())?,
+        ["list_grants", sub] => many(list_grants(actor()?, id(sub)?)?.into_iter().map(|(o, r)| vec![("obj", Cell::Id(o)), ("role", Cell::Role(r))]).collect())?,
+        ["list_subjects", obj] => many(list_subjects(actor()?, id(obj)?)?.into_iter().map(|(s, r)| vec![("sub", Cell::Id(s)), ("role", Cell::Role(r))]).collect())?,
+        ["list_inherits", sub, obj] => many(list_inherits(actor()?, id(sub)?, id(obj)?)?.into_iter().map(|(r, p)| vec![("role", Cell::Role(r)), ("parent", Cell::Id(p))]).collect())?,
+        ["list_inherits_on_obj", obj] => many(list_inherits_on_obj(actor()?, id(obj)?)?.into_iter()
+            .map(|(r, p, s)| vec![("role", Cell::Role(r)), ("parent", Cell::Id(p)), ("sub", Cell::Id(s))]).collect())?,
+        ["list_inherits_on_obj_role", obj, role] => many(list_inherits_on_obj_role(actor()?, id(obj)?, role_id(role)?)?.into_iter()
+            .map(|(p, s)| vec![("parent", Cell::Id(p)), ("sub", Cell::Id(s))]).collect())?,
+        ["list_inherits_from_parent", parent] => many(list_inherits_from_parent(actor()?, id(parent)?)?.into_iter()
+            .map(|(o, r, s)| vec![("obj", Cell::Id(o)), ("role", Cell::Role(r)), ("sub", Cell::Id(s))]).collect())?,
+        ["list_inherits_from_parent_on_obj", parent, obj] => many(list_inherits_from_parent_on_obj(actor()?, id(parent)?, id(obj)?)?.into_iter()
+            .map(|(r, s)| vec![("role", Cell::Role(r)), ("sub", Cell::Id(s))]).collect())?,
+        ["list_parents", obj] => { let obj = id(obj)?; many(list_parents(actor()?, obj)?.into_iter().map(|(p, m)| vec![("parent", Cell::Id(p)), ("mask", Cell::Mask(m, obj))]).collect())? }
+        ["list_children", obj] => { let obj = id(obj)?; many(list_children(actor()?, obj)?.into_iter().map(|(c, m)| vec![("child", Cell::Id(c)), ("mask", Cell::Mask(m, obj))]).collect())? }
+        ["list_members", group] => many(list_members(actor()?, id(group)?)?.into_iter().map(|m| vec![("member", Cell::Id(m))]).collect())?,
+        ["list_groups", sub] => many(list_groups(actor()?, id(sub)?)?.into_iter().map(|g| vec![("group", Cell::Id(g))]).collect())?,
+        ["list_bits", scope] => many(list_bits(actor()?, id(scope)?)?.into_iter()
+            .map(|(b, n, d)| vec![("bit", Cell::Bit(b)), ("name", Cell::Text(n)), ("desc", Cell::Text(d))]).collect())?,
+
+        ["export"] => Out::One(vec![("exported", Cell::Count(export(std::io::stdout().lock())? as u64))]),
+        ["export", path] => Out::One(vec![("exported", Cell::Count(export(File::create(path).map_err(|e| Error(e.to_string()))?)? as u64))]),
+        ["import"] => imported(import(std::io::stdin().lock(), o.mode)?),
+        ["import", path] => imported(import(BufReader::new(File::open(path).map_err(|e| Error(e.to_string()))?), o.mode)?),
+        ["backup", path] => Out::One(vec![("backed_up", Cell::Count(backup_to(path)?))]),
+        ["fsck"] => {
+            let r = verify(o.repair)?;
+            let unfixed = r.issues.iter().any(|i| !o.repair || !i.repairable());
+            let issues = r.issues.iter().map(|i| vec![("issue", Cell::Text(i.to_string()))]).collect();
+            print(o, Out::Nested(vec![("repaired", Cell::Count(r.repaired as u64))], "issues", issues));
+            exit(if unfixed { 2 } else { 0 })
+        }
+        ["apply", path] => {
+            let plan = policy::plan(&Policy::parse(&std::fs::read_to_string(path).map_err(|e| Error(e.to_string()))?)?, o.prune)?;
+            let changes = plan.changes.iter().map(|c| vec![("change", Cell::Text(c.to_string()))]).collect();
+            let n = Cell::Count(plan.changes.len() as u64);
+            if o.mode == ImportMode::DryRun { return Ok(Out::Nested(vec![("planned", n)], "changes", changes)) }
+            if let Err(results) = policy::apply(actor()?, &plan) {
+                let failed = plan.changes.iter().zip(results).filter_map(|(c, r)| r.err().map(|e| format!("{c}: {}", e.0)));
+                return Err(Error(failed.collect::<Vec<_>>().join("\n")));
+            }
+            Out::Nested(vec![("applied", n)], "changes", changes)
+        }
+
+        ["issue_key", sub] => { let (key_id, token) = keys::issue(actor()?, id(sub)?)?; Out::One(vec![("id", Cell::Text(format!("{key_id:016x}"))), ("key", Cell::Text(token))]) }
+        ["revoke_key", key_id] => { keys::revoke(actor()?, u64::from_str_radix(key_id, 16).map_err(|_| Error(format!("Unknown: {key_id}")))?)?; Out::Done }
+        ["list_keys", sub] => many(keys::list(actor()?, id(sub)?)?.into_iter().map(|k| vec![("id", Cell::Text(format!("{k:016x}")))]).collect())?,
+        _ => fail(USAGE),
+    })
+}
+
+fn imported(r: capbit::io::ImportReport) -> Out {
+    let conflicts = r.conflicts.into_iter().map(|c| vec![
+        ("line", Cell::Count(c.line as u64)), ("record", Cell::Text(format!("{:?}", c.record))), ("current", Cell::Text(format!("{:?}", c.current))),
+    ]).collect();
+    Out::Nested(vec![("records", Cell::Count(r.records as u64))], "conflicts", conflicts)
+}
+
+// Numbers are taken as ids; names must already exist, except where new_id interns them inside
+// the command's transaction, so a denied command leaves no names behind
+fn id(s: &str) -> Result<u64> { s.parse().or_else(|_| lookup(s)?.ok_or_else(|| Error(format!("Unknown: {s}")))) }
+fn new_id(s: &str) -> Result<u64> { s.parse().or_else(|_| intern(s)) }
+fn role_id(s: &str) -> Result<u64> { s.parse().or_else(|_| lookup_role(s)?.ok_or_else(|| Error(format!("Unknown: {s}")))) }
+fn new_role(s: &str) -> Result<u64> { s.parse().or_else(|_| intern_role(s)) }
+
+fn print(o: &Opts, out: Out) {
+    if o.json {
+        let obj = |r: Row| Value::Object(r.into_iter().map(|(k, c)| (k.to_string(), json_cell(c))).collect::<Map<_, _>>());
+        let v = match out {
+            Out::Done => json!({ "ok": true }),
+            Out::One(r) => obj(r),
+            Out::Many(rows) => Value::Array(rows.into_iter().map(obj).collect()),
+            Out::Nested(r, key, rows) => {
+                let Value::Object(mut m) = obj(r) else { unreachable!() };
+                m.insert(key.into(), Value::Array(rows.into_iter().map(obj).collect()));
+                Value::Object(m)
+            }
+        };
+        return println!("{v}");
+    }
+    let line = |r: Row| match &r[..] {
+        [(_, c)] => text_cell(c),
+        _ => r.iter().map(|(k, c)| format!("{k}={}", text_cell(c))).collect::<Vec<_>>().join(" "),
+    };
+    match out {
+        Out::Done => println!("ok"),
+        Out::One(r) => println!("{}", line(r)),
+        Out::Many(rows) => for r in rows { println!("{}", line(r)) },
+        Out::Nested(r, _, rows) => {
+            println!("{}", line(r));
+            for r in rows { println!("  {}", line(r)) }
+        }
+    }
+}
+
+fn json_cell(c: Cell) -> Value {
+    match c {
+        Cell::Id(i) | Cell::Role(i) | Cell::Count(i) => json!(i),
+        Cell::Mask(m, obj) => json!({ "mask": m, "bits": names_from_mask(obj, m).unwrap_or_default() }),
+        Cell::Bit(b) => json!(b),
+        Cell::Text(t) => json!(t),
+        Cell::Bool(b) => json!(b),
+        Cell::Path(p) => json!(p),
+    }
+}
+
+// Ids are shown by name where one is interned
+fn text_cell(c: &Cell) -> String {
+    let name = |i: &u64| name_of(*i).ok().flatten().unwrap_or_else(|| i.to_string());
+    match c {
+        Cell::Id(i) => name(i),
+        Cell::Role(r) => role_name(*r).ok().flatten().unwrap_or_else(|| r.to_string()),
+        Cell::Mask(m, obj) => format!("0x{m:X} ({})", names_from_mask(*obj, *m).unwrap_or_default().join("|")),
+        Cell::Bit(b) => b.to_string(),
+        Cell::Text(t) => t.clone(),
+        Cell::Bool(b) => b.to_string(),
+        Cell::Count(n) => n.to_string(),
+        Cell::Path(p) => p.iter().map(name).collect::<Vec<_>>().join(" → "),
+    }
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{msg}");
+    exit(1)
+}
@@ -1,64 +1,111 @@
-use axum::{extract::Json, response::Html, routing::{get, post}, Router};
+//! Web UI and JSON API. Every /api call authenticates with `Authorization: Bearer <key>` and
+//! acts as the key's subject; keys are issued with `capbit issue-key`. With CAPBIT_ADMIN_ROUTES=1
+//! the server also exposes /api/admin/bootstrap, which works only on an empty store and returns
+//! a root key, and /api/admin/clear, which only _root may call.
+
+use axum::{async_trait, extract::{FromRequestParts, Json, State}, http::{header::AUTHORIZATION, request::Parts, StatusCode},
+    response::Html, routing::{get, post}, Router};
+use capbit::aio::Handle;
 use capbit::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize)] struct GrantReq { actor: u64, sub: u64, obj: u64, role: u64 }
-#[derive(Deserialize)] struct RevokeReq { actor: u64, sub: u64, obj: u64, role: u64 }
-#[derive(Deserialize)] struct CreateReq { actor: u64, obj: u64, role: u64, mask: u64 }
-#[derive(Deserialize)] struct UpdateReq { actor: u64, obj: u64, role: u64, mask: u64 }
-#[derive(Deserialize)] struct DeleteReq { actor: u64, obj: u64, role: u64 }
+#[derive(Deserialize)] struct GrantReq { sub: u64, obj: u64, role: u64 }
+#[derive(Deserialize)] struct RevokeReq { sub: u64, obj: u64, role: u64 }
+#[derive(Deserialize)] struct CreateReq { obj: u64, role: u64, mask: u64 }
+#[derive(Deserialize)] struct UpdateReq { obj: u64, role: u64, mask: u64 }
+#[derive(Deserialize)] struct DeleteReq { obj: u64, role: u64 }
 #[derive(Deserialize)] struct CheckReq { sub: u64, obj: u64, req: u64 }
 #[derive(Deserialize)] struct GetMaskReq { sub: u64, obj: u64 }
-#[derive(Deserialize)] struct InheritReq { actor: u64, sub: u64, obj: u64, role: u64, parent: u64 }
-#[derive(Deserialize)] struct RemoveInheritReq { actor: u64, sub: u64, obj: u64, role: u64 }
-#[derive(Deserialize)] struct ListRolesReq { actor: u64, obj: u64 }
-#[derive(Deserialize)] struct ListRolesForReq { actor: u64, sub: u64, obj: u64 }
-#[derive(Deserialize)] struct ListGrantsReq { actor: u64, sub: u64 }
-#[derive(Deserialize)] struct ListSubjectsReq { actor: u64, obj: u64 }
-#[derive(Deserialize)] struct ListInheritsReq { actor: u64, sub: u64, obj: u64 }
-#[derive(Deserialize)] struct ListInheritsOnObjReq { actor: u64, obj: u64 }
-#[derive(Deserialize)] struct ListInheritsOnObjRoleReq { actor: u64, obj: u64, role: u64 }
-#[derive(Deserialize)] struct ListInheritsFromParentReq { actor: u64, parent: u64 }
-#[derive(Deserialize)] struct ListInheritsFromParentOnObjReq { actor: u64, parent: u64, obj: u64 }
+#[derive(Deserialize)] struct InheritReq { sub: u64, obj: u64, role: u64, parent: u64 }
+#[derive(Deserialize)] struct RemoveInheritReq { sub: u64, obj: u64, role: u64 }
+#[derive(Deserialize)] struct ListRolesReq { obj: u64 }
+#[derive(Deserialize)] struct ListRolesForReq { sub: u64, obj: u64 }
+#[derive(Deserialize)] struct ListGrantsReq { sub: u64 }
+#[derive(Deserialize)] struct ListSubjectsReq { obj: u64 }
+#[derive(Deserialize)] struct ListInheritsReq { sub: u64, obj: u64 }
+#[derive(Deserialize)] struct ListInheritsOnObjReq { obj: u64 }
+#[derive(Deserialize)] struct ListInheritsOnObjRoleReq { obj: u64, role: u64 }
+#[derive(Deserialize)] struct ListInheritsFromParentReq { parent: u64 }
+#[derive(Deserialize)] struct ListInheritsFromParentOnObjReq { parent: u64, obj: u64 }
+#[derive(Deserialize)] struct SetParentReq { obj: u64, parent: u64, mask: u64 }
+#[derive(Deserialize)] struct RemoveParentReq { obj: u64, parent: u64 }
+#[derive(Deserialize)] struct ListParentsReq { obj: u64 }
+#[derive(Deserialize)] struct ListChildrenReq { parent: u64 }
+#[derive(Deserialize)] struct MemberReq { group: u64, member: u64 }
+#[derive(Deserialize)] struct ListMembersReq { group: u64 }
+#[derive(Deserialize)] struct ListGroupsReq { sub: u64 }
+#[derive(Deserialize)] struct DefineBitReq { scope: u64, bit: u8, name: String, desc: String }
+#[derive(Deserialize)] struct UndefineBitReq { scope: u64, bit: u8 }
+#[derive(Deserialize)] struct ListBitsReq { scope: u64 }
 #[derive(Serialize)] struct Resp { ok: bool, msg: String }
 
+// The subject behind the request's bearer key
+struct Actor(u64);
+
+#[async_trait]
+impl FromRequestParts<Handle> for Actor {
+    type Rejection = (StatusCode, Json<Resp>);
+    async fn from_request_parts(parts: &mut Parts, h: &Handle) -> std::result::Result<Self, Self::Rejection> {
+        let unauthorized = |msg: String| (StatusCode::UNAUTHORIZED, Json(Resp { ok: false, msg }));
+        let bearer = parts.headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer "));
+        let Some(token) = bearer.map(str::to_owned) else { return Err(unauthorized("Unauthorized".into())) };
+        h.run(move || keys::authenticate(&token)).await.map(Actor).map_err(|e| unauthorized(e.0))
+    }
+}
+
 fn resp(r: Result<String>) -> Json<Resp> {
     Json(match r { Ok(m) => Resp { ok: true, msg: m }, Err(e) => Resp { ok: false, msg: e.0 } })
 }
 
 fn fmt2(v: &[(u64, u64)]) -> String { v.iter().map(|(a,b)| format!("({a},{b})")).collect::<Vec<_>>().join(", ") }
+fn sym(obj: u64, m: u64) -> String { names_from_mask(obj, m).map(|n| n.join("|")).unwrap_or_default() }
+fn fmt_masks(obj: u64, v: &[(u64, u64)]) -> String { v.iter().map(|(r,m)| format!("({r},0x{m:X} [{}])", sym(obj, *m))).collect::<Vec<_>>().join(", ") }
 fn fmt3(v: &[(u64, u64, u64)]) -> String { v.iter().map(|(a,b,c)| format!("({a},{b},{c})")).collect::<Vec<_>>().join(", ") }
 
-async fn do_bootstrap() -> Json<Resp> { resp(bootstrap().map(|(s,r)| format!("system={s}, root={r}"))) }
-async fn do_clear() -> Json<Resp> { resp(clear().map(|_| "Cleared".into())) }
-async fn do_grant(Json(r): Json<GrantReq>) -> Json<Resp> { resp(grant(r.actor, r.sub, r.obj, r.role).map(|_| "Granted".into())) }
-async fn do_revoke(Json(r): Json<RevokeReq>) -> Json<Resp> { resp(revoke(r.actor, r.sub, r.obj, r.role).map(|_| "Revoked".into())) }
-async fn do_create(Json(r): Json<CreateReq>) -> Json<Resp> { resp(create(r.actor, r.obj, r.role, r.mask).map(|_| "Created".into())) }
-async fn do_update(Json(r): Json<UpdateReq>) -> Json<Resp> { resp(update(r.actor, r.obj, r.role, r.mask).map(|_| "Updated".into())) }
-async fn do_delete(Json(r): Json<DeleteReq>) -> Json<Resp> { resp(delete(r.actor, r.obj, r.role).map(|_| "Deleted".into())) }
-async fn do_check(Json(r): Json<CheckReq>) -> Json<Resp> { resp(check(r.sub, r.obj, r.req).map(|b| if b { "Allowed" } else { "Denied" }.into())) }
-async fn do_get_mask(Json(r): Json<GetMaskReq>) -> Json<Resp> { resp(get_mask(r.sub, r.obj).map(|m| format!("0x{m:X} ({m})"))) }
-async fn do_inherit(Json(r): Json<InheritReq>) -> Json<Resp> { resp(inherit(r.actor, r.sub, r.obj, r.role, r.parent).map(|_| "Inherited".into())) }
-async fn do_remove_inherit(Json(r): Json<RemoveInheritReq>) -> Json<Resp> { resp(remove_inherit(r.actor, r.sub, r.obj, r.role).map(|_| "Removed".into())) }
-async fn do_list_roles(Json(r): Json<ListRolesReq>) -> Json<Resp> { resp(list_roles(r.actor, r.obj).map(|v| fmt2(&v))) }
-async fn do_list_roles_for(Json(r): Json<ListRolesForReq>) -> Json<Resp> { resp(list_roles_for(r.actor, r.sub, r.obj).map(|v| format!("{v:?}"))) }
-async fn do_list_grants(Json(r): Json<ListGrantsReq>) -> Json<Resp> { resp(list_grants(r.actor, r.sub).map(|v| fmt2(&v))) }
-async fn do_list_subjects(Json(r): Json<ListSubjectsReq>) -> Json<Resp> { resp(list_subjects(r.actor, r.obj).map(|v| fmt2(&v))) }
-async fn do_list_inherits(Json(r): Json<ListInheritsReq>) -> Json<Resp> { resp(list_inherits(r.actor, r.sub, r.obj).map(|v| fmt2(&v))) }
-async fn do_list_inherits_on_obj(Json(r): Json<ListInheritsOnObjReq>) -> Json<Resp> { resp(list_inherits_on_obj(r.actor, r.obj).map(|v| fmt3(&v))) }
-async fn do_list_inherits_on_obj_role(Json(r): Json<ListInheritsOnObjRoleReq>) -> Json<Resp> { resp(list_inherits_on_obj_role(r.actor, r.obj, r.role).map(|v| fmt2(&v))) }
-async fn do_list_inherits_from_parent(Json(r): Json<ListInheritsFromParentReq>) -> Json<Resp> { resp(list_inherits_from_parent(r.actor, r.parent).map(|v| fmt3(&v))) }
-async fn do_list_inherits_from_parent_on_obj(Json(r): Json<ListInheritsFromParentOnObjReq>) -> Json<Resp> { resp(list_inherits_from_parent_on_obj(r.actor, r.parent, r.obj).map(|v| fmt2(&v))) }
+async fn do_bootstrap(State(h): State<Handle>) -> Json<Resp> {
+    resp(h.run(|| { let (s, r) = bootstrap()?; let (_, key) = keys::issue(r, r)?; Ok(format!("system={s}, root={r}, key={key}")) }).await)
+}
+async fn do_clear(State(h): State<Handle>, Actor(a): Actor) -> Json<Resp> {
+    if a != _ROOT { return resp(Err(Error("Denied".into()))) }
+    resp(h.clear().await.map(|_| "Cleared".into()))
+}
+async fn do_grant(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<GrantReq>) -> Json<Resp> { resp(h.grant(a, r.sub, r.obj, r.role).await.map(|_| "Granted".into())) }
+async fn do_revoke(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<RevokeReq>) -> Json<Resp> { resp(h.revoke(a, r.sub, r.obj, r.role).await.map(|_| "Revoked".into())) }
+async fn do_create(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<CreateReq>) -> Json<Resp> { resp(h.create(a, r.obj, r.role, r.mask).await.map(|_| "Created".into())) }
+async fn do_update(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<UpdateReq>) -> Json<Resp> { resp(h.update(a, r.obj, r.role, r.mask).await.map(|_| "Updated".into())) }
+async fn do_delete(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<DeleteReq>) -> Json<Resp> { resp(h.delete(a, r.obj, r.role).await.map(|_| "Deleted".into())) }
+async fn do_check(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<CheckReq>) -> Json<Resp> { resp(h.run(move || { may_inspect(a, r.sub, r.obj)?; check(r.sub, r.obj, r.req) }).await.map(|b| if b { "Allowed" } else { "Denied" }.into())) }
+async fn do_get_mask(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<GetMaskReq>) -> Json<Resp> { resp(h.run(move || { may_inspect(a, r.sub, r.obj)?; get_mask(r.sub, r.obj) }.map(|m| format!("0x{m:X} ({m}) [{}]", sym(r.obj, m)))).await) }
+async fn do_inherit(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<InheritReq>) -> Json<Resp> { resp(h.inherit(a, r.sub, r.obj, r.role, r.parent).await.map(|_| "Inherited".into())) }
+async fn do_remove_inherit(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<RemoveInheritReq>) -> Json<Resp> { resp(h.remove_inherit(a, r.sub, r.obj, r.role).await.map(|_| "Removed".into())) }
+async fn do_list_roles(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListRolesReq>) -> Json<Resp> { resp(h.run(move || list_roles(a, r.obj).map(|v| fmt_masks(r.obj, &v))).await) }
+async fn do_list_roles_for(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListRolesForReq>) -> Json<Resp> { resp(h.list_roles_for(a, r.sub, r.obj).await.map(|v| format!("{v:?}"))) }
+async fn do_list_grants(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListGrantsReq>) -> Json<Resp> { resp(h.list_grants(a, r.sub).await.map(|v| fmt2(&v))) }
+async fn do_list_subjects(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListSubjectsReq>) -> Json<Resp> { resp(h.list_subjects(a, r.obj).await.map(|v| fmt2(&v))) }
+async fn do_list_inherits(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListInheritsReq>) -> Json<Resp> { resp(h.list_inherits(a, r.sub, r.obj).await.map(|v| fmt2(&v))) }
+async fn do_list_inherits_on_obj(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListInheritsOnObjReq>) -> Json<Resp> { resp(h.list_inherits_on_obj(a, r.obj).await.map(|v| fmt3(&v))) }
+async fn do_list_inherits_on_obj_role(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListInheritsOnObjRoleReq>) -> Json<Resp> { resp(h.list_inherits_on_obj_role(a, r.obj, r.role).await.map(|v| fmt2(&v))) }
+async fn do_list_inherits_from_parent(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListInheritsFromParentReq>) -> Json<Resp> { resp(h.list_inherits_from_parent(a, r.parent).await.map(|v| fmt3(&v))) }
+async fn do_list_inherits_from_parent_on_obj(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListInheritsFromParentOnObjReq>) -> Json<Resp> { resp(h.list_inherits_from_parent_on_obj(a, r.parent, r.obj).await.map(|v| fmt2(&v))) }
+async fn do_set_parent(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<SetParentReq>) -> Json<Resp> { resp(h.set_parent(a, r.obj, r.parent, r.mask).await.map(|_| "Parent set".into())) }
+async fn do_remove_parent(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<RemoveParentReq>) -> Json<Resp> { resp(h.remove_parent(a, r.obj, r.parent).await.map(|_| "Removed".into())) }
+async fn do_list_parents(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListParentsReq>) -> Json<Resp> { resp(h.run(move || list_parents(a, r.obj).map(|v| fmt_masks(r.obj, &v))).await) }
+async fn do_list_children(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListChildrenReq>) -> Json<Resp> { resp(h.list_children(a, r.parent).await.map(|v| fmt2(&v))) }
+async fn do_add_member(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<MemberReq>) -> Json<Resp> { resp(h.add_member(a, r.group, r.member).await.map(|_| "Added".into())) }
+async fn do_remove_member(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<MemberReq>) -> Json<Resp> { resp(h.remove_member(a, r.group, r.member).await.map(|_| "Removed".into())) }
+async fn do_list_members(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListMembersReq>) -> Json<Resp> { resp(h.list_members(a, r.group).await.map(|v| format!("{v:?}"))) }
+async fn do_list_groups(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListGroupsReq>) -> Json<Resp> { resp(h.list_groups(a, r.sub).await.map(|v| format!("{v:?}"))) }
+async fn do_define_bit(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<DefineBitReq>) -> Json<Resp> { resp(h.define_bit(a, r.scope, r.bit, r.name, r.desc).await.map(|_| "Defined".into())) }
+async fn do_undefine_bit(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<UndefineBitReq>) -> Json<Resp> { resp(h.undefine_bit(a, r.scope, r.bit).await.map(|_| "Undefined".into())) }
+async fn do_list_bits(State(h): State<Handle>, Actor(a): Actor, Json(r): Json<ListBitsReq>) -> Json<Resp> { resp(h.list_bits(a, r.scope).await.map(|v| v.iter().map(|(b,n,d)| format!("({b},{n},{d:?})")).collect::<Vec<_>>().join(", "))) }
 
 async fn index() -> Html<&'static str> { Html(include_str!("ui.html")) }
 
 #[tokio::main]
 async fn main() {
     init("capbit_data").expect("init failed");
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", get(index))
-        .route("/api/bootstrap", post(do_bootstrap))
-        .route("/api/clear", post(do_clear))
         .route("/api/grant", post(do_grant))
         .route("/api/revoke", post(do_revoke))
         .route("/api/create", post(do_create))
@@ -76,7 +123,22 @@ async fn main() {
         .route("/api/list_inherits_on_obj", post(do_list_inherits_on_obj))
         .route("/api/list_inherits_on_obj_role", post(do_list_inherits_on_obj_role))
         .route("/api/list_inherits_from_parent", post(do_list_inherits_from_parent))
-        .route("/api/list_inherits_from_parent_on_obj", post(do_list_inherits_from_parent_on_obj));
+        .route("/api/list_inherits_from_parent_on_obj", post(do_list_inherits_from_parent_on_obj))
+        .route("/api/set_parent", post(do_set_parent))
+        .route("/api/remove_parent", post(do_remove_parent))
+        .route("/api/list_parents", post(do_list_parents))
+        .route("/api/list_children", post(do_list_children))
+        .route("/api/add_member", post(do_add_member))
+        .route("/api/remove_member", post(do_remove_member))
+        .route("/api/list_members", post(do_list_members))
+        .route("/api/list_groups", post(do_list_groups))
+        .route("/api/define_bit", post(do_define_bit))
+        .route("/api/undefine_bit", post(do_undefine_bit))
+        .route("/api/list_bits", post(do_list_bits));
+    if std::env::var("CAPBIT_ADMIN_ROUTES").is_ok_and(|v| v == "1") {
+        app = app.route("/api/admin/bootstrap", post(do_bootstrap)).route("/api/admin/clear", post(do_clear));
+    }
+    let app = app.with_state(Handle::new(64));
     println!("UI running at http://localhost:3000");
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     axum::serve(listener, app).await.unwrap();
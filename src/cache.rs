@@ -0,0 +1,179 @@
+//! Resolution cache - (subject, object) → mask, evicting the least recently used entry.
+//! Commits invalidate exactly the pairs whose mask the written tuples can change. Each tenant
+//! has its own cache; the capacity applies to each.
+
+use super::*;
+use std::{collections::HashSet, sync::atomic::{AtomicUsize, Ordering}};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats { pub hits: u64, pub misses: u64, pub entries: usize, pub capacity: usize }
+
+#[derive(Default)]
+struct Cache {
+    cap: usize,
+    tick: u64,
+    generation: u64,
+    entries: HashMap<(u64, u64), (u64, u64)>,
+    lru: BTreeMap<u64, (u64, u64)>,
+    by_sub: HashMap<u64, HashSet<u64>>,
+    by_obj: HashMap<u64, HashSet<u64>>,
+    hits: u64,
+    misses: u64,
+}
+
+// Keyed by tenant id
+static CACHES: Mutex<BTreeMap<u64, Cache>> = Mutex::new(BTreeMap::new());
+static CAP: AtomicUsize = AtomicUsize::new(0);
+
+// The calling thread's tenant's cache
+fn with<T>(f: impl FnOnce(&mut Cache) -> T) -> T {
+    let id = tenant::current().map_or(0, |t| t.id);
+    let mut c = CACHES.lock().unwrap_or_else(|e| e.into_inner());
+    f(c.entry(id).or_insert_with(|| Cache { cap: CAP.load(Ordering::Relaxed), ..Cache::default() }))
+}
+
+// Checked before taking the lock so a disabled cache costs reads nothing
+fn off() -> bool { CAP.load(Ordering::Relaxed) == 0 }
+
+// Capacity 0 disables the cache (the default). Commits made while it is off are not tracked,
+// so lookups started before the change can not insert afterwards.
+pub fn set_cache_capacity(cap: usize) {
+    CAP.store(cap, Ordering::Relaxed);
+    for c in CACHES.lock().unwrap_or_else(|e| e.into_inner()).values_mut() {
+        c.cap = cap;
+        c.generation += 1;
+        while c.entries.len() > cap { c.evict(); }
+    }
+}
+
+pub fn cache_stats() -> CacheStats {
+    with(|c| CacheStats { hits: c.hits, misses: c.misses, entries: c.entries.len(), capacity: c.cap })
+}
+
+impl Cache {
+    fn evict(&mut self) {
+        if let Some((_, k)) = self.lru.pop_first() { self.remove(k); }
+    }
+
+    fn remove(&mut self, k: (u64, u64)) {
+        if let Some((_, t)) = self.entries.remove(&k) {
+            self.lru.remove(&t);
+            if let Some(s) = self.by_sub.get_mut(&k.0) { s.remove(&k.1); if s.is_empty() { self.by_sub.remove(&k.0); } }
+            if let Some(o) = self.by_obj.get_mut(&k.1) { o.remove(&k.0); if o.is_empty() { self.by_obj.remove(&k.1); } }
+        }
+    }
+}
+
+// Cached mask, or None with the generation to hand back to `insert`
+pub(crate) fn lookup(sub: u64, obj: u64) -> std::result::Result<u64, Option<u64>> {
+    if off() { return Err(None); }
+    let found = with(|c| {
+        if c.cap == 0 { return Err(None); }
+        c.tick += 1;
+        let tick = c.tick;
+        match c.entries.get_mut(&(sub, obj)) {
+            Some((mask, t)) => {
+                let (mask, old) = (*mask, std::mem::replace(t, tick));
+                c.lru.remove(&old);
+                c.lru.insert(tick, (sub, obj));
+                c.hits += 1;
+                Ok(mask)
+            }
+            None => { c.misses += 1; Err(Some(c.generation)) }
+        }
+    });
+    #[cfg(feature = "metrics")]
+    if found != Err(None) { metrics::cache(found.is_ok()); }
+    found
+}
+
+// Dropped if anything was invalidated since the lookup, as the mask may predate that commit
+pub(crate) fn insert(sub: u64, obj: u64, mask: u64, generation: u64) {
+    if off() { return; }
+    with(|c| {
+        if c.cap == 0 || c.generation != generation { return; }
+        c.remove((sub, obj));
+        while c.entries.len() >= c.cap { c.evict(); }
+        c.tick += 1;
+        c.entries.insert((sub, obj), (mask, c.tick));
+        c.lru.insert(c.tick, (sub, obj));
+        c.by_sub.entry(sub).or_default().insert(obj);
+        c.by_obj.entry(obj).or_default().insert(sub);
+    })
+}
+
+pub(crate) fn enabled() -> bool { !off() && with(|c| c.cap > 0) }
+
+pub(crate) fn reset() {
+    with(|c| {
+        let (cap, generation) = (c.cap, c.generation + 1);
+        *c = Cache { cap, generation, ..Cache::default() };
+    })
+}
+
+pub(crate) fn forget(tenant: u64) { CACHES.lock().unwrap_or_else(|e| e.into_inner()).remove(&tenant); }
+
+// Called after a commit with the (partition, key) pairs it wrote
+pub(crate) fn invalidate(written: &[(String, Vec<u8>)]) -> Result<()> {
+    if !enabled() { return Ok(()); }
+    let (mut pairs, mut objs, mut subs) = (HashSet::new(), HashSet::new(), HashSet::new());
+    for (p, k) in written {
+        match tenant::base(p) {
+            "subjects" | "inherits" => { pairs.insert((u64_at(k, 0), u64_at(k, 1))); }
+            "objects" | "parents" => { objs.insert(u64_at(k, 0)); }
+            "groups" => { subs.insert(u64_at(k, 0)); }
+            _ => {}
+        }
+    }
+    let mut drop_pairs = Vec::new();
+    for (s, o) in pairs {
+        let below = descendants(o)?;
+        for d in dependents(s, Some(o))? { for &b in &below { drop_pairs.push((d, b)); } }
+    }
+    let mut drop_objs = HashSet::new();
+    for o in objs { drop_objs.extend(descendants(o)?); }
+    let mut drop_subs = HashSet::new();
+    for s in subs { drop_subs.extend(dependents(s, None)?); }
+    with(|c| {
+        c.generation += 1;
+        for k in drop_pairs { c.remove(k); }
+        for o in drop_objs {
+            for s in c.by_obj.get(&o).cloned().unwrap_or_default() { c.remove((s, o)); }
+        }
+        for s in drop_subs {
+            for o in c.by_sub.get(&s).cloned().unwrap_or_default() { c.remove((s, o)); }
+        }
+    });
+    Ok(())
+}
+
+// obj and every object below it
+fn descendants(obj: u64) -> Result<Vec<u64>> {
+    let mut out = vec![obj];
+    let mut i = 0;
+    while i < out.len() {
+        for c in scan(&CHILDREN.get().unwrap(), &out[i].to_be_bytes(), |k, _| u64_at(k, 1))? {
+            if !out.contains(&c) { out.push(c); }
+        }
+        i += 1;
+    }
+    Ok(out)
+}
+
+// sub and every subject whose mask is derived from it: group members, and subjects inheriting
+// from it (on obj, or on any object when None)
+fn dependents(sub: u64, obj: Option<u64>) -> Result<Vec<u64>> {
+    let mut out = vec![sub];
+    let mut i = 0;
+    while i < out.len() {
+        let s = out[i];
+        let mut next = scan(&MEMBERS.get().unwrap(), &s.to_be_bytes(), |k, _| u64_at(k, 1))?;
+        next.extend(match obj {
+            Some(o) => scan(&INHERITS_BY_PARENT.get().unwrap(), &key(s, o), |k, _| u64_at(k, 3))?,
+            None => scan(&INHERITS_BY_PARENT.get().unwrap(), &s.to_be_bytes(), |k, _| u64_at(k, 3))?,
+        });
+        for n in next { if !out.contains(&n) { out.push(n); } }
+        i += 1;
+    }
+    Ok(out)
+}
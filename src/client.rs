@@ -0,0 +1,190 @@
+//! One API over an embedded store or a capbit-server sidecar. `Api` carries the library's own
+//! signatures; `Embedded` calls the library in-process and `Client` sends each call over the
+//! server's Unix socket, naming the actor in `x-capbit-actor`. `open` picks one from a config
+//! string, so switching between the two is a config change:
+//!
+//!   open("capbit_data")?            // in-process store at that path
+//!   open("unix:/run/capbit.sock")?  // sidecar started with --listen unix:/run/capbit.sock
+//!
+//! `Client` needs a unix platform; elsewhere only the embedded store can be opened.
+
+use super::*;
+#[cfg(unix)]
+use {serde::de::DeserializeOwned, serde_json::{json, Value}, std::{io::{Read, Write}, os::unix::net::UnixStream, path::PathBuf}};
+
+macro_rules! api {
+    ($($name:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*) => {
+        pub trait Api: Send + Sync { $(fn $name(&self, $($arg: $ty),*) -> Result<$ret>;)* }
+        impl Api for Embedded { $(fn $name(&self, $($arg: $ty),*) -> Result<$ret> { super::$name($($arg),*) })* }
+    };
+}
+
+// The library of this process, opened with init()
+pub struct Embedded;
+
+api! {
+    check(sub: u64, obj: u64, req: u64) -> bool;
+    get_mask(sub: u64, obj: u64) -> u64;
+    check_many(sub: u64, reqs: &[(u64, u64)]) -> Vec<bool>;
+    get_masks(sub: u64, objs: &[u64]) -> Vec<u64>;
+    create(actor: u64, obj: u64, role: u64, mask: u64) -> ();
+    update(actor: u64, obj: u64, role: u64, mask: u64) -> ();
+    delete(actor: u64, obj: u64, role: u64) -> ();
+    get_object(actor: u64, obj: u64, role: u64) -> Option<u64>;
+    grant(actor: u64, sub: u64, obj: u64, role: u64) -> ();
+    revoke(actor: u64, sub: u64, obj: u64, role: u64) -> ();
+    inherit(actor: u64, sub: u64, obj: u64, role: u64, parent: u64) -> ();
+    remove_inherit(actor: u64, sub: u64, obj: u64, role: u64) -> ();
+    set_parent(actor: u64, obj: u64, parent: u64, mask: u64) -> ();
+    remove_parent(actor: u64, obj: u64, parent: u64) -> ();
+    add_member(actor: u64, group: u64, member: u64) -> ();
+    remove_member(actor: u64, group: u64, member: u64) -> ();
+    define_bit(actor: u64, scope: u64, bit: u8, name: &str, desc: &str) -> ();
+    undefine_bit(actor: u64, scope: u64, bit: u8) -> ();
+    list_roles(actor: u64, obj: u64) -> Vec<(u64, u64)>;
+    list_roles_for(actor: u64, sub: u64, obj: u64) -> Vec<u64>;
+    list_grants(actor: u64, sub: u64) -> Vec<(u64, u64)>;
+    list_subjects(actor: u64, obj: u64) -> Vec<(u64, u64)>;
+    list_inherits(actor: u64, sub: u64, obj: u64) -> Vec<(u64, u64)>;
+    list_inherits_on_obj(actor: u64, obj: u64) -> Vec<(u64, u64, u64)>;
+    list_inherits_on_obj_role(actor: u64, obj: u64, role: u64) -> Vec<(u64, u64)>;
+    list_inherits_from_parent(actor: u64, parent: u64) -> Vec<(u64, u64, u64)>;
+    list_inherits_from_parent_on_obj(actor: u64, parent: u64, obj: u64) -> Vec<(u64, u64)>;
+    list_parents(actor: u64, obj: u64) -> Vec<(u64, u64)>;
+    list_children(actor: u64, parent: u64) -> Vec<(u64, u64)>;
+    list_members(actor: u64, group: u64) -> Vec<u64>;
+    list_groups(actor: u64, sub: u64) -> Vec<u64>;
+    list_bits(actor: u64, scope: u64) -> Vec<(u8, String, String)>;
+}
+
+// "unix:PATH" talks to a sidecar on that socket; anything else opens the store at that path
+pub fn open(target: &str) -> Result<Box<dyn Api>> {
+    match target.strip_prefix("unix:") {
+        #[cfg(unix)]
+        Some(path) => Ok(Box::new(Client::new(path))),
+        #[cfg(not(unix))]
+        Some(_) => Err(Error("Unsupported: unix sockets need a unix platform".into())),
+        None => { init(target)?; Ok(Box::new(Embedded)) }
+    }
+}
+
+// A capbit-server listening on a Unix socket; each call is one HTTP/1.1 request
+#[cfg(unix)]
+pub struct Client { path: PathBuf }
+
+#[cfg(unix)]
+impl Client {
+    pub fn new(path: impl Into<PathBuf>) -> Self { Self { path: path.into() } }
+
+    // The status of the reply, its body and its x-next-cursor
+    fn request(&self, method: &str, uri: &str, actor: u64, body: Option<Value>, extra: &str) -> Result<(u16, Vec<u8>, Option<String>)> {
+        let mut s = UnixStream::connect(&self.path).map_err(err)?;
+        let body = body.map(|b| b.to_string()).unwrap_or_default();
+        write!(s, "{method} {uri} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\nx-capbit-actor: {actor}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n{extra}\r\n{body}", body.len()).map_err(err)?;
+        let mut buf = Vec::new();
+        s.read_to_end(&mut buf).map_err(err)?;
+        let split = buf.windows(4).position(|w| w == b"\r\n\r\n").ok_or_else(|| Error("Bad reply from server".into()))?;
+        let head = String::from_utf8_lossy(&buf[..split]);
+        let status: u16 = head.split(' ').nth(1).and_then(|s| s.parse().ok()).ok_or_else(|| Error("Bad reply from server".into()))?;
+        let next = head.lines().skip(1).filter_map(|l| l.split_once(':')).find(|(k, _)| k.eq_ignore_ascii_case("x-next-cursor")).map(|(_, v)| v.trim().to_string());
+        Ok((status, buf.split_off(split + 4), next))
+    }
+
+    // The body of a 2xx reply and its x-next-cursor; error replies come back as their Error
+    fn call(&self, method: &str, uri: &str, actor: u64, body: Option<Value>, extra: &str) -> Result<(Vec<u8>, Option<String>)> {
+        let (status, body, next) = self.request(method, uri, actor, body, extra)?;
+        Ok((ok(status, body)?, next))
+    }
+
+    fn send(&self, method: &str, uri: &str, actor: u64, body: Option<Value>) -> Result<()> { self.call(method, uri, actor, body, "").map(drop) }
+
+    fn get<T: DeserializeOwned>(&self, actor: u64, uri: &str) -> Result<T> {
+        serde_json::from_slice(&self.call("GET", uri, actor, None, "")?.0).map_err(err)
+    }
+
+    // Every page of a list route
+    fn list<T: DeserializeOwned>(&self, actor: u64, uri: &str) -> Result<Vec<T>> {
+        let (mut out, mut after) = (Vec::new(), None);
+        loop {
+            let uri = after.map_or(uri.to_string(), |c| format!("{uri}?after={c}"));
+            let (body, next) = self.call("GET", &uri, actor, None, "")?;
+            out.extend(serde_json::from_slice::<Vec<T>>(&body).map_err(err)?);
+            if next.is_none() { return Ok(out) }
+            after = next;
+        }
+    }
+}
+
+// The body of a 2xx reply, or the error the server sent
+#[cfg(unix)]
+fn ok(status: u16, body: Vec<u8>) -> Result<Vec<u8>> {
+    if status < 300 { return Ok(body) }
+    let msg = serde_json::from_slice::<Value>(&body).ok().and_then(|v| v["error"].as_str().map(str::to_owned));
+    Err(Error(msg.unwrap_or_else(|| format!("HTTP {status}"))))
+}
+
+// Resolution calls have no actor, so they run as the subject being resolved
+#[cfg(unix)]
+impl Api for Client {
+    fn check(&self, sub: u64, obj: u64, req: u64) -> Result<bool> {
+        let (body, _) = self.call("POST", "/check", sub, Some(json!({"sub": sub, "obj": obj, "mask": req})), "")?;
+        serde_json::from_slice::<Value>(&body).map_err(err)?["allowed"].as_bool().ok_or_else(|| Error("Bad reply from server".into()))
+    }
+    fn get_mask(&self, sub: u64, obj: u64) -> Result<u64> {
+        self.get::<Value>(sub, &format!("/subjects/{sub}/objects/{obj}/mask"))?["mask"].as_u64().ok_or_else(|| Error("Bad reply from server".into()))
+    }
+    fn check_many(&self, sub: u64, reqs: &[(u64, u64)]) -> Result<Vec<bool>> {
+        let items: Vec<Value> = reqs.iter().map(|&(obj, req)| json!({"obj": obj, "mask": req})).collect();
+        let (body, _) = self.call("POST", "/check/many", sub, Some(json!({"sub": sub, "items": items})), "")?;
+        serde_json::from_value(serde_json::from_slice::<Value>(&body).map_err(err)?["allowed"].take()).map_err(|_| Error("Bad reply from server".into()))
+    }
+    fn get_masks(&self, sub: u64, objs: &[u64]) -> Result<Vec<u64>> { objs.iter().map(|&obj| self.get_mask(sub, obj)).collect() }
+
+    fn create(&self, actor: u64, obj: u64, role: u64, mask: u64) -> Result<()> {
+        self.call("PUT", &format!("/objects/{obj}/roles/{role}"), actor, Some(json!({"mask": mask})), "if-none-match: *\r\n").map(drop)
+    }
+    fn update(&self, actor: u64, obj: u64, role: u64, mask: u64) -> Result<()> {
+        self.call("PUT", &format!("/objects/{obj}/roles/{role}"), actor, Some(json!({"mask": mask})), "if-match: *\r\n").map(drop)
+    }
+    fn delete(&self, actor: u64, obj: u64, role: u64) -> Result<()> { self.send("DELETE", &format!("/objects/{obj}/roles/{role}"), actor, None) }
+    fn get_object(&self, actor: u64, obj: u64, role: u64) -> Result<Option<u64>> {
+        // Only the API's own not-found reply means no role; any other 404 is an error
+        let (status, body, _) = self.request("GET", &format!("/objects/{obj}/roles/{role}"), actor, None, "")?;
+        match ok(status, body) {
+            Err(e) if status == 404 && e.kind() == ErrorKind::NotFound => Ok(None),
+            body => Ok(serde_json::from_slice::<Value>(&body?).map_err(err)?["mask"].as_u64()),
+        }
+    }
+    fn grant(&self, actor: u64, sub: u64, obj: u64, role: u64) -> Result<()> { self.send("PUT", &format!("/objects/{obj}/subjects/{sub}/roles/{role}"), actor, None) }
+    fn revoke(&self, actor: u64, sub: u64, obj: u64, role: u64) -> Result<()> { self.send("DELETE", &format!("/objects/{obj}/subjects/{sub}/roles/{role}"), actor, None) }
+    fn inherit(&self, actor: u64, sub: u64, obj: u64, role: u64, parent: u64) -> Result<()> {
+        self.send("PUT", &format!("/objects/{obj}/subjects/{sub}/inherits/{role}"), actor, Some(json!({"parent": parent})))
+    }
+    fn remove_inherit(&self, actor: u64, sub: u64, obj: u64, role: u64) -> Result<()> { self.send("DELETE", &format!("/objects/{obj}/subjects/{sub}/inherits/{role}"), actor, None) }
+    fn set_parent(&self, actor: u64, obj: u64, parent: u64, mask: u64) -> Result<()> { self.send("PUT", &format!("/objects/{obj}/parents/{parent}"), actor, Some(json!({"mask": mask}))) }
+    fn remove_parent(&self, actor: u64, obj: u64, parent: u64) -> Result<()> { self.send("DELETE", &format!("/objects/{obj}/parents/{parent}"), actor, None) }
+    fn add_member(&self, actor: u64, group: u64, member: u64) -> Result<()> { self.send("PUT", &format!("/groups/{group}/members/{member}"), actor, None) }
+    fn remove_member(&self, actor: u64, group: u64, member: u64) -> Result<()> { self.send("DELETE", &format!("/groups/{group}/members/{member}"), actor, None) }
+    fn define_bit(&self, actor: u64, scope: u64, bit: u8, name: &str, desc: &str) -> Result<()> {
+        self.send("PUT", &format!("/scopes/{scope}/bits/{bit}"), actor, Some(json!({"name": name, "desc": desc})))
+    }
+    fn undefine_bit(&self, actor: u64, scope: u64, bit: u8) -> Result<()> { self.send("DELETE", &format!("/scopes/{scope}/bits/{bit}"), actor, None) }
+
+    fn list_roles(&self, actor: u64, obj: u64) -> Result<Vec<(u64, u64)>> { self.list(actor, &format!("/objects/{obj}/roles")) }
+    fn list_roles_for(&self, actor: u64, sub: u64, obj: u64) -> Result<Vec<u64>> { self.list(actor, &format!("/objects/{obj}/subjects/{sub}/roles")) }
+    fn list_grants(&self, actor: u64, sub: u64) -> Result<Vec<(u64, u64)>> { self.list(actor, &format!("/subjects/{sub}/grants")) }
+    fn list_subjects(&self, actor: u64, obj: u64) -> Result<Vec<(u64, u64)>> { self.list(actor, &format!("/objects/{obj}/subjects")) }
+    fn list_inherits(&self, actor: u64, sub: u64, obj: u64) -> Result<Vec<(u64, u64)>> { self.list(actor, &format!("/objects/{obj}/subjects/{sub}/inherits")) }
+    fn list_inherits_on_obj(&self, actor: u64, obj: u64) -> Result<Vec<(u64, u64, u64)>> { self.list(actor, &format!("/objects/{obj}/inherits")) }
+    fn list_inherits_on_obj_role(&self, actor: u64, obj: u64, role: u64) -> Result<Vec<(u64, u64)>> { self.list(actor, &format!("/objects/{obj}/inherits/{role}")) }
+    fn list_inherits_from_parent(&self, actor: u64, parent: u64) -> Result<Vec<(u64, u64, u64)>> { self.list(actor, &format!("/subjects/{parent}/inheritors")) }
+    fn list_inherits_from_parent_on_obj(&self, actor: u64, parent: u64, obj: u64) -> Result<Vec<(u64, u64)>> { self.list(actor, &format!("/subjects/{parent}/inheritors/{obj}")) }
+    fn list_parents(&self, actor: u64, obj: u64) -> Result<Vec<(u64, u64)>> { self.list(actor, &format!("/objects/{obj}/parents")) }
+    fn list_children(&self, actor: u64, parent: u64) -> Result<Vec<(u64, u64)>> { self.list(actor, &format!("/objects/{parent}/children")) }
+    fn list_members(&self, actor: u64, group: u64) -> Result<Vec<u64>> { self.list(actor, &format!("/groups/{group}/members")) }
+    fn list_groups(&self, actor: u64, sub: u64) -> Result<Vec<u64>> { self.list(actor, &format!("/subjects/{sub}/groups")) }
+    fn list_bits(&self, actor: u64, scope: u64) -> Result<Vec<(u8, String, String)>> {
+        let bits: Vec<Value> = self.list(actor, &format!("/scopes/{scope}/bits"))?;
+        Ok(bits.iter().map(|b| (b["bit"].as_u64().unwrap_or_default() as u8, b["name"].as_str().unwrap_or_default().into(), b["desc"].as_str().unwrap_or_default().into())).collect())
+    }
+}
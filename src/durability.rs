@@ -0,0 +1,164 @@
+//! Durability of commits. Buffer hands writes to the OS; SyncData and SyncAll fsync the journal
+//! before the call returns. With group commit, syncing writers wait for a background flusher
+//! that fsyncs once per interval for everyone who committed in the meantime. Once one of its
+//! fsyncs fails, every commit from that flush on reports the error: the kernel may have dropped
+//! the pages it could not write, so a later fsync succeeding does not make them durable.
+
+use super::*;
+use std::{cell::Cell, sync::{atomic::{AtomicU64, Ordering}, Condvar}, time::Duration};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Durability { #[default] Buffer, SyncData, SyncAll }
+
+impl From<Durability> for fjall::PersistMode {
+    fn from(d: Durability) -> Self {
+        match d {
+            Durability::Buffer => fjall::PersistMode::Buffer,
+            Durability::SyncData => fjall::PersistMode::SyncData,
+            Durability::SyncAll => fjall::PersistMode::SyncAll,
+        }
+    }
+}
+
+static DEFAULT: Mutex<Durability> = Mutex::new(Durability::Buffer);
+thread_local! { static OVERRIDE: Cell<Option<Durability>> = const { Cell::new(None) }; }
+static SYNCS: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_durability(d: Durability) { *DEFAULT.lock().unwrap_or_else(|e| e.into_inner()) = d; }
+
+pub fn durability() -> Durability {
+    OVERRIDE.with(Cell::get).unwrap_or_else(|| *DEFAULT.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+// Commits made inside f use d, e.g. durable(Durability::SyncAll, || revoke(root, admin, _SYSTEM, _ADMIN)).
+// The override belongs to the calling thread; aio::Handle carries it to the blocking pool.
+pub fn durable<T>(d: Durability, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    struct Restore(Option<Durability>);
+    impl Drop for Restore { fn drop(&mut self) { OVERRIDE.with(|o| o.set(self.0)); } }
+    let _r = Restore(OVERRIDE.with(|o| o.replace(Some(d))));
+    f()
+}
+
+// The calling thread's override, if inside durable()
+#[cfg(feature = "async")]
+pub(crate) fn overridden() -> Option<Durability> { OVERRIDE.with(Cell::get) }
+
+// Fsyncs issued for commits since start; with group commit, one covers many commits
+pub fn sync_count() -> u64 { SYNCS.load(Ordering::Relaxed) }
+
+// Fails every fsync while set, for testing commits the disk rejects
+#[cfg(test)]
+static FAIL_SYNCS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+// Counts the fsyncs that commits wait for
+fn sync(mode: Durability) -> fjall::Result<()> {
+    if mode != Durability::Buffer { SYNCS.fetch_add(1, Ordering::Relaxed); }
+    #[cfg(test)]
+    if mode != Durability::Buffer && FAIL_SYNCS.load(Ordering::Relaxed) { return Err(std::io::Error::other("fsync failed").into()); }
+    ks().persist(mode.into())
+}
+
+#[derive(Default)]
+struct Group {
+    interval: Option<Duration>,
+    running: bool,
+    requested: u64,
+    synced: u64,
+    mode: Durability,
+    // First sequence of the first flush that failed, with its error
+    failed: Option<(u64, String)>,
+}
+
+impl Group {
+    fn fail(&mut self, from: u64, e: String) { self.failed.get_or_insert((from, e)); }
+
+    fn outcome(&self, ticket: u64) -> Result<()> {
+        match &self.failed {
+            Some((first, e)) if ticket >= *first => Err(Error(e.clone())),
+            _ => Ok(()),
+        }
+    }
+}
+
+static GROUP: (Mutex<Group>, Condvar) = (Mutex::new(Group { interval: None, running: false, requested: 0, synced: 0, mode: Durability::Buffer, failed: None }), Condvar::new());
+
+// Fsyncs everything committed so far, whatever the durability in effect, e.g. before exit
+pub fn flush() -> Result<()> { ks().persist(fjall::PersistMode::SyncAll).map_err(err) }
+
+// None turns group commit off; pending syncs are still flushed
+pub fn set_group_commit(interval: Option<Duration>) {
+    GROUP.0.lock().unwrap_or_else(|e| e.into_inner()).interval = interval;
+}
+
+// Called by commit() once the batch is applied
+pub(crate) fn persist() -> Result<()> {
+    let mode = durability();
+    let (lock, cv) = &GROUP;
+    let mut g = lock.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(interval) = g.interval.filter(|_| mode != Durability::Buffer) else {
+        drop(g);
+        return sync(mode).map_err(err);
+    };
+    g.requested += 1;
+    g.mode = g.mode.max(mode);
+    let ticket = g.requested;
+    if !g.running {
+        g.running = true;
+        std::thread::spawn(move || flusher(interval));
+    }
+    while g.synced < ticket { g = cv.wait(g).unwrap_or_else(|e| e.into_inner()); }
+    g.outcome(ticket)
+}
+
+fn flusher(mut interval: Duration) {
+    let (lock, cv) = &GROUP;
+    loop {
+        std::thread::sleep(interval);
+        let mut g = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if g.requested == g.synced {
+            match g.interval {
+                Some(i) => { interval = i; continue; }
+                None => { g.running = false; return; }
+            }
+        }
+        let (from, to, mode) = (g.synced + 1, g.requested, std::mem::take(&mut g.mode));
+        drop(g);
+        let out = sync(mode);
+        let mut g = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = out { g.fail(from, e.to_string()); }
+        g.synced = to;
+        interval = g.interval.unwrap_or(interval);
+        cv.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test] fn test_failures_latch() {
+        let mut g = Group::default();
+        g.fail(3, "first".into());
+        g.fail(6, "second".into());
+        // A waiter from the first failed flush that wakes after the second still sees its error
+        assert!(g.outcome(2).is_ok());
+        assert_eq!(g.outcome(4).unwrap_err().0, "first");
+        assert_eq!(g.outcome(7).unwrap_err().0, "first");
+    }
+
+    // A commit that reached the store is visible to cached reads even if its fsync fails
+    #[test] fn test_failed_persist_invalidates() {
+        init("target/test_db_durability").unwrap();
+        clear().unwrap();
+        let (sys, root) = bootstrap().unwrap();
+        set_cache_capacity(100);
+        grant(root, 10, sys, _VIEWER).unwrap();
+        assert!(check(10, sys, VIEWER_BITS).unwrap());
+        FAIL_SYNCS.store(true, Ordering::Relaxed);
+        let failed = durable(Durability::SyncAll, || revoke(root, 10, sys, _VIEWER));
+        FAIL_SYNCS.store(false, Ordering::Relaxed);
+        assert!(failed.unwrap_err().0.contains("fsync failed"));
+        assert!(!check(10, sys, VIEWER_BITS).unwrap());
+        set_cache_capacity(0);
+    }
+}
@@ -0,0 +1,152 @@
+//! Integrity checks. Every reverse index is recomputed from its forward partition and compared
+//! entry by entry; forward tuples are checked for edges resolution can never follow, roles that
+//! fall back to their own id, and cycles. Repair rewrites the reverse indexes from the forward
+//! partitions in one commit; the other findings are policy and are only reported.
+
+use super::*;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    // Reverse index entry the forward partition implies but which is absent
+    Missing { index: &'static str, key: Vec<u8> },
+    // Reverse index entry with no forward tuple behind it
+    Orphaned { index: &'static str, key: Vec<u8> },
+    // Reverse index entry whose value disagrees with the forward tuple
+    Mismatch { index: &'static str, key: Vec<u8> },
+    // Inherit edge for a role the subject was never granted, so resolution never follows it
+    DanglingInherit { sub: u64, obj: u64, role: u64, parent: u64 },
+    // Grant of a role not declared on the object; it resolves to the role id as a mask
+    UndeclaredRole { sub: u64, obj: u64, role: u64 },
+    // Loop in the parent or group graph, first id repeated at the end
+    Cycle { index: &'static str, ids: Vec<u64> },
+    // Loop of inherit edges between subjects on one object
+    InheritCycle { obj: u64, subs: Vec<u64> },
+}
+
+impl Issue {
+    pub fn repairable(&self) -> bool { matches!(self, Issue::Missing { .. } | Issue::Orphaned { .. } | Issue::Mismatch { .. }) }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = |k: &[u8]| k.iter().map(|b| format!("{b:02x}")).collect::<String>();
+        let path = |ids: &[u64]| ids.iter().map(u64::to_string).collect::<Vec<_>>().join(" → ");
+        match self {
+            Issue::Missing { index, key } => write!(f, "{index}: missing {}", hex(key)),
+            Issue::Orphaned { index, key } => write!(f, "{index}: orphaned {}", hex(key)),
+            Issue::Mismatch { index, key } => write!(f, "{index}: wrong value at {}", hex(key)),
+            Issue::DanglingInherit { sub, obj, role, parent } => write!(f, "inherit {sub} → {parent} on {obj} role {role}: role not granted"),
+            Issue::UndeclaredRole { sub, obj, role } => write!(f, "grant {sub} on {obj} role {role}: role not declared"),
+            Issue::Cycle { index, ids } => write!(f, "{index}: cycle {}", path(ids)),
+            Issue::InheritCycle { obj, subs } => write!(f, "inherits on {obj}: cycle {}", path(subs)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport { pub issues: Vec<Issue>, pub repaired: usize }
+
+type Rows = Vec<(Vec<u8>, Vec<u8>)>;
+
+// Checks the partitions against one state: a report reads them at one instant and takes no lock,
+// so it also runs on followers, while a repair runs under the write lock
+pub fn verify(repair: bool) -> Result<VerifyReport> {
+    if repair { return write(|| inspect(true, |p| scan(p, &[], |k, v| (k.to_vec(), v.to_vec())))); }
+    let at = ks().instant();
+    inspect(false, |p| p.snapshot_at(at).iter().map(|kv| kv.map(|(k, v)| (k.to_vec(), v.to_vec())).map_err(err)).collect())
+}
+
+fn inspect(repair: bool, all: impl Fn(&PartitionHandle) -> Result<Rows>) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let one = 1u64.to_be_bytes().to_vec();
+    let (subjects, inherits, parents) = (all(&SUBJECTS.get().unwrap())?, all(&INHERITS.get().unwrap())?, all(&PARENTS.get().unwrap())?);
+    let groups = all(&GROUPS.get().unwrap())?;
+
+    let mut expect = [("subjects_rev", &SUBJECTS_REV), ("inherits_by_obj", &INHERITS_BY_OBJ),
+        ("inherits_by_parent", &INHERITS_BY_PARENT), ("children", &CHILDREN), ("members", &MEMBERS),
+        ("bit_names", &BIT_NAMES), ("ids", &IDS)].map(|(n, p)| (n, p.get().unwrap(), BTreeMap::new()));
+    for (k, _) in &subjects { expect[0].2.insert(key3(u64_at(k, 1), u64_at(k, 0), u64_at(k, 2)).to_vec(), one.clone()); }
+    for (k, v) in &inherits {
+        let (s, o, r, p) = (u64_at(k, 0), u64_at(k, 1), u64_at(k, 2), val(v));
+        expect[1].2.insert(key4(o, r, p, s).to_vec(), one.clone());
+        expect[2].2.insert(key4(p, o, r, s).to_vec(), one.clone());
+    }
+    for (k, v) in &parents { expect[3].2.insert(key(u64_at(k, 1), u64_at(k, 0)).to_vec(), v.clone()); }
+    for (k, _) in &groups { expect[4].2.insert(key(u64_at(k, 1), u64_at(k, 0)).to_vec(), one.clone()); }
+    for (k, v) in all(&BITS.get().unwrap())? { expect[5].2.insert(key_str(u64_at(&k, 0), bit_name(&v)), u64_at(&k, 1).to_be_bytes().to_vec()); }
+    for (k, v) in all(&NAMES.get().unwrap())? { expect[6].2.insert([&k[..1], &v[..]].concat(), k[1..].to_vec()); }
+
+    for (index, p, want) in expect {
+        let have: BTreeMap<Vec<u8>, Vec<u8>> = all(&p)?.into_iter().collect();
+        for (k, v) in &want {
+            match have.get(k) {
+                None => report.issues.push(Issue::Missing { index, key: k.clone() }),
+                Some(h) if h != v => report.issues.push(Issue::Mismatch { index, key: k.clone() }),
+                _ => continue,
+            }
+            if repair { set_raw(&p, k, v); report.repaired += 1; }
+        }
+        for k in have.keys().filter(|k| !want.contains_key(*k)) {
+            report.issues.push(Issue::Orphaned { index, key: k.clone() });
+            if repair { del(&p, k); report.repaired += 1; }
+        }
+    }
+
+    let granted: BTreeSet<&[u8]> = subjects.iter().map(|(k, _)| &k[..]).collect();
+    for (k, v) in &inherits {
+        let (sub, obj, role, parent) = (u64_at(k, 0), u64_at(k, 1), u64_at(k, 2), val(v));
+        if !granted.contains(&key3(sub, obj, role)[..]) {
+            report.issues.push(Issue::DanglingInherit { sub, obj, role, parent });
+        }
+    }
+    let declared: BTreeSet<Vec<u8>> = all(&OBJECTS.get().unwrap())?.into_iter().map(|(k, _)| k).collect();
+    for (k, _) in &subjects {
+        let (sub, obj, role) = (u64_at(k, 0), u64_at(k, 1), u64_at(k, 2));
+        if !declared.contains(&key(obj, role)[..]) {
+            report.issues.push(Issue::UndeclaredRole { sub, obj, role });
+        }
+    }
+
+    let graph = |rows: &[(Vec<u8>, Vec<u8>)], from: usize, to: usize| {
+        let mut g: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for (k, _) in rows { g.entry(u64_at(k, from)).or_default().push(u64_at(k, to)); }
+        g
+    };
+    for ids in cycles(&graph(&parents, 0, 1)) { report.issues.push(Issue::Cycle { index: "parents", ids }); }
+    for ids in cycles(&graph(&groups, 0, 1)) { report.issues.push(Issue::Cycle { index: "groups", ids }); }
+    let mut by_obj: BTreeMap<u64, BTreeMap<u64, Vec<u64>>> = BTreeMap::new();
+    for (k, v) in &inherits { by_obj.entry(u64_at(k, 1)).or_default().entry(u64_at(k, 0)).or_default().push(val(v)); }
+    for (obj, g) in by_obj {
+        for subs in cycles(&g) { report.issues.push(Issue::InheritCycle { obj, subs }); }
+    }
+    Ok(report)
+}
+
+// Every cycle reachable by depth-first search, each reported once as a closed path
+pub(crate) fn cycles(edges: &BTreeMap<u64, Vec<u64>>) -> Vec<Vec<u64>> {
+    let mut done: HashMap<u64, bool> = HashMap::new();
+    let mut out = Vec::new();
+    for &start in edges.keys() {
+        if done.contains_key(&start) { continue; }
+        let (mut path, mut next) = (vec![start], vec![0]);
+        done.insert(start, false);
+        while let (Some(&node), Some(i)) = (path.last(), next.last_mut()) {
+            match edges.get(&node).and_then(|e| e.get(*i)) {
+                Some(&n) => {
+                    *i += 1;
+                    match done.get(&n) {
+                        None => { done.insert(n, false); path.push(n); next.push(0); }
+                        Some(false) => {
+                            let at = path.iter().position(|&p| p == n).unwrap();
+                            out.push([&path[at..], &[n]].concat());
+                        }
+                        Some(true) => {}
+                    }
+                }
+                None => { done.insert(node, true); path.pop(); next.pop(); }
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,134 @@
+//! gRPC service generated from proto/capbit.proto, served over an async Handle. Calls carry
+//! `authorization: Bearer <key>` metadata and run as the key's subject; checking another subject's
+//! rights needs what may_inspect requires. Errors map to status codes through Error::kind. Only
+//! the default tenant is served, and Watch streams its commits from the moment it is called, for
+//! as long as the watcher keeps ADMIN_BITS on _SYSTEM. A watcher more than WATCH_BUFFER commits
+//! behind is dropped: its stream ends once the buffered commits are read, and it must call Watch
+//! again.
+
+use super::{aio::Handle, watch, *};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+// Commits queued per watcher; subscribers run under the write lock, so a full queue is never waited on
+pub const WATCH_BUFFER: usize = 1024;
+
+pub mod pb { tonic::include_proto!("capbit.v1"); }
+
+use pb::{capbit_server::{Capbit, CapbitServer}, event::Kind};
+
+impl From<Error> for Status {
+    fn from(e: Error) -> Self {
+        match e.kind() {
+            ErrorKind::Denied => Status::permission_denied(e.0),
+            ErrorKind::Unauthorized => Status::unauthenticated(e.0),
+            ErrorKind::NotFound => Status::not_found(e.0),
+            ErrorKind::Conflict => Status::already_exists(e.0),
+            ErrorKind::Invalid => Status::invalid_argument(e.0),
+            ErrorKind::Internal => Status::internal(e.0),
+        }
+    }
+}
+
+impl From<watch::Event> for pb::Event {
+    fn from(e: watch::Event) -> Self {
+        let kind = match e {
+            watch::Event::Role { obj, role, mask } => Kind::Role(pb::RoleEvent { obj, role, mask }),
+            watch::Event::Grant { sub, obj, role, granted } => Kind::Grant(pb::GrantEvent { sub, obj, role, granted }),
+            watch::Event::Inherit { sub, obj, role, parent } => Kind::Inherit(pb::InheritEvent { sub, obj, role, parent }),
+            watch::Event::Parent { obj, parent, mask } => Kind::Parent(pb::ParentEvent { obj, parent, mask }),
+            watch::Event::Member { group, member, added } => Kind::Member(pb::MemberEvent { group, member, added }),
+        };
+        pb::Event { kind: Some(kind) }
+    }
+}
+
+pub struct Service { h: Handle }
+
+pub fn service(h: Handle) -> CapbitServer<Service> { CapbitServer::new(Service { h }) }
+
+fn pairs(v: Vec<(u64, u64)>) -> Response<pb::Pairs> { Response::new(pb::Pairs { items: v.into_iter().map(|(a, b)| pb::Pair { a, b }).collect() }) }
+fn triples(v: Vec<(u64, u64, u64)>) -> Response<pb::Triples> { Response::new(pb::Triples { items: v.into_iter().map(|(a, b, c)| pb::Triple { a, b, c }).collect() }) }
+fn ids(ids: Vec<u64>) -> Response<pb::Ids> { Response::new(pb::Ids { ids }) }
+fn done() -> Response<pb::Empty> { Response::new(pb::Empty {}) }
+
+impl Service {
+    // The subject behind the call's bearer key
+    async fn actor<T>(&self, req: &Request<T>) -> Result<u64> {
+        let bearer = req.metadata().get("authorization").and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer "));
+        let Some(token) = bearer.map(str::to_owned) else { return Err(Error("Unauthorized".into())) };
+        self.h.run(move || keys::authenticate(&token)).await
+    }
+}
+
+type Reply<T> = std::result::Result<Response<T>, Status>;
+
+#[tonic::async_trait]
+impl Capbit for Service {
+    async fn check(&self, req: Request<pb::CheckRequest>) -> Reply<pb::CheckResponse> {
+        let (a, r) = (self.actor(&req).await?, req.into_inner());
+        Ok(Response::new(pb::CheckResponse { allowed: self.h.run(move || { may_inspect(a, r.sub, r.obj)?; check(r.sub, r.obj, r.mask) }).await? }))
+    }
+    async fn check_many(&self, req: Request<pb::CheckManyRequest>) -> Reply<pb::CheckManyResponse> {
+        let (a, r) = (self.actor(&req).await?, req.into_inner());
+        let items: Vec<(u64, u64)> = r.items.iter().map(|i| (i.obj, i.mask)).collect();
+        let allowed = self.h.run(move || {
+            for &(obj, _) in &items { may_inspect(a, r.sub, obj)?; }
+            check_many(r.sub, &items)
+        }).await?;
+        Ok(Response::new(pb::CheckManyResponse { allowed }))
+    }
+    async fn get_mask(&self, req: Request<pb::GetMaskRequest>) -> Reply<pb::GetMaskResponse> {
+        let (a, r) = (self.actor(&req).await?, req.into_inner());
+        Ok(Response::new(pb::GetMaskResponse { mask: self.h.run(move || { may_inspect(a, r.sub, r.obj)?; get_mask(r.sub, r.obj) }).await? }))
+    }
+
+    async fn grant(&self, req: Request<pb::GrantRequest>) -> Reply<pb::Empty> {
+        let (a, r) = (self.actor(&req).await?, req.into_inner());
+        self.h.grant(a, r.sub, r.obj, r.role).await?;
+        Ok(done())
+    }
+    async fn revoke(&self, req: Request<pb::GrantRequest>) -> Reply<pb::Empty> {
+        let (a, r) = (self.actor(&req).await?, req.into_inner());
+        self.h.revoke(a, r.sub, r.obj, r.role).await?;
+        Ok(done())
+    }
+    async fn inherit(&self, req: Request<pb::InheritRequest>) -> Reply<pb::Empty> {
+        let (a, r) = (self.actor(&req).await?, req.into_inner());
+        self.h.inherit(a, r.sub, r.obj, r.role, r.parent).await?;
+        Ok(done())
+    }
+    async fn remove_inherit(&self, req: Request<pb::GrantRequest>) -> Reply<pb::Empty> {
+        let (a, r) = (self.actor(&req).await?, req.into_inner());
+        self.h.remove_inherit(a, r.sub, r.obj, r.role).await?;
+        Ok(done())
+    }
+
+    async fn list_roles(&self, req: Request<pb::ObjectRequest>) -> Reply<pb::Pairs> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(pairs(self.h.list_roles(a, r.obj).await?)) }
+    async fn list_roles_for(&self, req: Request<pb::SubjectObjectRequest>) -> Reply<pb::Ids> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(ids(self.h.list_roles_for(a, r.sub, r.obj).await?)) }
+    async fn list_grants(&self, req: Request<pb::SubjectRequest>) -> Reply<pb::Pairs> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(pairs(self.h.list_grants(a, r.sub).await?)) }
+    async fn list_subjects(&self, req: Request<pb::ObjectRequest>) -> Reply<pb::Pairs> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(pairs(self.h.list_subjects(a, r.obj).await?)) }
+    async fn list_inherits(&self, req: Request<pb::SubjectObjectRequest>) -> Reply<pb::Pairs> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(pairs(self.h.list_inherits(a, r.sub, r.obj).await?)) }
+    async fn list_inherits_on_obj(&self, req: Request<pb::ObjectRequest>) -> Reply<pb::Triples> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(triples(self.h.list_inherits_on_obj(a, r.obj).await?)) }
+    async fn list_inherits_on_obj_role(&self, req: Request<pb::ObjectRoleRequest>) -> Reply<pb::Pairs> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(pairs(self.h.list_inherits_on_obj_role(a, r.obj, r.role).await?)) }
+    async fn list_inherits_from_parent(&self, req: Request<pb::ParentRequest>) -> Reply<pb::Triples> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(triples(self.h.list_inherits_from_parent(a, r.parent).await?)) }
+    async fn list_inherits_from_parent_on_obj(&self, req: Request<pb::ParentObjectRequest>) -> Reply<pb::Pairs> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(pairs(self.h.list_inherits_from_parent_on_obj(a, r.parent, r.obj).await?)) }
+    async fn list_parents(&self, req: Request<pb::ObjectRequest>) -> Reply<pb::Pairs> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(pairs(self.h.list_parents(a, r.obj).await?)) }
+    async fn list_children(&self, req: Request<pb::ObjectRequest>) -> Reply<pb::Pairs> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(pairs(self.h.list_children(a, r.obj).await?)) }
+    async fn list_members(&self, req: Request<pb::SubjectRequest>) -> Reply<pb::Ids> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(ids(self.h.list_members(a, r.sub).await?)) }
+    async fn list_groups(&self, req: Request<pb::SubjectRequest>) -> Reply<pb::Ids> { let (a, r) = (self.actor(&req).await?, req.into_inner()); Ok(ids(self.h.list_groups(a, r.sub).await?)) }
+
+    type WatchStream = ReceiverStream<std::result::Result<pb::Commit, Status>>;
+
+    async fn watch(&self, req: Request<pb::WatchRequest>) -> Reply<Self::WatchStream> {
+        let a = self.actor(&req).await?;
+        if !self.h.check(a, _SYSTEM, ADMIN_BITS).await? { return Err(Error("Denied".into()).into()) }
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+        // Dropped streams, full queues and watchers no longer holding ADMIN_BITS fail the send,
+        // which unsubscribes and closes the stream; the check sees the commit being published
+        watch::subscribe(move |c| c.tenant.is_some() || (check(a, _SYSTEM, ADMIN_BITS).unwrap_or(false)
+            && tx.try_send(Ok(pb::Commit { seq: c.seq, events: c.events.iter().cloned().map(Into::into).collect() })).is_ok()));
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
@@ -0,0 +1,410 @@
+//! REST API over an async Handle. Resources are addressed by numeric id in the path, writes are
+//! idempotent PUTs and DELETEs, lists are JSON arrays of tuples and masks are plain numbers.
+//! Lists return at most `?limit=` items (default and cap MAX_PAGE); when more follow, the
+//! `x-next-cursor` header carries the value to pass as `?after=` for the next page.
+//! Every route except /health, /openapi.json and /metrics needs `Authorization: Bearer <key>`
+//! and runs as the key's subject. Errors are `{"error": msg}` with a status derived from
+//! Error::kind.
+//!
+//! Each handler carries its OpenAPI operation, so request and response schemas come from the
+//! handler types; `openapi()` assembles the document served at /openapi.json.
+//!
+//! `serve_unix` serves the same router on a Unix socket for sidecar use. Reaching the socket is
+//! the access check there, so callers may name their actor with `x-capbit-actor` instead of a key.
+//!
+//! `x-capbit-tenant` runs a request in that tenant, keys included, so a key only works for the
+//! tenant it was issued in. Without it requests use the default tenant. /admin/bootstrap with
+//! the header creates the tenant first and needs a root key of the default tenant.
+
+use super::{aio::Handle, *};
+use axum::{async_trait, extract::{FromRequestParts, Path, Query, State}, http::{header::{AUTHORIZATION, IF_MATCH, IF_NONE_MATCH}, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response}, routing::{on, MethodFilter, MethodRouter}, Json, Router};
+use serde::{Deserialize, Serialize};
+use utoipa::{openapi::{self, security::{HttpAuthScheme, HttpBuilder, SecurityScheme}, ContentBuilder, Ref, RefOr, ResponseBuilder}, IntoParams, OpenApi, ToSchema};
+
+pub struct ApiError(pub Error);
+
+impl From<Error> for ApiError { fn from(e: Error) -> Self { ApiError(e) } }
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self.0.kind() {
+            ErrorKind::Denied => StatusCode::FORBIDDEN,
+            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::Conflict => StatusCode::CONFLICT,
+            ErrorKind::Invalid => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(ErrorBody { error: self.0 .0 })).into_response()
+    }
+}
+
+// The error statuses any authenticated operation may answer with
+impl utoipa::IntoResponses for ApiError {
+    fn responses() -> BTreeMap<String, RefOr<openapi::Response>> {
+        [("401", "No or unknown API key"), ("403", "Denied"), ("404", "Unknown id"), ("409", "Already exists"),
+            ("422", "Invalid request or tuple"), ("500", "Internal error")]
+            .map(|(status, desc)| {
+                let body = ContentBuilder::new().schema(Some(Ref::from_schema_name("ErrorBody"))).build();
+                (status.to_string(), ResponseBuilder::new().description(desc).content("application/json", body).build().into())
+            })
+            .into()
+    }
+}
+
+type ApiResult<T> = std::result::Result<T, ApiError>;
+
+// The handle for the request's x-capbit-tenant, or the default tenant's
+pub struct Scoped(pub Handle);
+
+fn tenant_header(headers: &HeaderMap) -> ApiResult<Option<String>> {
+    let Some(v) = headers.get("x-capbit-tenant") else { return Ok(None) };
+    Ok(Some(v.to_str().map_err(|_| Error("Invalid".into()))?.to_owned()))
+}
+
+#[async_trait]
+impl FromRequestParts<Handle> for Scoped {
+    type Rejection = ApiError;
+    async fn from_request_parts(parts: &mut Parts, h: &Handle) -> ApiResult<Self> {
+        Ok(Scoped(tenant_header(&parts.headers)?.map_or_else(|| h.clone(), |t| h.tenant(&t))))
+    }
+}
+
+// The subject behind the request's bearer key, or the x-capbit-actor of a local socket peer
+pub struct Actor(pub u64);
+
+// Marks requests that arrived over serve_unix
+#[derive(Clone, Copy)]
+struct Local;
+
+#[async_trait]
+impl FromRequestParts<Handle> for Actor {
+    type Rejection = ApiError;
+    async fn from_request_parts(parts: &mut Parts, h: &Handle) -> ApiResult<Self> {
+        if let (Some(Local), Some(v)) = (parts.extensions.get::<Local>(), parts.headers.get("x-capbit-actor")) {
+            return v.to_str().ok().and_then(|v| v.parse().ok()).map(Actor).ok_or_else(|| Error("Invalid".into()).into());
+        }
+        let bearer = parts.headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer "));
+        let Some(token) = bearer.map(str::to_owned) else { return Err(Error("Unauthorized".into()).into()) };
+        let Scoped(h) = Scoped::from_request_parts(parts, h).await?;
+        Ok(Actor(h.run(move || keys::authenticate(&token)).await?))
+    }
+}
+
+pub const MAX_PAGE: usize = 1000;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    /// Items per page, at most 1000
+    pub limit: Option<usize>,
+    /// x-next-cursor of the previous page
+    pub after: Option<String>,
+}
+
+// The ?limit= and ?after= of a list request
+pub struct Paging(pub Option<Cursor>, pub usize);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Paging {
+    type Rejection = ApiError;
+    async fn from_request_parts(parts: &mut Parts, s: &S) -> ApiResult<Self> {
+        let Query(q) = Query::<PageQuery>::from_request_parts(parts, s).await.map_err(|_| Error("Invalid".into()))?;
+        let after = q.after.map(|c| c.parse()).transpose()?;
+        Ok(Paging(after, q.limit.unwrap_or(MAX_PAGE).clamp(1, MAX_PAGE)))
+    }
+}
+
+// One page as a JSON array, with the cursor for the next one in x-next-cursor
+pub struct Listed<T>(pub Page<T>);
+
+impl<T: Serialize> IntoResponse for Listed<T> {
+    fn into_response(self) -> Response {
+        let next = self.0.next.map(|c| [("x-next-cursor", c.to_string())]);
+        (next, Json(self.0.items)).into_response()
+    }
+}
+
+#[derive(Serialize, ToSchema)] pub struct ErrorBody { pub error: String }
+#[derive(Deserialize, ToSchema)] pub struct CheckBody { pub sub: u64, pub obj: u64, pub mask: u64 }
+#[derive(Deserialize, ToSchema)] pub struct CheckManyBody { pub sub: u64, pub items: Vec<CheckItem> }
+#[derive(Deserialize, ToSchema)] pub struct CheckItem { pub obj: u64, pub mask: u64 }
+#[derive(Deserialize, ToSchema)] pub struct MaskBody { pub mask: u64 }
+#[derive(Deserialize, ToSchema)] pub struct InheritBody { pub parent: u64 }
+#[derive(Deserialize, ToSchema)] pub struct BitBody { pub name: String, #[serde(default)] pub desc: String }
+#[derive(Serialize, ToSchema)] pub struct Allowed { pub allowed: bool }
+#[derive(Serialize, ToSchema)] pub struct AllowedMany { pub allowed: Vec<bool> }
+#[derive(Serialize, ToSchema)] pub struct Mask {
+    pub mask: u64,
+    /// Name of each set bit, lowest first, from the object's bit registry or the built-in names
+    pub names: Vec<String>,
+}
+// The mask with its bit names as registered for obj
+fn named(obj: u64, mask: u64) -> Result<Mask> { Ok(Mask { mask, names: names_from_mask(obj, mask)? }) }
+#[derive(Serialize, ToSchema)] pub struct Bit { pub bit: u8, pub name: String, pub desc: String }
+#[derive(Serialize, ToSchema)] pub struct Bootstrapped { pub system: u64, pub root: u64, pub key: String }
+
+#[utoipa::path(get, path = "/health", security(()), responses((status = 200, body = String)))]
+async fn health() -> &'static str { "ok" }
+#[cfg(feature = "metrics")]
+#[utoipa::path(get, path = "/metrics", security(()), responses((status = 200, body = String, content_type = "text/plain; version=0.0.4")))]
+async fn metrics() -> ([(axum::http::header::HeaderName, &'static str); 1], String) {
+    ([(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")], super::metrics::render())
+}
+#[utoipa::path(get, path = "/openapi.json", security(()), responses((status = 200, description = "This document")))]
+async fn spec() -> Json<openapi::OpenApi> { Json(openapi()) }
+
+#[utoipa::path(post, path = "/check", request_body = CheckBody, responses((status = 200, body = Allowed), ApiError))]
+async fn check(Scoped(h): Scoped, Actor(a): Actor, Json(b): Json<CheckBody>) -> ApiResult<Json<Allowed>> {
+    Ok(Json(Allowed { allowed: h.run(move || { may_inspect(a, b.sub, b.obj)?; super::check(b.sub, b.obj, b.mask) }).await? }))
+}
+// Every item in one resolution; another subject needs may_inspect on each object
+#[utoipa::path(post, path = "/check/many", request_body = CheckManyBody, responses((status = 200, body = AllowedMany), ApiError))]
+async fn check_many(Scoped(h): Scoped, Actor(a): Actor, Json(b): Json<CheckManyBody>) -> ApiResult<Json<AllowedMany>> {
+    let reqs: Vec<(u64, u64)> = b.items.iter().map(|i| (i.obj, i.mask)).collect();
+    Ok(Json(AllowedMany { allowed: h.run(move || { for &(obj, _) in &reqs { may_inspect(a, b.sub, obj)?; } super::check_many(b.sub, &reqs) }).await? }))
+}
+#[utoipa::path(get, path = "/subjects/{sub}/objects/{obj}/mask", params(("sub" = u64, Path), ("obj" = u64, Path)), responses((status = 200, body = Mask), ApiError))]
+async fn get_mask(Scoped(h): Scoped, Actor(a): Actor, Path((sub, obj)): Path<(u64, u64)>) -> ApiResult<Json<Mask>> {
+    Ok(Json(h.run(move || { may_inspect(a, sub, obj)?; named(obj, super::get_mask(sub, obj)?) }).await?))
+}
+#[utoipa::path(get, path = "/subjects/{sub}/objects/{obj}/explain", params(("sub" = u64, Path), ("obj" = u64, Path)), responses((status = 200, body = Explanation), ApiError))]
+async fn explain(Scoped(h): Scoped, Actor(a): Actor, Path((sub, obj)): Path<(u64, u64)>) -> ApiResult<Json<Explanation>> {
+    Ok(Json(h.run(move || { may_inspect(a, sub, obj)?; super::explain(sub, obj) }).await?))
+}
+
+#[utoipa::path(get, path = "/objects/{obj}/roles", params(("obj" = u64, Path), PageQuery), responses((status = 200, body = Vec<(u64, u64)>, description = "(role, mask)", headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_roles(Scoped(h): Scoped, Actor(a): Actor, Path(obj): Path<u64>, Paging(after, limit): Paging) -> ApiResult<Listed<(u64, u64)>> { Ok(Listed(h.list_roles_page(a, obj, after, limit).await?)) }
+#[utoipa::path(get, path = "/objects/{obj}/roles/{role}", params(("obj" = u64, Path), ("role" = u64, Path)), responses((status = 200, body = Mask), ApiError))]
+async fn get_role(Scoped(h): Scoped, Actor(a): Actor, Path((obj, role)): Path<(u64, u64)>) -> ApiResult<Json<Mask>> {
+    let mask = h.get_object(a, obj, role).await?.ok_or_else(|| Error(format!("Unknown: {role}")))?;
+    Ok(Json(h.run(move || named(obj, mask)).await?))
+}
+// Declares the role, or changes its mask if it exists. If-None-Match: * only declares and
+// If-Match: * only sets the mask, so each needs just the rights of create or update
+#[utoipa::path(put, path = "/objects/{obj}/roles/{role}", request_body = MaskBody,
+    params(("obj" = u64, Path), ("role" = u64, Path), ("If-None-Match" = Option<String>, Header, description = "* to fail with 409 if the role exists"),
+        ("If-Match" = Option<String>, Header, description = "* to set the mask without declaring")),
+    responses((status = 201, description = "Declared"), (status = 204, description = "Mask updated"), ApiError))]
+async fn put_role(Scoped(h): Scoped, Actor(a): Actor, Path((obj, role)): Path<(u64, u64)>, headers: HeaderMap, Json(b): Json<MaskBody>) -> ApiResult<StatusCode> {
+    let only = |name| headers.get(name).is_some_and(|v| v == "*");
+    let (if_match, if_none_match) = (only(IF_MATCH), only(IF_NONE_MATCH));
+    // One write, so a concurrent create or delete can not land between the lookup and the change
+    Ok(h.run(move || write(|| {
+        let exists = get(&OBJECTS.get().unwrap(), &key(obj, role))?.is_some();
+        if if_match || (exists && !if_none_match) { update(a, obj, role, b.mask)?; Ok(StatusCode::NO_CONTENT) }
+        else { create(a, obj, role, b.mask)?; Ok(StatusCode::CREATED) }
+    })).await?)
+}
+#[utoipa::path(delete, path = "/objects/{obj}/roles/{role}", params(("obj" = u64, Path), ("role" = u64, Path)), responses((status = 204), ApiError))]
+async fn delete_role(Scoped(h): Scoped, Actor(a): Actor, Path((obj, role)): Path<(u64, u64)>) -> ApiResult<StatusCode> { h.delete(a, obj, role).await?; Ok(StatusCode::NO_CONTENT) }
+
+#[utoipa::path(get, path = "/subjects/{sub}/grants", params(("sub" = u64, Path), PageQuery), responses((status = 200, body = Vec<(u64, u64)>, description = "(obj, role)", headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_grants(Scoped(h): Scoped, Actor(a): Actor, Path(sub): Path<u64>, Paging(after, limit): Paging) -> ApiResult<Listed<(u64, u64)>> { Ok(Listed(h.list_grants_page(a, sub, after, limit).await?)) }
+#[utoipa::path(get, path = "/objects/{obj}/subjects", params(("obj" = u64, Path), PageQuery), responses((status = 200, body = Vec<(u64, u64)>, description = "(sub, role)", headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_subjects(Scoped(h): Scoped, Actor(a): Actor, Path(obj): Path<u64>, Paging(after, limit): Paging) -> ApiResult<Listed<(u64, u64)>> { Ok(Listed(h.list_subjects_page(a, obj, after, limit).await?)) }
+#[utoipa::path(get, path = "/objects/{obj}/subjects/{sub}/roles", params(("obj" = u64, Path), ("sub" = u64, Path), PageQuery), responses((status = 200, body = Vec<u64>, headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_roles_for(Scoped(h): Scoped, Actor(a): Actor, Path((obj, sub)): Path<(u64, u64)>, Paging(after, limit): Paging) -> ApiResult<Listed<u64>> { Ok(Listed(h.list_roles_for_page(a, sub, obj, after, limit).await?)) }
+#[utoipa::path(put, path = "/objects/{obj}/subjects/{sub}/roles/{role}", params(("obj" = u64, Path), ("sub" = u64, Path), ("role" = u64, Path)), responses((status = 204), ApiError))]
+async fn grant(Scoped(h): Scoped, Actor(a): Actor, Path((obj, sub, role)): Path<(u64, u64, u64)>) -> ApiResult<StatusCode> { h.grant(a, sub, obj, role).await?; Ok(StatusCode::NO_CONTENT) }
+#[utoipa::path(delete, path = "/objects/{obj}/subjects/{sub}/roles/{role}", params(("obj" = u64, Path), ("sub" = u64, Path), ("role" = u64, Path)), responses((status = 204), ApiError))]
+async fn revoke(Scoped(h): Scoped, Actor(a): Actor, Path((obj, sub, role)): Path<(u64, u64, u64)>) -> ApiResult<StatusCode> { h.revoke(a, sub, obj, role).await?; Ok(StatusCode::NO_CONTENT) }
+
+#[utoipa::path(get, path = "/objects/{obj}/subjects/{sub}/inherits", params(("obj" = u64, Path), ("sub" = u64, Path), PageQuery), responses((status = 200, body = Vec<(u64, u64)>, description = "(role, parent)", headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_inherits(Scoped(h): Scoped, Actor(a): Actor, Path((obj, sub)): Path<(u64, u64)>, Paging(after, limit): Paging) -> ApiResult<Listed<(u64, u64)>> { Ok(Listed(h.list_inherits_page(a, sub, obj, after, limit).await?)) }
+#[utoipa::path(put, path = "/objects/{obj}/subjects/{sub}/inherits/{role}", params(("obj" = u64, Path), ("sub" = u64, Path), ("role" = u64, Path)), request_body = InheritBody, responses((status = 204), ApiError))]
+async fn inherit(Scoped(h): Scoped, Actor(a): Actor, Path((obj, sub, role)): Path<(u64, u64, u64)>, Json(b): Json<InheritBody>) -> ApiResult<StatusCode> { h.inherit(a, sub, obj, role, b.parent).await?; Ok(StatusCode::NO_CONTENT) }
+#[utoipa::path(delete, path = "/objects/{obj}/subjects/{sub}/inherits/{role}", params(("obj" = u64, Path), ("sub" = u64, Path), ("role" = u64, Path)), responses((status = 204), ApiError))]
+async fn remove_inherit(Scoped(h): Scoped, Actor(a): Actor, Path((obj, sub, role)): Path<(u64, u64, u64)>) -> ApiResult<StatusCode> { h.remove_inherit(a, sub, obj, role).await?; Ok(StatusCode::NO_CONTENT) }
+#[utoipa::path(get, path = "/objects/{obj}/inherits", params(("obj" = u64, Path), PageQuery), responses((status = 200, body = Vec<(u64, u64, u64)>, description = "(role, parent, sub)", headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_inherits_on_obj(Scoped(h): Scoped, Actor(a): Actor, Path(obj): Path<u64>, Paging(after, limit): Paging) -> ApiResult<Listed<(u64, u64, u64)>> { Ok(Listed(h.list_inherits_on_obj_page(a, obj, after, limit).await?)) }
+#[utoipa::path(get, path = "/objects/{obj}/inherits/{role}", params(("obj" = u64, Path), ("role" = u64, Path), PageQuery), responses((status = 200, body = Vec<(u64, u64)>, description = "(parent, sub)", headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_inherits_on_obj_role(Scoped(h): Scoped, Actor(a): Actor, Path((obj, role)): Path<(u64, u64)>, Paging(after, limit): Paging) -> ApiResult<Listed<(u64, u64)>> { Ok(Listed(h.list_inherits_on_obj_role_page(a, obj, role, after, limit).await?)) }
+#[utoipa::path(get, path = "/subjects/{parent}/inheritors", params(("parent" = u64, Path), PageQuery), responses((status = 200, body = Vec<(u64, u64, u64)>, description = "(obj, role, sub)", headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_inheritors(Scoped(h): Scoped, Actor(a): Actor, Path(parent): Path<u64>, Paging(after, limit): Paging) -> ApiResult<Listed<(u64, u64, u64)>> { Ok(Listed(h.list_inherits_from_parent_page(a, parent, after, limit).await?)) }
+#[utoipa::path(get, path = "/subjects/{parent}/inheritors/{obj}", params(("parent" = u64, Path), ("obj" = u64, Path), PageQuery), responses((status = 200, body = Vec<(u64, u64)>, description = "(role, sub)", headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_inheritors_on_obj(Scoped(h): Scoped, Actor(a): Actor, Path((parent, obj)): Path<(u64, u64)>, Paging(after, limit): Paging) -> ApiResult<Listed<(u64, u64)>> { Ok(Listed(h.list_inherits_from_parent_on_obj_page(a, parent, obj, after, limit).await?)) }
+
+#[utoipa::path(get, path = "/objects/{obj}/parents", params(("obj" = u64, Path), PageQuery), responses((status = 200, body = Vec<(u64, u64)>, description = "(parent, mask)", headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_parents(Scoped(h): Scoped, Actor(a): Actor, Path(obj): Path<u64>, Paging(after, limit): Paging) -> ApiResult<Listed<(u64, u64)>> { Ok(Listed(h.list_parents_page(a, obj, after, limit).await?)) }
+#[utoipa::path(get, path = "/objects/{obj}/children", params(("obj" = u64, Path), PageQuery), responses((status = 200, body = Vec<(u64, u64)>, description = "(child, mask)", headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_children(Scoped(h): Scoped, Actor(a): Actor, Path(obj): Path<u64>, Paging(after, limit): Paging) -> ApiResult<Listed<(u64, u64)>> { Ok(Listed(h.list_children_page(a, obj, after, limit).await?)) }
+#[utoipa::path(put, path = "/objects/{obj}/parents/{parent}", params(("obj" = u64, Path), ("parent" = u64, Path)), request_body = MaskBody, responses((status = 204), ApiError))]
+async fn set_parent(Scoped(h): Scoped, Actor(a): Actor, Path((obj, parent)): Path<(u64, u64)>, Json(b): Json<MaskBody>) -> ApiResult<StatusCode> { h.set_parent(a, obj, parent, b.mask).await?; Ok(StatusCode::NO_CONTENT) }
+#[utoipa::path(delete, path = "/objects/{obj}/parents/{parent}", params(("obj" = u64, Path), ("parent" = u64, Path)), responses((status = 204), ApiError))]
+async fn remove_parent(Scoped(h): Scoped, Actor(a): Actor, Path((obj, parent)): Path<(u64, u64)>) -> ApiResult<StatusCode> { h.remove_parent(a, obj, parent).await?; Ok(StatusCode::NO_CONTENT) }
+
+#[utoipa::path(get, path = "/groups/{group}/members", params(("group" = u64, Path), PageQuery), responses((status = 200, body = Vec<u64>, headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_members(Scoped(h): Scoped, Actor(a): Actor, Path(group): Path<u64>, Paging(after, limit): Paging) -> ApiResult<Listed<u64>> { Ok(Listed(h.list_members_page(a, group, after, limit).await?)) }
+#[utoipa::path(get, path = "/subjects/{sub}/groups", params(("sub" = u64, Path), PageQuery), responses((status = 200, body = Vec<u64>, headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_groups(Scoped(h): Scoped, Actor(a): Actor, Path(sub): Path<u64>, Paging(after, limit): Paging) -> ApiResult<Listed<u64>> { Ok(Listed(h.list_groups_page(a, sub, after, limit).await?)) }
+#[utoipa::path(put, path = "/groups/{group}/members/{member}", params(("group" = u64, Path), ("member" = u64, Path)), responses((status = 204), ApiError))]
+async fn add_member(Scoped(h): Scoped, Actor(a): Actor, Path((group, member)): Path<(u64, u64)>) -> ApiResult<StatusCode> { h.add_member(a, group, member).await?; Ok(StatusCode::NO_CONTENT) }
+#[utoipa::path(delete, path = "/groups/{group}/members/{member}", params(("group" = u64, Path), ("member" = u64, Path)), responses((status = 204), ApiError))]
+async fn remove_member(Scoped(h): Scoped, Actor(a): Actor, Path((group, member)): Path<(u64, u64)>) -> ApiResult<StatusCode> { h.remove_member(a, group, member).await?; Ok(StatusCode::NO_CONTENT) }
+
+#[utoipa::path(get, path = "/scopes/{scope}/bits", params(("scope" = u64, Path), PageQuery), responses((status = 200, body = Vec<Bit>, headers(("x-next-cursor" = String, description = "Cursor for the next page, absent on the last one"))), ApiError))]
+async fn list_bits(Scoped(h): Scoped, Actor(a): Actor, Path(scope): Path<u64>, Paging(after, limit): Paging) -> ApiResult<Listed<Bit>> {
+    let page = h.list_bits_page(a, scope, after, limit).await?;
+    Ok(Listed(Page { items: page.items.into_iter().map(|(bit, name, desc)| Bit { bit, name, desc }).collect(), next: page.next }))
+}
+#[utoipa::path(put, path = "/scopes/{scope}/bits/{bit}", params(("scope" = u64, Path), ("bit" = u8, Path)), request_body = BitBody, responses((status = 204), ApiError))]
+async fn define_bit(Scoped(h): Scoped, Actor(a): Actor, Path((scope, bit)): Path<(u64, u8)>, Json(b): Json<BitBody>) -> ApiResult<StatusCode> { h.define_bit(a, scope, bit, b.name, b.desc).await?; Ok(StatusCode::NO_CONTENT) }
+#[utoipa::path(delete, path = "/scopes/{scope}/bits/{bit}", params(("scope" = u64, Path), ("bit" = u8, Path)), responses((status = 204), ApiError))]
+async fn undefine_bit(Scoped(h): Scoped, Actor(a): Actor, Path((scope, bit)): Path<(u64, u8)>) -> ApiResult<StatusCode> { h.undefine_bit(a, scope, bit).await?; Ok(StatusCode::NO_CONTENT) }
+
+// Works only on an empty store, and returns the only root key. Without x-capbit-tenant it needs
+// no key, for the first run; with it, creating and bootstrapping the tenant needs the default
+// tenant's root, so callers can not mint tenants and root keys at will.
+#[utoipa::path(post, path = "/admin/bootstrap", security((), ("bearer" = [])), responses((status = 201, body = Bootstrapped), ApiError))]
+async fn do_bootstrap(State(h): State<Handle>, local: Option<axum::Extension<Local>>, headers: HeaderMap) -> ApiResult<(StatusCode, Json<Bootstrapped>)> {
+    let name = tenant_header(&headers)?;
+    if name.is_some() {
+        let actor = match (local, headers.get("x-capbit-actor")) {
+            (Some(_), Some(v)) => v.to_str().ok().and_then(|v| v.parse().ok()).ok_or_else(|| Error("Invalid".into()))?,
+            _ => {
+                let bearer = headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer "));
+                let Some(token) = bearer.map(str::to_owned) else { return Err(Error("Unauthorized".into()).into()) };
+                h.run(move || keys::authenticate(&token)).await?
+            }
+        };
+        if actor != _ROOT { return Err(Error("Denied".into()).into()) }
+    }
+    let (system, root, key) = h.run(move || {
+        let boot = || { let (s, r) = bootstrap()?; Ok((s, r, keys::issue(r, r)?.1)) };
+        let Some(t) = name else { return boot() };
+        if !tenants().contains(&t) { create_tenant(&t)?; }
+        tenant(&t, boot)
+    }).await?;
+    Ok((StatusCode::CREATED, Json(Bootstrapped { system, root, key })))
+}
+#[utoipa::path(post, path = "/admin/clear", responses((status = 204), ApiError))]
+async fn do_clear(Scoped(h): Scoped, Actor(a): Actor) -> ApiResult<StatusCode> {
+    if a != _ROOT { return Err(Error("Denied".into()).into()) }
+    h.clear().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Route tables as (method, path, handler); the router is built from them and routes() lists
+// them so the spec can be checked against what is actually served
+macro_rules! routes {
+    ($name:ident { $($method:ident $path:literal => $handler:ident,)* }) => {
+        fn $name() -> Vec<(&'static str, &'static str, MethodRouter<Handle>)> {
+            vec![$((stringify!($method), $path, on(MethodFilter::$method, $handler))),*]
+        }
+    };
+}
+
+routes!(public_routes {
+    GET "/health" => health,
+    GET "/openapi.json" => spec,
+    POST "/check" => check,
+    POST "/check/many" => check_many,
+    GET "/subjects/:sub/objects/:obj/mask" => get_mask,
+    GET "/subjects/:sub/objects/:obj/explain" => explain,
+    GET "/subjects/:sub/grants" => list_grants,
+    GET "/subjects/:sub/groups" => list_groups,
+    GET "/subjects/:parent/inheritors" => list_inheritors,
+    GET "/subjects/:parent/inheritors/:obj" => list_inheritors_on_obj,
+    GET "/objects/:obj/roles" => list_roles,
+    GET "/objects/:obj/roles/:role" => get_role,
+    PUT "/objects/:obj/roles/:role" => put_role,
+    DELETE "/objects/:obj/roles/:role" => delete_role,
+    GET "/objects/:obj/subjects" => list_subjects,
+    GET "/objects/:obj/subjects/:sub/roles" => list_roles_for,
+    PUT "/objects/:obj/subjects/:sub/roles/:role" => grant,
+    DELETE "/objects/:obj/subjects/:sub/roles/:role" => revoke,
+    GET "/objects/:obj/subjects/:sub/inherits" => list_inherits,
+    PUT "/objects/:obj/subjects/:sub/inherits/:role" => inherit,
+    DELETE "/objects/:obj/subjects/:sub/inherits/:role" => remove_inherit,
+    GET "/objects/:obj/inherits" => list_inherits_on_obj,
+    GET "/objects/:obj/inherits/:role" => list_inherits_on_obj_role,
+    GET "/objects/:obj/parents" => list_parents,
+    PUT "/objects/:obj/parents/:parent" => set_parent,
+    DELETE "/objects/:obj/parents/:parent" => remove_parent,
+    GET "/objects/:obj/children" => list_children,
+    GET "/groups/:group/members" => list_members,
+    PUT "/groups/:group/members/:member" => add_member,
+    DELETE "/groups/:group/members/:member" => remove_member,
+    GET "/scopes/:scope/bits" => list_bits,
+    PUT "/scopes/:scope/bits/:bit" => define_bit,
+    DELETE "/scopes/:scope/bits/:bit" => undefine_bit,
+});
+
+// Unauthenticated like /health, for scrapers
+#[cfg(feature = "metrics")]
+routes!(metrics_routes {
+    GET "/metrics" => metrics,
+});
+#[cfg(not(feature = "metrics"))]
+fn metrics_routes() -> Vec<(&'static str, &'static str, MethodRouter<Handle>)> { Vec::new() }
+
+routes!(admin_routes {
+    POST "/admin/bootstrap" => do_bootstrap,
+    POST "/admin/clear" => do_clear,
+});
+
+// All routes; /admin/bootstrap and /admin/clear only when admin is set
+pub fn router(h: Handle, admin: bool) -> Router {
+    let mut r = Router::new();
+    for (_, path, m) in public_routes().into_iter().chain(metrics_routes()).chain(if admin { admin_routes() } else { Vec::new() }) { r = r.route(path, m); }
+    r.with_state(h)
+}
+
+// Serves app on a Unix socket until shutdown resolves, then lets open requests finish
+#[cfg(unix)]
+pub async fn serve_unix(listener: tokio::net::UnixListener, app: Router, shutdown: impl std::future::Future<Output = ()>) -> std::io::Result<()> {
+    use hyper_util::{rt::TokioIo, server::graceful::GracefulShutdown, service::TowerToHyperService};
+    let (app, graceful) = (app.layer(axum::Extension(Local)), GracefulShutdown::new());
+    tokio::pin!(shutdown);
+    loop {
+        let stream = tokio::select! { c = listener.accept() => c?.0, _ = &mut shutdown => break };
+        let conn = hyper::server::conn::http1::Builder::new().serve_connection(TokioIo::new(stream), TowerToHyperService::new(app.clone()));
+        let conn = graceful.watch(conn);
+        tokio::spawn(async move { let _ = conn.await; });
+    }
+    graceful.shutdown().await;
+    Ok(())
+}
+
+// Every (method, path) the router can serve, paths in axum syntax
+pub fn routes() -> Vec<(&'static str, &'static str)> {
+    public_routes().into_iter().chain(metrics_routes()).chain(admin_routes()).map(|(m, p, _)| (m, p)).collect()
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "capbit", description = "Capability-based access control"),
+    paths(health, spec, check, check_many, get_mask, explain, list_grants, list_groups, list_inheritors, list_inheritors_on_obj,
+        list_roles, get_role, put_role, delete_role, list_subjects, list_roles_for, grant, revoke,
+        list_inherits, inherit, remove_inherit, list_inherits_on_obj, list_inherits_on_obj_role,
+        list_parents, set_parent, remove_parent, list_children, list_members, add_member, remove_member,
+        list_bits, define_bit, undefine_bit, do_bootstrap, do_clear),
+    components(schemas(ErrorBody)),
+    modifiers(&Bearer),
+    security(("bearer" = [])),
+)]
+struct ApiDoc;
+
+struct Bearer;
+
+impl utoipa::Modify for Bearer {
+    fn modify(&self, doc: &mut openapi::OpenApi) {
+        let scheme = SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).description(Some("API key from capbit issue-key")).build());
+        doc.components.get_or_insert_with(Default::default).add_security_scheme("bearer", scheme);
+    }
+}
+
+pub fn openapi() -> openapi::OpenApi {
+    #[cfg_attr(not(feature = "metrics"), allow(unused_mut))]
+    let mut doc = ApiDoc::openapi();
+    #[cfg(feature = "metrics")]
+    {
+        #[derive(OpenApi)]
+        #[openapi(paths(metrics))]
+        struct Metrics;
+        doc.merge(Metrics::openapi());
+    }
+    doc
+}
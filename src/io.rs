@@ -0,0 +1,238 @@
+//! Import and export as JSON Lines. The first line is a header, every other line one tuple:
+//!
+//! ```text
+//! {"t":"header","format":"capbit","version":1}
+//! {"t":"object","obj":1,"role":3,"mask":870}
+//! {"t":"grant","sub":2,"obj":1,"role":1}
+//! {"t":"inherit","sub":10,"obj":1,"role":4,"parent":11}
+//! {"t":"parent","obj":100,"parent":1,"mask":4194303}
+//! {"t":"member","group":20,"member":10}
+//! {"t":"bit","scope":1,"bit":22,"name":"read","desc":"Read documents"}
+//! {"t":"name","ns":"entity","name":"alice","id":1099511627776}
+//! ```
+//!
+//! Only forward tuples are written; reverse indexes and id counters are rebuilt on import.
+//! Both directions bypass authorization - they are operator tools like clear and bootstrap.
+//! API keys are not exported, and a replacing import keeps the ones already stored.
+
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename_all = "lowercase")]
+pub enum Record {
+    Header { format: String, version: u32 },
+    Object { obj: u64, role: u64, mask: u64 },
+    Grant { sub: u64, obj: u64, role: u64 },
+    Inherit { sub: u64, obj: u64, role: u64, parent: u64 },
+    Parent { obj: u64, parent: u64, mask: u64 },
+    Member { group: u64, member: u64 },
+    Bit { scope: u64, bit: u8, name: String, desc: String },
+    Name { ns: Namespace, name: String, id: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Namespace { Entity, Role }
+
+impl Namespace {
+    fn byte(self) -> u8 { match self { Namespace::Entity => ENTITY, Namespace::Role => ROLE } }
+}
+
+// Merge keeps existing tuples and lets the file win where both disagree; Replace wipes the
+// store first, API keys excepted; DryRun reports what Merge would do without writing anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode { Merge, Replace, DryRun }
+
+// A record that overwrote (or would overwrite) a different stored value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict { pub line: usize, pub record: Record, pub current: Record }
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport { pub records: usize, pub conflicts: Vec<Conflict> }
+
+// Writes every tuple from one consistent snapshot, concurrent writes continue meanwhile.
+// Returns the number of records written after the header.
+pub fn export(w: impl Write) -> Result<usize> {
+    let at = ks().instant();
+    let mut w = std::io::BufWriter::new(w);
+    let mut n = 0;
+    let mut emit = |r: Record| -> Result<()> {
+        serde_json::to_writer(&mut w, &r).map_err(err)?;
+        w.write_all(b"\n").map_err(err)?;
+        n += 1;
+        Ok(())
+    };
+    emit(Record::Header { format: "capbit".into(), version: FORMAT_VERSION })?;
+    for kv in snap(&OBJECTS, at) { let (k, v) = kv?; emit(Record::Object { obj: u64_at(&k, 0), role: u64_at(&k, 1), mask: val(&v) })?; }
+    for kv in snap(&SUBJECTS, at) { let (k, _) = kv?; emit(Record::Grant { sub: u64_at(&k, 0), obj: u64_at(&k, 1), role: u64_at(&k, 2) })?; }
+    for kv in snap(&INHERITS, at) { let (k, v) = kv?; emit(Record::Inherit { sub: u64_at(&k, 0), obj: u64_at(&k, 1), role: u64_at(&k, 2), parent: val(&v) })?; }
+    for kv in snap(&PARENTS, at) { let (k, v) = kv?; emit(Record::Parent { obj: u64_at(&k, 0), parent: u64_at(&k, 1), mask: val(&v) })?; }
+    for kv in snap(&GROUPS, at) { let (k, _) = kv?; emit(Record::Member { group: u64_at(&k, 1), member: u64_at(&k, 0) })?; }
+    for kv in snap(&BITS, at) {
+        let (k, v) = kv?;
+        let (name, desc) = split_bit(&v);
+        emit(Record::Bit { scope: u64_at(&k, 0), bit: u64_at(&k, 1) as u8, name, desc })?;
+    }
+    for kv in snap(&NAMES, at) {
+        let (k, v) = kv?;
+        let ns = if k[0] == ROLE { Namespace::Role } else { Namespace::Entity };
+        emit(Record::Name { ns, name: String::from_utf8_lossy(&k[1..]).into(), id: val(&v) })?;
+    }
+    w.flush().map_err(err)?;
+    Ok(n - 1)
+}
+
+fn snap(p: &Part, at: fjall::Instant) -> impl Iterator<Item = Result<(fjall::Slice, fjall::Slice)>> {
+    p.get().unwrap().snapshot_at(at).iter().map(|kv| kv.map_err(err))
+}
+
+// Applies the whole file as one commit: a malformed line, an invalid record or a cycle in the
+// resulting parents, groups or inherit edges aborts with nothing written
+pub fn import(r: impl BufRead, mode: ImportMode) -> Result<ImportReport> {
+    let records = parse(r)?;
+    let mut report = ImportReport::default();
+    let mut done = false;
+    let out = write(|| {
+        if mode == ImportMode::Replace {
+            let keys = scan(&META.get().unwrap(), b"apikey:", |k, v| (k.to_vec(), v.to_vec()))?;
+            wipe()?;
+            for (k, v) in keys { set_raw(&META.get().unwrap(), &k, &v); }
+        }
+        let mut next = [(ENTITY, NAMED_BASE), (ROLE, NAMED_BASE)];
+        for (line, rec) in &records {
+            for current in apply(rec).map_err(|e| Error(format!("{}: line {line}", e.0)))? {
+                report.conflicts.push(Conflict { line: *line, record: rec.clone(), current });
+            }
+            if let Record::Name { ns, id, .. } = rec {
+                let n = next.iter_mut().find(|(b, _)| *b == ns.byte()).unwrap();
+                n.1 = n.1.max(id + 1);
+            }
+            report.records += 1;
+        }
+        for (ns, id) in next {
+            let counter = [b"next_id:".as_slice(), &[ns]].concat();
+            if get(&META.get().unwrap(), &counter)?.is_none_or(|c| c < id) { set(&META.get().unwrap(), &counter, id); }
+        }
+        if let Some(ids) = cycle()? { return Err(Error(format!("Cycle: {}", ids.iter().map(u64::to_string).collect::<Vec<_>>().join(" → ")))); }
+        done = true;
+        if mode == ImportMode::DryRun { Err(Error("Dry run".into())) } else { Ok(()) }
+    });
+    match out {
+        Err(_) if done && mode == ImportMode::DryRun => Ok(report),
+        out => out.map(|()| report),
+    }
+}
+
+// The first cycle the staged parents, groups or per-object inherit edges form, as fsck finds them
+fn cycle() -> Result<Option<Vec<u64>>> {
+    let graph = |p: &Part, from: usize, to: usize| -> Result<BTreeMap<u64, Vec<u64>>> {
+        let mut g: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for (a, b) in scan(&p.get().unwrap(), &[], |k, _| (u64_at(k, from), u64_at(k, to)))? { g.entry(a).or_default().push(b); }
+        Ok(g)
+    };
+    let mut by_obj: BTreeMap<u64, BTreeMap<u64, Vec<u64>>> = BTreeMap::new();
+    for (obj, sub, parent) in scan(&INHERITS.get().unwrap(), &[], |k, v| (u64_at(k, 1), u64_at(k, 0), val(v)))? {
+        by_obj.entry(obj).or_default().entry(sub).or_default().push(parent);
+    }
+    let found = [graph(&PARENTS, 0, 1)?, graph(&GROUPS, 0, 1)?].into_iter().chain(by_obj.into_values())
+        .find_map(|g| fsck::cycles(&g).into_iter().next());
+    Ok(found)
+}
+
+fn parse(r: impl BufRead) -> Result<Vec<(usize, Record)>> {
+    let mut out = Vec::new();
+    for (i, line) in r.lines().enumerate() {
+        let line = line.map_err(err)?;
+        if line.trim().is_empty() { continue; }
+        let rec: Record = serde_json::from_str(&line).map_err(|e| Error(format!("Invalid: line {}: {e}", i + 1)))?;
+        match (&rec, out.is_empty()) {
+            (Record::Header { format, version }, true) => {
+                if format != "capbit" { return Err(Error(format!("Invalid: line {}", i + 1))); }
+                if *version != FORMAT_VERSION { return Err(Error(format!("Unsupported version: {version}"))); }
+                out.push((i + 1, rec));
+            }
+            (_, true) | (Record::Header { .. }, false) => return Err(Error(format!("Invalid: line {}", i + 1))),
+            _ => out.push((i + 1, rec)),
+        }
+    }
+    if out.is_empty() { return Err(Error("Invalid: missing header".into())); }
+    out.remove(0);
+    Ok(out)
+}
+
+// Stages one record with its reverse entries, returning the stored values it replaces
+fn apply(rec: &Record) -> Result<Vec<Record>> {
+    let mut replaced = Vec::new();
+    match rec.clone() {
+        Record::Header { .. } => return Err(Error("Invalid".into())),
+        Record::Object { obj, role, mask } => {
+            if let Some(m) = get(&OBJECTS.get().unwrap(), &key(obj, role))?.filter(|&m| m != mask) {
+                replaced.push(Record::Object { obj, role, mask: m });
+            }
+            set(&OBJECTS.get().unwrap(), &key(obj, role), mask);
+        }
+        Record::Grant { sub, obj, role } => {
+            set(&SUBJECTS.get().unwrap(), &key3(sub, obj, role), 1);
+            set(&SUBJECTS_REV.get().unwrap(), &key3(obj, sub, role), 1);
+        }
+        Record::Inherit { sub, obj, role, parent } => {
+            if sub == parent { return Err(Error("Self".into())); }
+            if let Some(p) = get(&INHERITS.get().unwrap(), &key3(sub, obj, role))?.filter(|&p| p != parent) {
+                del(&INHERITS_BY_OBJ.get().unwrap(), &key4(obj, role, p, sub));
+                del(&INHERITS_BY_PARENT.get().unwrap(), &key4(p, obj, role, sub));
+                replaced.push(Record::Inherit { sub, obj, role, parent: p });
+            }
+            set(&INHERITS.get().unwrap(), &key3(sub, obj, role), parent);
+            set(&INHERITS_BY_OBJ.get().unwrap(), &key4(obj, role, parent, sub), 1);
+            set(&INHERITS_BY_PARENT.get().unwrap(), &key4(parent, obj, role, sub), 1);
+        }
+        Record::Parent { obj, parent, mask } => {
+            if obj == parent { return Err(Error("Self".into())); }
+            if let Some(m) = get(&PARENTS.get().unwrap(), &key(obj, parent))?.filter(|&m| m != mask) {
+                replaced.push(Record::Parent { obj, parent, mask: m });
+            }
+            set(&PARENTS.get().unwrap(), &key(obj, parent), mask);
+            set(&CHILDREN.get().unwrap(), &key(parent, obj), mask);
+        }
+        Record::Member { group, member } => {
+            if group == member { return Err(Error("Self".into())); }
+            set(&GROUPS.get().unwrap(), &key(member, group), 1);
+            set(&MEMBERS.get().unwrap(), &key(group, member), 1);
+        }
+        Record::Bit { scope, bit, name, desc } => {
+            if !(22..=63).contains(&bit) || !valid_name(&name) || desc.contains('\0') { return Err(Error("Invalid".into())); }
+            let (bp, np) = (&BITS.get().unwrap(), &BIT_NAMES.get().unwrap());
+            if let Some(b) = get(np, &key_str(scope, &name))?.filter(|&b| b != bit as u64) {
+                let (_, d) = split_bit(&raw(bp, &key(scope, b))?.unwrap_or_default());
+                del(bp, &key(scope, b));
+                replaced.push(Record::Bit { scope, bit: b as u8, name: name.clone(), desc: d });
+            }
+            if let Some(old) = raw(bp, &key(scope, bit as u64))? {
+                let (n, d) = split_bit(&old);
+                if n != name { del(np, &key_str(scope, &n)); }
+                if (&n, &d) != (&name, &desc) { replaced.push(Record::Bit { scope, bit, name: n, desc: d }); }
+            }
+            set_raw(bp, &key(scope, bit as u64), &[name.as_bytes(), b"\0", desc.as_bytes()].concat());
+            set(np, &key_str(scope, &name), bit as u64);
+        }
+        Record::Name { ns, name, id } => {
+            if name.is_empty() || id < NAMED_BASE { return Err(Error("Invalid".into())); }
+            let (nk, ik) = ([&[ns.byte()], name.as_bytes()].concat(), [&[ns.byte()][..], &id.to_be_bytes()].concat());
+            if let Some(x) = get(&NAMES.get().unwrap(), &nk)?.filter(|&x| x != id) {
+                del(&IDS.get().unwrap(), &[&[ns.byte()][..], &x.to_be_bytes()].concat());
+                replaced.push(Record::Name { ns, name: name.clone(), id: x });
+            }
+            if let Some(n) = raw(&IDS.get().unwrap(), &ik)?.map(|v| String::from_utf8_lossy(&v).into_owned()).filter(|n| *n != name) {
+                del(&NAMES.get().unwrap(), &[&[ns.byte()], n.as_bytes()].concat());
+                replaced.push(Record::Name { ns, name: n, id });
+            }
+            set(&NAMES.get().unwrap(), &nk, id);
+            set_raw(&IDS.get().unwrap(), &ik, name.as_bytes());
+        }
+    }
+    Ok(replaced)
+}
@@ -0,0 +1,57 @@
+//! API keys mapped to subjects. A key is `cbk_` followed by 64 hex digits of randomness; only
+//! its SHA-256 is stored, in META under `apikey:`, with the subject id as value. The first
+//! eight bytes of the hash are the key id used to list and revoke it. Subjects may issue and
+//! revoke their own keys; holders of ADMIN_BITS on _SYSTEM may list and revoke anyone's, but only
+//! _ROOT, which holds every right, may issue keys for other subjects.
+
+use super::*;
+use sha2::{Digest, Sha256};
+
+const PREFIX: &[u8] = b"apikey:";
+
+fn hash(token: &str) -> [u8; 32] { Sha256::digest(token.as_bytes()).into() }
+fn key_id(k: &[u8]) -> u64 { u64::from_be_bytes(k[PREFIX.len()..PREFIX.len() + 8].try_into().unwrap()) }
+
+fn may_manage(actor: u64, sub: u64) -> Result<()> {
+    if actor == sub { Ok(()) } else { auth(actor, _SYSTEM, ADMIN_BITS) }
+}
+
+// A key acts with all of sub's rights on every object, which no mask on one object bounds, so
+// only _ROOT may issue one for another subject
+fn may_issue(actor: u64, sub: u64) -> Result<()> {
+    if actor == sub || actor == _ROOT { Ok(()) } else { Err(Error("Denied".into())) }
+}
+
+// Returns the key id and the token; the token cannot be recovered later
+pub fn issue(actor: u64, sub: u64) -> Result<(u64, String)> {
+    let mut secret = [0u8; 32];
+    getrandom::getrandom(&mut secret).map_err(|e| Error(e.to_string()))?;
+    let token = format!("cbk_{}", secret.iter().map(|b| format!("{b:02x}")).collect::<String>());
+    let k = [PREFIX, &hash(&token)].concat();
+    write(|| {
+        may_issue(actor, sub)?;
+        set(&META.get().unwrap(), &k, sub);
+        Ok((key_id(&k), token))
+    })
+}
+
+pub fn revoke(actor: u64, id: u64) -> Result<()> {
+    write(|| {
+        let found = scan(&META.get().unwrap(), &[PREFIX, &id.to_be_bytes()].concat(), |k, v| (k.to_vec(), val(v)))?;
+        let Some((k, sub)) = found.into_iter().next() else { return Err(Error(format!("Unknown: {id:016x}"))) };
+        may_manage(actor, sub)?;
+        del(&META.get().unwrap(), &k);
+        Ok(())
+    })
+}
+
+// Key ids held by sub
+pub fn list(actor: u64, sub: u64) -> Result<Vec<u64>> {
+    may_manage(actor, sub)?;
+    Ok(scan(&META.get().unwrap(), PREFIX, |k, v| (key_id(k), val(v)))?.into_iter().filter(|&(_, s)| s == sub).map(|(id, _)| id).collect())
+}
+
+// The subject a token acts as
+pub fn authenticate(token: &str) -> Result<u64> {
+    get(&META.get().unwrap(), &[PREFIX, &hash(token)].concat())?.ok_or_else(|| Error("Unauthorized".into()))
+}
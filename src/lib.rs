@@ -1,7 +1,39 @@
 //! Capbit - Minimal capability-based access control
 
 use fjall::{Config, Keyspace, PartitionCreateOptions, PartitionHandle};
-use std::{path::Path, sync::OnceLock};
+use std::{cell::RefCell, collections::{BTreeMap, HashMap}, path::Path, sync::{Mutex, OnceLock}};
+
+mod cache;
+pub use cache::{cache_stats, set_cache_capacity, CacheStats};
+mod durability;
+pub use durability::{durability, durable, flush, set_durability, set_group_commit, sync_count, Durability};
+mod backup;
+pub use backup::{backup_to, restore_from, BACKUP_VERSION};
+mod fsck;
+pub use fsck::{verify, Issue, VerifyReport};
+pub mod watch;
+pub mod replication;
+mod tenant;
+pub use tenant::{create_tenant, current_tenant, drop_tenant, export_tenant, tenant, tenants};
+mod page;
+pub use page::{iter_pages, Cursor, Page};
+use page::paged;
+#[cfg(feature = "async")]
+pub mod aio;
+#[cfg(feature = "io")]
+pub mod io;
+#[cfg(feature = "policy")]
+pub mod policy;
+#[cfg(feature = "keys")]
+pub mod keys;
+#[cfg(feature = "server")]
+pub mod http;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 
 #[derive(Debug, Clone)]
 pub struct Error(pub String);
@@ -10,6 +42,23 @@ impl std::error::Error for Error {}
 pub type Result<T> = std::result::Result<T, Error>;
 fn err(e: impl std::error::Error) -> Error { Error(e.to_string()) }
 
+// Broad class of an error, for mapping to protocol status codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind { Denied, Unauthorized, NotFound, Conflict, Invalid, Internal }
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self.0.split(':').next().unwrap_or_default() {
+            "Denied" | "Read only" => ErrorKind::Denied,
+            "Unauthorized" => ErrorKind::Unauthorized,
+            "Unknown" => ErrorKind::NotFound,
+            "Exists" | "Already bootstrapped" | "Not empty" => ErrorKind::Conflict,
+            "Invalid" | "Self" | "Cycle" | "Reserved" | "Corrupt" | "Unsupported version" => ErrorKind::Invalid,
+            _ => ErrorKind::Internal,
+        }
+    }
+}
+
 // Reserved IDs
 pub const _SYSTEM: u64 = 1;
 pub const _ROOT: u64 = 2;
@@ -51,12 +100,51 @@ const _GET_INHERIT: u64 = 1 << 20;
 const _CHECK_INHERIT: u64 = 1 << 21;
 
 static KS: OnceLock<Keyspace> = OnceLock::new();
-static OBJECTS: OnceLock<PartitionHandle> = OnceLock::new();
-static SUBJECTS: OnceLock<PartitionHandle> = OnceLock::new();
-static SUBJECTS_REV: OnceLock<PartitionHandle> = OnceLock::new();
-static INHERITS: OnceLock<PartitionHandle> = OnceLock::new();
-static INHERITS_BY_OBJ: OnceLock<PartitionHandle> = OnceLock::new();
-static INHERITS_BY_PARENT: OnceLock<PartitionHandle> = OnceLock::new();
+// Partition names, indexed by Part; tenants prefix them with "<tenant>#"
+const PARTS: [&str; 16] = ["objects", "subjects", "subjects_rev", "inherits", "inherits_by_obj", "inherits_by_parent", "parents", "children", "groups", "members", "bits", "bit_names", "names", "ids", "meta", "log"];
+// A partition of the calling thread's tenant
+struct Part(usize);
+impl Part { fn get(&self) -> Option<PartitionHandle> { tenant::current().map(|t| t.parts[self.0].clone()) } }
+static OBJECTS: Part = Part(0);
+static SUBJECTS: Part = Part(1);
+static SUBJECTS_REV: Part = Part(2);
+static INHERITS: Part = Part(3);
+static INHERITS_BY_OBJ: Part = Part(4);
+static INHERITS_BY_PARENT: Part = Part(5);
+static PARENTS: Part = Part(6);
+static CHILDREN: Part = Part(7);
+static GROUPS: Part = Part(8);
+static MEMBERS: Part = Part(9);
+static BITS: Part = Part(10);
+static BIT_NAMES: Part = Part(11);
+static NAMES: Part = Part(12);
+static IDS: Part = Part(13);
+static META: Part = Part(14);
+// Replication log, outside parts() so clear() and verify() leave it alone
+static LOG: Part = Part(15);
+// Held by the outermost write from its first read to its commit, so every mutator
+// authorizes and writes against the same state (no check-then-write races)
+static WRITER: Mutex<()> = Mutex::new(());
+
+// Interned string identifiers are allocated from here upwards (below 2^53 so they survive JSON)
+pub const NAMED_BASE: u64 = 1 << 40;
+
+// Name namespaces: subjects and objects share one id space, roles have their own
+const ENTITY: u8 = b'e';
+const ROLE: u8 = b'r';
+const BUILTIN_NAMES: [(u8, &str, u64); 6] = [
+    (ENTITY, "_system", _SYSTEM), (ENTITY, "_root", _ROOT),
+    (ROLE, "owner", _OWNER), (ROLE, "admin", _ADMIN), (ROLE, "editor", _EDITOR), (ROLE, "viewer", _VIEWER),
+];
+
+// Names of the reserved bits, used when no registry entry overrides them
+const BUILTIN_BITS: [&str; 22] = [
+    "create_role", "update_role", "delete_role", "get_role", "check_role",
+    "create_mask", "update_mask", "delete_mask", "get_mask", "check_mask",
+    "create_object", "delete_object", "get_object", "check_object",
+    "grant", "revoke", "get_grant", "check_grant",
+    "set_inherit", "remove_inherit", "get_inherit", "check_inherit",
+];
 
 // Key builders
 #[inline] fn key(a: u64, b: u64) -> [u8; 16] { let mut x = [0u8; 16]; x[..8].copy_from_slice(&a.to_be_bytes()); x[8..].copy_from_slice(&b.to_be_bytes()); x }
@@ -66,28 +154,120 @@ static INHERITS_BY_PARENT: OnceLock<PartitionHandle> = OnceLock::new();
 // Key/value helpers
 #[inline] fn u64_at(k: &[u8], pos: usize) -> u64 { u64::from_be_bytes(k[pos*8..(pos+1)*8].try_into().unwrap()) }
 #[inline] fn val(v: &[u8]) -> u64 { u64::from_be_bytes(v[..8].try_into().unwrap()) }
+#[inline] fn key_str(a: u64, s: &str) -> Vec<u8> { [&a.to_be_bytes()[..], s.as_bytes()].concat() }
 
-// CRUD primitives
-fn ks() -> &'static Keyspace { KS.get().unwrap() }
-fn get(p: &PartitionHandle, k: &[u8]) -> Result<Option<u64>> { Ok(p.get(k).map_err(err)?.map(|v| val(&v))) }
-fn set(p: &PartitionHandle, k: &[u8], v: u64) -> Result<()> { p.insert(k, &v.to_be_bytes()).map_err(err)?; ks().persist(fjall::PersistMode::Buffer).map_err(err) }
-fn del(p: &PartitionHandle, k: &[u8]) -> Result<()> { p.remove(k).map_err(err)?; ks().persist(fjall::PersistMode::Buffer).map_err(err) }
+// Staged writes of the current thread, keyed by (partition name, key). Mutators stage into it,
+// reads see it before the store, and the outermost write() commits it as one batch.
+type Staged = BTreeMap<(String, Vec<u8>), (PartitionHandle, Option<Vec<u8>>)>;
+type Undo = Vec<((String, Vec<u8>), Option<(PartitionHandle, Option<Vec<u8>>)>)>;
+type Written = Vec<(String, Vec<u8>)>;
+thread_local! { static STAGED: RefCell<Option<(Staged, Undo)>> = const { RefCell::new(None) }; }
+
+fn staged<T>(f: impl FnOnce(&Staged) -> T) -> Option<T> { STAGED.with(|s| s.borrow().as_ref().map(|(w, _)| f(w))) }
+fn stage(p: &PartitionHandle, k: &[u8], v: Option<Vec<u8>>) {
+    STAGED.with(|s| {
+        let mut s = s.borrow_mut();
+        let (w, undo) = s.as_mut().expect("stage outside write()");
+        let id = (p.name.to_string(), k.to_vec());
+        let prev = w.insert(id.clone(), (p.clone(), v));
+        undo.push((id, prev));
+    })
+}
+
+// Runs f with staging on; nested calls roll back only their own writes on error
+fn write<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    if let Some(mark) = STAGED.with(|s| s.borrow().as_ref().map(|(_, u)| u.len())) {
+        let out = f();
+        if out.is_err() { rollback(mark); }
+        return out;
+    }
+    replication::writable()?;
+    struct Unstage;
+    impl Drop for Unstage { fn drop(&mut self) { STAGED.with(|s| *s.borrow_mut() = None); } }
+    let lock = WRITER.lock().unwrap_or_else(|e| e.into_inner());
+    STAGED.with(|s| *s.borrow_mut() = Some(Default::default()));
+    let _u = Unstage;
+    let out = f()?;
+    let (w, _) = STAGED.with(|s| s.borrow_mut().take()).unwrap_or_default();
+    let written = apply(w)?;
+    // Waiting on the disk happens outside the lock so group commit can batch writers
+    drop(lock);
+    commit(written)?;
+    Ok(out)
+}
+
+fn rollback(mark: usize) {
+    STAGED.with(|s| {
+        let mut s = s.borrow_mut();
+        let (w, undo) = s.as_mut().unwrap();
+        while undo.len() > mark {
+            let (id, prev) = undo.pop().unwrap();
+            match prev { Some(p) => { w.insert(id, p); } None => { w.remove(&id); } }
+        }
+    })
+}
 
-// Transaction helper for atomic multi-partition writes
-fn transact(f: impl FnOnce(&mut fjall::Batch)) -> Result<()> {
+// Writes the staged keys in one batch and drops the cached masks they change, before the write
+// lock is released and whether or not they are persisted; returns false when there was nothing
+// to write
+fn apply(w: Staged) -> Result<bool> {
+    if w.is_empty() { return Ok(false); }
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("commit", keys = w.len()).entered();
     let mut batch = ks().batch();
-    f(&mut batch);
+    let written: Written = if cache::enabled() { w.keys().cloned().collect() } else { Vec::new() };
+    let events = watch::events(&w);
+    replication::log(&mut batch, &w)?;
+    #[cfg(feature = "metrics")]
+    metrics::written(&w);
+    for ((_, k), (p, v)) in w {
+        match v { Some(v) => batch.insert(&p, k, v), None => batch.remove(&p, k) }
+    }
     batch.commit().map_err(err)?;
-    ks().persist(fjall::PersistMode::Buffer).map_err(err)
+    let invalidated = cache::invalidate(&written);
+    watch::publish(events);
+    invalidated?;
+    Ok(true)
+}
+
+fn commit(written: bool) -> Result<()> {
+    if !written { return Ok(()) }
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("persist", mode = ?durability()).entered();
+    durability::persist()
 }
 
-// Generic scan with extractor
+// CRUD primitives
+fn ks() -> &'static Keyspace { KS.get().unwrap() }
+fn raw(p: &PartitionHandle, k: &[u8]) -> Result<Option<Vec<u8>>> {
+    if let Some(v) = staged(|w| w.get(&(p.name.to_string(), k.to_vec())).map(|(_, v)| v.clone())).flatten() { return Ok(v); }
+    Ok(p.get(k).map_err(err)?.map(|v| v.to_vec()))
+}
+fn get(p: &PartitionHandle, k: &[u8]) -> Result<Option<u64>> { Ok(raw(p, k)?.map(|v| val(&v))) }
+fn set(p: &PartitionHandle, k: &[u8], v: u64) { stage(p, k, Some(v.to_be_bytes().to_vec())) }
+fn set_raw(p: &PartitionHandle, k: &[u8], v: &[u8]) { stage(p, k, Some(v.to_vec())) }
+fn del(p: &PartitionHandle, k: &[u8]) { stage(p, k, None) }
+
+// Generic scan with extractor, merging staged writes under the prefix
 fn scan<T>(p: &PartitionHandle, prefix: &[u8], f: impl Fn(&[u8], &[u8]) -> T) -> Result<Vec<T>> {
+    let name = p.name.to_string();
+    let over = staged(|w| w.range((name.clone(), prefix.to_vec())..).take_while(|((n, k), _)| *n == name && k.starts_with(prefix))
+        .map(|((_, k), (_, v))| (k.clone(), v.clone())).collect::<Vec<_>>()).unwrap_or_default();
     let mut out = Vec::new();
+    if over.is_empty() {
+        for kv in p.prefix(prefix) {
+            let (k, v) = kv.map_err(err)?;
+            out.push(f(&k, &v));
+        }
+        return Ok(out);
+    }
+    let mut merged = BTreeMap::new();
     for kv in p.prefix(prefix) {
         let (k, v) = kv.map_err(err)?;
-        out.push(f(&k, &v));
+        merged.insert(k.to_vec(), Some(v.to_vec()));
     }
+    merged.extend(over);
+    for (k, v) in merged { if let Some(v) = v { out.push(f(&k, &v)); } }
     Ok(out)
 }
 
@@ -95,192 +275,895 @@ pub fn init(path: &str) -> Result<()> {
     if KS.get().is_some() { return Ok(()); }
     std::fs::create_dir_all(path).map_err(err)?;
     let ks = Config::new(Path::new(path)).open().map_err(err)?;
-    let o = PartitionCreateOptions::default();
-    let _ = (OBJECTS.set(ks.open_partition("objects", o.clone()).map_err(err)?),
-             SUBJECTS.set(ks.open_partition("subjects", o.clone()).map_err(err)?),
-             SUBJECTS_REV.set(ks.open_partition("subjects_rev", o.clone()).map_err(err)?),
-             INHERITS.set(ks.open_partition("inherits", o.clone()).map_err(err)?),
-             INHERITS_BY_OBJ.set(ks.open_partition("inherits_by_obj", o.clone()).map_err(err)?),
-             INHERITS_BY_PARENT.set(ks.open_partition("inherits_by_parent", o).map_err(err)?),
-             KS.set(ks));
+    tenant::open_default(&ks)?;
+    let _ = KS.set(ks);
     Ok(())
 }
 
+fn parts() -> [PartitionHandle; 15] {
+    [OBJECTS.get().unwrap(), SUBJECTS.get().unwrap(), SUBJECTS_REV.get().unwrap(),
+     INHERITS.get().unwrap(), INHERITS_BY_OBJ.get().unwrap(), INHERITS_BY_PARENT.get().unwrap(),
+     PARENTS.get().unwrap(), CHILDREN.get().unwrap(), GROUPS.get().unwrap(), MEMBERS.get().unwrap(),
+     BITS.get().unwrap(), BIT_NAMES.get().unwrap(), NAMES.get().unwrap(), IDS.get().unwrap(), META.get().unwrap()]
+}
+
 fn auth(actor: u64, object: u64, req: u64) -> Result<()> {
-    if check(actor, object, req)? { Ok(()) } else { Err(Error("Denied".into())) }
+    let allowed = check(actor, object, req)?;
+    #[cfg(feature = "tracing")]
+    tracing::debug!(actor, object, req, allowed, "auth");
+    if allowed { return Ok(()) }
+    #[cfg(feature = "metrics")]
+    metrics::denied(req);
+    Err(Error("Denied".into()))
+}
+
+// Whether actor may read sub's rights on obj. Another subject's rights reveal its grants and
+// inherit edges, so reading them needs what list_grants and list_inherits need
+pub fn may_inspect(actor: u64, sub: u64, obj: u64) -> Result<()> {
+    if actor == sub { return Ok(()); }
+    auth(actor, _SYSTEM, _GET_GRANT)?;
+    auth(actor, obj, _GET_INHERIT)
 }
 
 // Resolution
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug"), fields(hops, roles)))]
 pub fn get_mask(sub: u64, obj: u64) -> Result<u64> {
-    let (sp, op, ip) = (SUBJECTS.get().unwrap(), OBJECTS.get().unwrap(), INHERITS.get().unwrap());
-    let (mut mask, mut cur) = (0u64, sub);
-    for _ in 0..10 {
-        let mut found = false;
-        for kv in sp.prefix(&key(cur, obj)) {
-            let (k, _) = kv.map_err(err)?;
-            let role = u64_at(&k, 2);
-            mask |= get(op, &key(obj, role))?.unwrap_or(role);
-            found = true;
-            if let Some(p) = get(ip, &key3(cur, obj, role))? {
-                cur = p;
-                break;
+    #[cfg(feature = "metrics")]
+    let start = std::time::Instant::now();
+    let found = cached_mask(sub, obj);
+    #[cfg(feature = "metrics")]
+    metrics::check(start, matches!(found, Ok((_, true))));
+    found.map(|(mask, _)| mask)
+}
+
+// The mask and whether the cache held it
+fn cached_mask(sub: u64, obj: u64) -> Result<(u64, bool)> {
+    if staged(|_| ()).is_some() { return Ok((resolve(sub, obj)?, false)); }
+    let generation = match cache::lookup(sub, obj) { Ok(m) => return Ok((m, true)), Err(g) => g };
+    let mask = resolve(sub, obj)?;
+    if let Some(g) = generation { cache::insert(sub, obj, mask, g); }
+    Ok((mask, false))
+}
+
+fn resolve(sub: u64, obj: u64) -> Result<u64> {
+    let mut r = Resolver::new(sub, false)?;
+    let mask = r.mask(obj, 0)?;
+    #[cfg(feature = "metrics")]
+    metrics::hops(r.hops);
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("hops", r.hops).record("roles", r.visited);
+    Ok(mask)
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", ret, err(level = "debug")))]
+pub fn check(sub: u64, obj: u64, req: u64) -> Result<bool> { Ok(get_mask(sub, obj)? & req == req) }
+
+// Batch resolution for one subject: a single prefix scan per principal, with role masks,
+// inherit edges, parent edges and ancestor masks memoized across the objects
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(objs), fields(objs = objs.len()), err(level = "debug")))]
+pub fn get_masks(sub: u64, objs: &[u64]) -> Result<Vec<u64>> {
+    let mut sorted: Vec<u64> = objs.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+    let mut masks = HashMap::with_capacity(sorted.len());
+    let cached = staged(|_| ()).is_none();
+    let mut misses = Vec::new();
+    for o in sorted {
+        match if cached { cache::lookup(sub, o) } else { Err(None) } {
+            Ok(m) => { masks.insert(o, m); }
+            Err(g) => misses.push((o, g)),
+        }
+    }
+    if !misses.is_empty() {
+        let mut r = Resolver::new(sub, misses.len() > 1)?;
+        for (o, g) in misses {
+            let m = r.mask(o, 0)?;
+            if let Some(g) = g { cache::insert(sub, o, m, g); }
+            masks.insert(o, m);
+        }
+    }
+    Ok(objs.iter().map(|o| masks[o]).collect())
+}
+
+pub fn check_many(sub: u64, reqs: &[(u64, u64)]) -> Result<Vec<bool>> {
+    let objs: Vec<u64> = reqs.iter().map(|r| r.0).collect();
+    Ok(get_masks(sub, &objs)?.into_iter().zip(reqs).map(|(m, (_, req))| m & req == *req).collect())
+}
+
+pub fn filter_authorized(sub: u64, req: u64, objs: impl IntoIterator<Item = u64>) -> Result<Vec<u64>> {
+    let objs: Vec<u64> = objs.into_iter().collect();
+    Ok(get_masks(sub, &objs)?.into_iter().zip(objs).filter(|(m, _)| m & req == req).map(|(_, o)| o).collect())
+}
+
+// Where the bits of a mask come from: each role that contributes, who holds it and on which
+// object, and the parent path it propagated along
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Explanation { pub mask: u64, pub sources: Vec<Source> }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Source {
+    pub principal: u64,  // the subject or one of its groups
+    pub holder: u64,     // who holds the role: the principal or a subject it inherits from
+    pub obj: u64,        // where the role is held: the object or an ancestor
+    pub role: u64,
+    pub mask: u64,       // bits that reach the queried object
+    pub path: Vec<u64>,  // objects from the queried one up to obj
+}
+
+pub fn explain(sub: u64, obj: u64) -> Result<Explanation> {
+    let mut sources = Vec::new();
+    Resolver::new(sub, false)?.sources(obj, u64::MAX, &mut vec![obj], &mut sources)?;
+    Ok(Explanation { mask: sources.iter().fold(0, |m, s| m | s.mask), sources })
+}
+
+struct Resolver {
+    subs: Vec<u64>,
+    grants: HashMap<u64, HashMap<u64, Vec<u64>>>,
+    roles: HashMap<(u64, u64), Vec<u64>>,
+    masks: HashMap<(u64, u64), u64>,
+    inherits: HashMap<(u64, u64, u64), Option<u64>>,
+    parents: HashMap<u64, Vec<(u64, u64)>>,
+    resolved: HashMap<(u64, usize), u64>,
+    // Inherit and parent edges followed, and roles looked at, for metrics and tracing
+    #[cfg_attr(not(any(feature = "metrics", feature = "tracing")), allow(dead_code))]
+    hops: u64,
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    visited: u64,
+}
+
+impl Resolver {
+    // With prefetch, each principal's grants are loaded by one prefix scan up front
+    fn new(sub: u64, prefetch: bool) -> Result<Self> {
+        let subs = principals(sub)?;
+        let mut grants = HashMap::new();
+        if prefetch {
+            for &s in &subs {
+                let mut by_obj: HashMap<u64, Vec<u64>> = HashMap::new();
+                for (o, r) in scan(&SUBJECTS.get().unwrap(), &s.to_be_bytes(), |k, _| (u64_at(k, 1), u64_at(k, 2)))? {
+                    by_obj.entry(o).or_default().push(r);
+                }
+                grants.insert(s, by_obj);
             }
         }
-        if !found { break; }
+        Ok(Self { subs, grants, roles: HashMap::new(), masks: HashMap::new(), inherits: HashMap::new(), parents: HashMap::new(), resolved: HashMap::new(), hops: 0, visited: 0 })
+    }
+
+    // Own mask on obj for the subject and its groups, plus each parent object's mask filtered
+    // by the propagation mask of the edge
+    fn mask(&mut self, obj: u64, depth: usize) -> Result<u64> {
+        if let Some(&m) = self.resolved.get(&(obj, depth)) { return Ok(m); }
+        let mut mask = 0;
+        for i in 0..self.subs.len() { mask |= self.walk(self.subs[i], obj)?; }
+        if depth < 10 {
+            for (parent, prop) in self.parents_of(obj)? {
+                if prop & !mask != 0 { self.hops += 1; mask |= self.mask(parent, depth + 1)? & prop; }
+            }
+        }
+        self.resolved.insert((obj, depth), mask);
+        Ok(mask)
+    }
+
+    fn walk(&mut self, sub: u64, obj: u64) -> Result<u64> {
+        let (mut mask, mut cur) = (0u64, sub);
+        for _ in 0..10 {
+            let mut found = false;
+            for role in self.roles_of(cur, obj)? {
+                self.visited += 1;
+                mask |= self.role_mask(obj, role)?;
+                found = true;
+                if let Some(p) = self.inherit_of(cur, obj, role)? {
+                    cur = p;
+                    self.hops += 1;
+                    break;
+                }
+            }
+            if !found { break; }
+        }
+        Ok(mask)
+    }
+
+    // Same traversal as mask and walk, recording every contribution instead of folding them
+    fn sources(&mut self, obj: u64, filter: u64, path: &mut Vec<u64>, out: &mut Vec<Source>) -> Result<()> {
+        for i in 0..self.subs.len() {
+            let (principal, mut cur) = (self.subs[i], self.subs[i]);
+            // walk revisits the same holder when no inherit edge is taken; that adds nothing
+            for _ in 0..10 {
+                let mut next = None;
+                for role in self.roles_of(cur, obj)? {
+                    let mask = self.role_mask(obj, role)? & filter;
+                    if mask != 0 { out.push(Source { principal, holder: cur, obj, role, mask, path: path.clone() }); }
+                    if let Some(p) = self.inherit_of(cur, obj, role)? {
+                        next = Some(p);
+                        break;
+                    }
+                }
+                match next { Some(p) => cur = p, None => break }
+            }
+        }
+        if path.len() <= 10 {
+            for (parent, prop) in self.parents_of(obj)? {
+                if filter & prop == 0 { continue; }
+                path.push(parent);
+                self.sources(parent, filter & prop, path, out)?;
+                path.pop();
+            }
+        }
+        Ok(())
+    }
+
+    fn roles_of(&mut self, sub: u64, obj: u64) -> Result<Vec<u64>> {
+        if let Some(g) = self.grants.get(&sub) { return Ok(g.get(&obj).cloned().unwrap_or_default()); }
+        if let Some(r) = self.roles.get(&(sub, obj)) { return Ok(r.clone()); }
+        let r = scan(&SUBJECTS.get().unwrap(), &key(sub, obj), |k, _| u64_at(k, 2))?;
+        self.roles.insert((sub, obj), r.clone());
+        Ok(r)
+    }
+
+    fn parents_of(&mut self, obj: u64) -> Result<Vec<(u64, u64)>> {
+        if let Some(p) = self.parents.get(&obj) { return Ok(p.clone()); }
+        let p = scan(&PARENTS.get().unwrap(), &obj.to_be_bytes(), |k, v| (u64_at(k, 1), val(v)))?;
+        self.parents.insert(obj, p.clone());
+        Ok(p)
+    }
+
+    // Undeclared roles count as their own id
+    fn role_mask(&mut self, obj: u64, role: u64) -> Result<u64> {
+        if let Some(&m) = self.masks.get(&(obj, role)) { return Ok(m); }
+        let m = get(&OBJECTS.get().unwrap(), &key(obj, role))?.unwrap_or(role);
+        self.masks.insert((obj, role), m);
+        Ok(m)
+    }
+
+    fn inherit_of(&mut self, sub: u64, obj: u64, role: u64) -> Result<Option<u64>> {
+        if let Some(&p) = self.inherits.get(&(sub, obj, role)) { return Ok(p); }
+        let p = get(&INHERITS.get().unwrap(), &key3(sub, obj, role))?;
+        self.inherits.insert((sub, obj, role), p);
+        Ok(p)
     }
-    Ok(mask)
 }
 
-pub fn check(sub: u64, obj: u64, req: u64) -> Result<bool> { Ok(get_mask(sub, obj)? & req == req) }
+// The subject followed by every group it transitively belongs to
+fn principals(sub: u64) -> Result<Vec<u64>> {
+    let mut out = vec![sub];
+    let mut i = 0;
+    while i < out.len() {
+        for g in scan(&GROUPS.get().unwrap(), &out[i].to_be_bytes(), |k, _| u64_at(k, 1))? {
+            if !out.contains(&g) { out.push(g); }
+        }
+        i += 1;
+    }
+    Ok(out)
+}
+
+// Objects on which group, or a group it belongs to, holds a grant or an inherit edge
+fn conferred(group: u64) -> Result<Vec<u64>> {
+    let mut out = Vec::new();
+    for g in principals(group)? {
+        out.extend(scan(&SUBJECTS.get().unwrap(), &g.to_be_bytes(), |k, _| u64_at(k, 1))?);
+        out.extend(scan(&INHERITS.get().unwrap(), &g.to_be_bytes(), |k, _| u64_at(k, 1))?);
+    }
+    out.sort_unstable();
+    out.dedup();
+    Ok(out)
+}
 
 // OBJECTS table
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
 pub fn create(actor: u64, obj: u64, role: u64, mask: u64) -> Result<()> {
-    auth(actor, obj, _CREATE_ROLE | _CREATE_MASK)?;
-    if get(OBJECTS.get().unwrap(), &key(obj, role))?.is_some() { return Err(Error("Exists".into())); }
-    set(OBJECTS.get().unwrap(), &key(obj, role), mask)
+    write(|| {
+        auth(actor, obj, _CREATE_ROLE | _CREATE_MASK)?;
+        if get(&OBJECTS.get().unwrap(), &key(obj, role))?.is_some() { return Err(Error("Exists".into())); }
+        set(&OBJECTS.get().unwrap(), &key(obj, role), mask);
+        Ok(())
+    })
 }
 
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
 pub fn delete(actor: u64, obj: u64, role: u64) -> Result<()> {
-    auth(actor, obj, _DELETE_ROLE | _DELETE_MASK)?;
-    del(OBJECTS.get().unwrap(), &key(obj, role))
+    write(|| {
+        auth(actor, obj, _DELETE_ROLE | _DELETE_MASK)?;
+        del(&OBJECTS.get().unwrap(), &key(obj, role));
+        Ok(())
+    })
 }
 
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
 pub fn update(actor: u64, obj: u64, role: u64, mask: u64) -> Result<()> {
-    auth(actor, obj, _UPDATE_ROLE | _UPDATE_MASK)?;
-    set(OBJECTS.get().unwrap(), &key(obj, role), mask)
+    write(|| {
+        auth(actor, obj, _UPDATE_ROLE | _UPDATE_MASK)?;
+        set(&OBJECTS.get().unwrap(), &key(obj, role), mask);
+        Ok(())
+    })
 }
 
 pub fn get_object(actor: u64, obj: u64, role: u64) -> Result<Option<u64>> {
     auth(actor, obj, _GET_ROLE | _GET_MASK)?;
-    get(OBJECTS.get().unwrap(), &key(obj, role))
+    get(&OBJECTS.get().unwrap(), &key(obj, role))
 }
 
 pub fn check_object(actor: u64, obj: u64, role: u64) -> Result<bool> {
     auth(actor, obj, _CHECK_ROLE | _CHECK_MASK)?;
-    Ok(get(OBJECTS.get().unwrap(), &key(obj, role))?.is_some())
+    Ok(get(&OBJECTS.get().unwrap(), &key(obj, role))?.is_some())
 }
 
 pub fn list_roles(actor: u64, obj: u64) -> Result<Vec<(u64, u64)>> {
     auth(actor, obj, _GET_ROLE | _GET_MASK)?;
-    scan(OBJECTS.get().unwrap(), &obj.to_be_bytes(), |k, v| (u64_at(k, 1), val(v)))
+    scan(&OBJECTS.get().unwrap(), &obj.to_be_bytes(), |k, v| (u64_at(k, 1), val(v)))
+}
+
+pub fn list_roles_page(actor: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Result<Page<(u64, u64)>> {
+    auth(actor, obj, _GET_ROLE | _GET_MASK)?;
+    paged(&OBJECTS.get().unwrap(), &obj.to_be_bytes(), after, limit, |k, v| (u64_at(k, 1), val(v)))
 }
 
 // SUBJECTS table - (subject, object, role) with reverse index (object, subject, role)
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
 pub fn grant(actor: u64, sub: u64, obj: u64, role: u64) -> Result<()> {
-    auth(actor, obj, _GRANT)?;
-    transact(|b| {
-        b.insert(SUBJECTS.get().unwrap(), &key3(sub, obj, role), &1u64.to_be_bytes());
-        b.insert(SUBJECTS_REV.get().unwrap(), &key3(obj, sub, role), &1u64.to_be_bytes());
+    write(|| {
+        auth(actor, obj, _GRANT)?;
+        set(&SUBJECTS.get().unwrap(), &key3(sub, obj, role), 1);
+        set(&SUBJECTS_REV.get().unwrap(), &key3(obj, sub, role), 1);
+        Ok(())
     })
 }
 
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
 pub fn revoke(actor: u64, sub: u64, obj: u64, role: u64) -> Result<()> {
-    auth(actor, obj, _REVOKE)?;
-    transact(|b| {
-        b.remove(SUBJECTS.get().unwrap(), &key3(sub, obj, role));
-        b.remove(SUBJECTS_REV.get().unwrap(), &key3(obj, sub, role));
+    write(|| {
+        auth(actor, obj, _REVOKE)?;
+        del(&SUBJECTS.get().unwrap(), &key3(sub, obj, role));
+        del(&SUBJECTS_REV.get().unwrap(), &key3(obj, sub, role));
+        Ok(())
     })
 }
 
 pub fn check_subject(sub: u64, obj: u64, role: u64) -> Result<bool> {
-    Ok(get(SUBJECTS.get().unwrap(), &key3(sub, obj, role))?.is_some())
+    Ok(get(&SUBJECTS.get().unwrap(), &key3(sub, obj, role))?.is_some())
 }
 
 pub fn list_roles_for(actor: u64, sub: u64, obj: u64) -> Result<Vec<u64>> {
     auth(actor, obj, _GET_GRANT)?;
-    scan(SUBJECTS.get().unwrap(), &key(sub, obj), |k, _| u64_at(k, 2))
+    scan(&SUBJECTS.get().unwrap(), &key(sub, obj), |k, _| u64_at(k, 2))
+}
+
+pub fn list_roles_for_page(actor: u64, sub: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Result<Page<u64>> {
+    auth(actor, obj, _GET_GRANT)?;
+    paged(&SUBJECTS.get().unwrap(), &key(sub, obj), after, limit, |k, _| u64_at(k, 2))
 }
 
 pub fn list_grants(actor: u64, sub: u64) -> Result<Vec<(u64, u64)>> {
     auth(actor, _SYSTEM, _GET_GRANT)?;
-    scan(SUBJECTS.get().unwrap(), &sub.to_be_bytes(), |k, _| (u64_at(k, 1), u64_at(k, 2)))
+    scan(&SUBJECTS.get().unwrap(), &sub.to_be_bytes(), |k, _| (u64_at(k, 1), u64_at(k, 2)))
+}
+
+pub fn list_grants_page(actor: u64, sub: u64, after: Option<Cursor>, limit: usize) -> Result<Page<(u64, u64)>> {
+    auth(actor, _SYSTEM, _GET_GRANT)?;
+    paged(&SUBJECTS.get().unwrap(), &sub.to_be_bytes(), after, limit, |k, _| (u64_at(k, 1), u64_at(k, 2)))
 }
 
 pub fn list_subjects(actor: u64, obj: u64) -> Result<Vec<(u64, u64)>> {
     auth(actor, obj, _GET_GRANT)?;
-    scan(SUBJECTS_REV.get().unwrap(), &obj.to_be_bytes(), |k, _| (u64_at(k, 1), u64_at(k, 2)))
+    scan(&SUBJECTS_REV.get().unwrap(), &obj.to_be_bytes(), |k, _| (u64_at(k, 1), u64_at(k, 2)))
+}
+
+pub fn list_subjects_page(actor: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Result<Page<(u64, u64)>> {
+    auth(actor, obj, _GET_GRANT)?;
+    paged(&SUBJECTS_REV.get().unwrap(), &obj.to_be_bytes(), after, limit, |k, _| (u64_at(k, 1), u64_at(k, 2)))
 }
 
 // INHERITS table - (subject, object, role) → parent with reverse indexes
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
 pub fn inherit(actor: u64, sub: u64, obj: u64, role: u64, parent: u64) -> Result<()> {
-    auth(actor, obj, _SET_INHERIT)?;
-    if sub == parent { return Err(Error("Self".into())); }
-    transact(|b| {
-        b.insert(INHERITS.get().unwrap(), &key3(sub, obj, role), &parent.to_be_bytes());
-        b.insert(INHERITS_BY_OBJ.get().unwrap(), &key4(obj, role, parent, sub), &1u64.to_be_bytes());
-        b.insert(INHERITS_BY_PARENT.get().unwrap(), &key4(parent, obj, role, sub), &1u64.to_be_bytes());
+    write(|| {
+        auth(actor, obj, _SET_INHERIT)?;
+        if sub == parent { return Err(Error("Self".into())); }
+        if let Some(old) = get(&INHERITS.get().unwrap(), &key3(sub, obj, role))?.filter(|&p| p != parent) {
+            del(&INHERITS_BY_OBJ.get().unwrap(), &key4(obj, role, old, sub));
+            del(&INHERITS_BY_PARENT.get().unwrap(), &key4(old, obj, role, sub));
+        }
+        set(&INHERITS.get().unwrap(), &key3(sub, obj, role), parent);
+        set(&INHERITS_BY_OBJ.get().unwrap(), &key4(obj, role, parent, sub), 1);
+        set(&INHERITS_BY_PARENT.get().unwrap(), &key4(parent, obj, role, sub), 1);
+        Ok(())
     })
 }
 
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
 pub fn remove_inherit(actor: u64, sub: u64, obj: u64, role: u64) -> Result<()> {
-    auth(actor, obj, _REMOVE_INHERIT)?;
-    if let Some(parent) = get(INHERITS.get().unwrap(), &key3(sub, obj, role))? {
-        transact(|b| {
-            b.remove(INHERITS.get().unwrap(), &key3(sub, obj, role));
-            b.remove(INHERITS_BY_OBJ.get().unwrap(), &key4(obj, role, parent, sub));
-            b.remove(INHERITS_BY_PARENT.get().unwrap(), &key4(parent, obj, role, sub));
-        })
-    } else {
+    write(|| {
+        auth(actor, obj, _REMOVE_INHERIT)?;
+        if let Some(parent) = get(&INHERITS.get().unwrap(), &key3(sub, obj, role))? {
+            del(&INHERITS.get().unwrap(), &key3(sub, obj, role));
+            del(&INHERITS_BY_OBJ.get().unwrap(), &key4(obj, role, parent, sub));
+            del(&INHERITS_BY_PARENT.get().unwrap(), &key4(parent, obj, role, sub));
+        }
         Ok(())
-    }
+    })
 }
 
 pub fn get_inherit(actor: u64, sub: u64, obj: u64, role: u64) -> Result<Option<u64>> {
     auth(actor, obj, _GET_INHERIT)?;
-    get(INHERITS.get().unwrap(), &key3(sub, obj, role))
+    get(&INHERITS.get().unwrap(), &key3(sub, obj, role))
 }
 
 pub fn check_inherit(actor: u64, sub: u64, obj: u64, role: u64) -> Result<bool> {
     auth(actor, obj, _CHECK_INHERIT)?;
-    Ok(get(INHERITS.get().unwrap(), &key3(sub, obj, role))?.is_some())
+    Ok(get(&INHERITS.get().unwrap(), &key3(sub, obj, role))?.is_some())
 }
 
 pub fn list_inherits(actor: u64, sub: u64, obj: u64) -> Result<Vec<(u64, u64)>> {
     auth(actor, obj, _GET_INHERIT)?;
-    scan(INHERITS.get().unwrap(), &key(sub, obj), |k, v| (u64_at(k, 2), val(v)))
+    scan(&INHERITS.get().unwrap(), &key(sub, obj), |k, v| (u64_at(k, 2), val(v)))
+}
+
+pub fn list_inherits_page(actor: u64, sub: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Result<Page<(u64, u64)>> {
+    auth(actor, obj, _GET_INHERIT)?;
+    paged(&INHERITS.get().unwrap(), &key(sub, obj), after, limit, |k, v| (u64_at(k, 2), val(v)))
 }
 
 pub fn list_inherits_on_obj(actor: u64, obj: u64) -> Result<Vec<(u64, u64, u64)>> {
     auth(actor, obj, _GET_INHERIT)?;
-    scan(INHERITS_BY_OBJ.get().unwrap(), &obj.to_be_bytes(), |k, _| (u64_at(k, 1), u64_at(k, 2), u64_at(k, 3)))
+    scan(&INHERITS_BY_OBJ.get().unwrap(), &obj.to_be_bytes(), |k, _| (u64_at(k, 1), u64_at(k, 2), u64_at(k, 3)))
+}
+
+pub fn list_inherits_on_obj_page(actor: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Result<Page<(u64, u64, u64)>> {
+    auth(actor, obj, _GET_INHERIT)?;
+    paged(&INHERITS_BY_OBJ.get().unwrap(), &obj.to_be_bytes(), after, limit, |k, _| (u64_at(k, 1), u64_at(k, 2), u64_at(k, 3)))
 }
 
 pub fn list_inherits_on_obj_role(actor: u64, obj: u64, role: u64) -> Result<Vec<(u64, u64)>> {
     auth(actor, obj, _GET_INHERIT)?;
-    scan(INHERITS_BY_OBJ.get().unwrap(), &key(obj, role), |k, _| (u64_at(k, 2), u64_at(k, 3)))
+    scan(&INHERITS_BY_OBJ.get().unwrap(), &key(obj, role), |k, _| (u64_at(k, 2), u64_at(k, 3)))
+}
+
+pub fn list_inherits_on_obj_role_page(actor: u64, obj: u64, role: u64, after: Option<Cursor>, limit: usize) -> Result<Page<(u64, u64)>> {
+    auth(actor, obj, _GET_INHERIT)?;
+    paged(&INHERITS_BY_OBJ.get().unwrap(), &key(obj, role), after, limit, |k, _| (u64_at(k, 2), u64_at(k, 3)))
 }
 
 pub fn list_inherits_from_parent(actor: u64, parent: u64) -> Result<Vec<(u64, u64, u64)>> {
     auth(actor, _SYSTEM, _GET_INHERIT)?;
-    scan(INHERITS_BY_PARENT.get().unwrap(), &parent.to_be_bytes(), |k, _| (u64_at(k, 1), u64_at(k, 2), u64_at(k, 3)))
+    scan(&INHERITS_BY_PARENT.get().unwrap(), &parent.to_be_bytes(), |k, _| (u64_at(k, 1), u64_at(k, 2), u64_at(k, 3)))
+}
+
+pub fn list_inherits_from_parent_page(actor: u64, parent: u64, after: Option<Cursor>, limit: usize) -> Result<Page<(u64, u64, u64)>> {
+    auth(actor, _SYSTEM, _GET_INHERIT)?;
+    paged(&INHERITS_BY_PARENT.get().unwrap(), &parent.to_be_bytes(), after, limit, |k, _| (u64_at(k, 1), u64_at(k, 2), u64_at(k, 3)))
 }
 
 pub fn list_inherits_from_parent_on_obj(actor: u64, parent: u64, obj: u64) -> Result<Vec<(u64, u64)>> {
     auth(actor, obj, _GET_INHERIT)?;
-    scan(INHERITS_BY_PARENT.get().unwrap(), &key(parent, obj), |k, _| (u64_at(k, 2), u64_at(k, 3)))
+    scan(&INHERITS_BY_PARENT.get().unwrap(), &key(parent, obj), |k, _| (u64_at(k, 2), u64_at(k, 3)))
+}
+
+pub fn list_inherits_from_parent_on_obj_page(actor: u64, parent: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Result<Page<(u64, u64)>> {
+    auth(actor, obj, _GET_INHERIT)?;
+    paged(&INHERITS_BY_PARENT.get().unwrap(), &key(parent, obj), after, limit, |k, _| (u64_at(k, 2), u64_at(k, 3)))
+}
+
+// PARENTS table - (object, parent) → propagation mask with reverse index (parent, object)
+// A fresh object (no roles, grants or parents) is claimed by attaching it under a parent
+// the actor may create objects on; anything else needs _SET_INHERIT on the object itself.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
+pub fn set_parent(actor: u64, obj: u64, parent: u64, mask: u64) -> Result<()> {
+    write(|| {
+        // Attaching under parent always needs the right to create there; moving an object also
+        // needs the right to re-link it, and the edge may pass on at most what the actor already
+        // holds on it, so a parent of their own can not raise them
+        if !claimable(obj)? {
+            auth(actor, obj, _SET_INHERIT)?;
+            if mask & !get_mask(actor, obj)? != 0 { return Err(Error("Denied".into())); }
+        }
+        auth(actor, parent, _CREATE_OBJECT)?;
+        if obj == parent { return Err(Error("Self".into())); }
+        if ancestors(parent)?.contains(&obj) { return Err(Error("Cycle".into())); }
+        set(&PARENTS.get().unwrap(), &key(obj, parent), mask);
+        set(&CHILDREN.get().unwrap(), &key(parent, obj), mask);
+        Ok(())
+    })
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
+pub fn remove_parent(actor: u64, obj: u64, parent: u64) -> Result<()> {
+    write(|| {
+        auth(actor, obj, _REMOVE_INHERIT)?;
+        del(&PARENTS.get().unwrap(), &key(obj, parent));
+        del(&CHILDREN.get().unwrap(), &key(parent, obj));
+        Ok(())
+    })
+}
+
+pub fn get_parent(actor: u64, obj: u64, parent: u64) -> Result<Option<u64>> {
+    auth(actor, obj, _GET_INHERIT)?;
+    get(&PARENTS.get().unwrap(), &key(obj, parent))
+}
+
+pub fn list_parents(actor: u64, obj: u64) -> Result<Vec<(u64, u64)>> {
+    auth(actor, obj, _GET_INHERIT)?;
+    scan(&PARENTS.get().unwrap(), &obj.to_be_bytes(), |k, v| (u64_at(k, 1), val(v)))
+}
+
+pub fn list_parents_page(actor: u64, obj: u64, after: Option<Cursor>, limit: usize) -> Result<Page<(u64, u64)>> {
+    auth(actor, obj, _GET_INHERIT)?;
+    paged(&PARENTS.get().unwrap(), &obj.to_be_bytes(), after, limit, |k, v| (u64_at(k, 1), val(v)))
+}
+
+pub fn list_children(actor: u64, parent: u64) -> Result<Vec<(u64, u64)>> {
+    auth(actor, parent, _GET_INHERIT)?;
+    scan(&CHILDREN.get().unwrap(), &parent.to_be_bytes(), |k, v| (u64_at(k, 1), val(v)))
+}
+
+pub fn list_children_page(actor: u64, parent: u64, after: Option<Cursor>, limit: usize) -> Result<Page<(u64, u64)>> {
+    auth(actor, parent, _GET_INHERIT)?;
+    paged(&CHILDREN.get().unwrap(), &parent.to_be_bytes(), after, limit, |k, v| (u64_at(k, 1), val(v)))
+}
+
+// True when no partition mentions obj in any position it is keyed by: as an object, a subject,
+// a parent, a group or a member
+fn is_fresh(obj: u64) -> Result<bool> {
+    let p = obj.to_be_bytes();
+    for part in [OBJECTS.get().unwrap(), SUBJECTS.get().unwrap(), SUBJECTS_REV.get().unwrap(), INHERITS.get().unwrap(),
+                 INHERITS_BY_OBJ.get().unwrap(), INHERITS_BY_PARENT.get().unwrap(), PARENTS.get().unwrap(),
+                 CHILDREN.get().unwrap(), GROUPS.get().unwrap(), MEMBERS.get().unwrap()] {
+        if !scan(&part, &p, |_, _| ())?.is_empty() { return Ok(false); }
+    }
+    Ok(true)
+}
+
+// Interned ids are allocated in sequence, so anyone can predict the next one; being fresh only
+// makes such an id claimable in the write that interns it
+fn claimable(obj: u64) -> Result<bool> {
+    if !is_fresh(obj)? { return Ok(false); }
+    if obj < NAMED_BASE { return Ok(true); }
+    let (p, k) = (IDS.get().unwrap(), [&[ENTITY][..], &obj.to_be_bytes()].concat());
+    Ok(staged(|w| w.contains_key(&(p.name.to_string(), k.clone()))) == Some(true) && !p.contains_key(&k).map_err(err)?)
+}
+
+// All objects reachable upwards from obj (including obj), nearest first
+fn ancestors(obj: u64) -> Result<Vec<u64>> {
+    let mut out = vec![obj];
+    let mut i = 0;
+    while i < out.len() {
+        for p in scan(&PARENTS.get().unwrap(), &out[i].to_be_bytes(), |k, _| u64_at(k, 1))? {
+            if !out.contains(&p) { out.push(p); }
+        }
+        i += 1;
+    }
+    Ok(out)
+}
+
+// GROUPS table - (member, group) → 1 with reverse index MEMBERS (group, member)
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
+pub fn add_member(actor: u64, group: u64, member: u64) -> Result<()> {
+    write(|| {
+        auth(actor, group, _GRANT)?;
+        // A member receives everything the group holds, so the actor must be able to grant it directly
+        for obj in conferred(group)? { auth(actor, obj, _GRANT)?; }
+        if group == member { return Err(Error("Self".into())); }
+        if principals(group)?.contains(&member) { return Err(Error("Cycle".into())); }
+        set(&GROUPS.get().unwrap(), &key(member, group), 1);
+        set(&MEMBERS.get().unwrap(), &key(group, member), 1);
+        Ok(())
+    })
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
+pub fn remove_member(actor: u64, group: u64, member: u64) -> Result<()> {
+    write(|| {
+        auth(actor, group, _REVOKE)?;
+        del(&GROUPS.get().unwrap(), &key(member, group));
+        del(&MEMBERS.get().unwrap(), &key(group, member));
+        Ok(())
+    })
+}
+
+pub fn check_member(group: u64, member: u64) -> Result<bool> {
+    Ok(get(&GROUPS.get().unwrap(), &key(member, group))?.is_some())
+}
+
+pub fn list_members(actor: u64, group: u64) -> Result<Vec<u64>> {
+    auth(actor, group, _GET_GRANT)?;
+    scan(&MEMBERS.get().unwrap(), &group.to_be_bytes(), |k, _| u64_at(k, 1))
+}
+
+pub fn list_members_page(actor: u64, group: u64, after: Option<Cursor>, limit: usize) -> Result<Page<u64>> {
+    auth(actor, group, _GET_GRANT)?;
+    paged(&MEMBERS.get().unwrap(), &group.to_be_bytes(), after, limit, |k, _| u64_at(k, 1))
+}
+
+pub fn list_groups(actor: u64, sub: u64) -> Result<Vec<u64>> {
+    auth(actor, _SYSTEM, _GET_GRANT)?;
+    scan(&GROUPS.get().unwrap(), &sub.to_be_bytes(), |k, _| u64_at(k, 1))
+}
+
+pub fn list_groups_page(actor: u64, sub: u64, after: Option<Cursor>, limit: usize) -> Result<Page<u64>> {
+    auth(actor, _SYSTEM, _GET_GRANT)?;
+    paged(&GROUPS.get().unwrap(), &sub.to_be_bytes(), after, limit, |k, _| u64_at(k, 1))
+}
+
+// BITS table - (scope, bit) → "name\0description" with reverse index BIT_NAMES (scope, name) → bit.
+// A scope is an object or a type object; names resolve through the object's ancestors, then
+// _SYSTEM, then the built-in names of the reserved bits.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
+pub fn define_bit(actor: u64, scope: u64, bit: u8, name: &str, desc: &str) -> Result<()> {
+    write(|| {
+        auth(actor, scope, _CREATE_MASK)?;
+        if bit < 22 { return Err(Error("Reserved".into())); }
+        if bit > 63 || !valid_name(name) || desc.contains('\0') { return Err(Error("Invalid".into())); }
+        let (bp, np) = (&BITS.get().unwrap(), &BIT_NAMES.get().unwrap());
+        if get(np, &key_str(scope, name))?.is_some_and(|b| b != bit as u64) { return Err(Error("Exists".into())); }
+        if let Some(old) = raw(bp, &key(scope, bit as u64))? { del(np, &key_str(scope, bit_name(&old))); }
+        set_raw(bp, &key(scope, bit as u64), &[name.as_bytes(), b"\0", desc.as_bytes()].concat());
+        set(np, &key_str(scope, name), bit as u64);
+        Ok(())
+    })
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
+pub fn undefine_bit(actor: u64, scope: u64, bit: u8) -> Result<()> {
+    write(|| {
+        auth(actor, scope, _DELETE_MASK)?;
+        let bp = &BITS.get().unwrap();
+        if let Some(old) = raw(bp, &key(scope, bit as u64))? {
+            del(bp, &key(scope, bit as u64));
+            del(&BIT_NAMES.get().unwrap(), &key_str(scope, bit_name(&old)));
+        }
+        Ok(())
+    })
+}
+
+pub fn list_bits(actor: u64, scope: u64) -> Result<Vec<(u8, String, String)>> {
+    auth(actor, scope, _GET_MASK)?;
+    scan(&BITS.get().unwrap(), &scope.to_be_bytes(), |k, v| {
+        let (name, desc) = split_bit(v);
+        (u64_at(k, 1) as u8, name, desc)
+    })
+}
+
+pub fn list_bits_page(actor: u64, scope: u64, after: Option<Cursor>, limit: usize) -> Result<Page<(u8, String, String)>> {
+    auth(actor, scope, _GET_MASK)?;
+    paged(&BITS.get().unwrap(), &scope.to_be_bytes(), after, limit, |k, v| {
+        let (name, desc) = split_bit(v);
+        (u64_at(k, 1) as u8, name, desc)
+    })
+}
+
+pub fn mask_from_names(obj: u64, names: &[&str]) -> Result<u64> {
+    let scopes = bit_scopes(obj)?;
+    let mut mask = 0;
+    for &n in names {
+        let mut bit = BUILTIN_BITS.iter().position(|b| *b == n).map(|b| b as u64);
+        for &s in &scopes {
+            if let Some(b) = get(&BIT_NAMES.get().unwrap(), &key_str(s, n))? { bit = Some(b); break; }
+        }
+        mask |= 1 << bit.ok_or_else(|| Error(format!("Unknown: {n}")))?;
+    }
+    Ok(mask)
+}
+
+// Unnamed bits are rendered as hex
+pub fn names_from_mask(obj: u64, mask: u64) -> Result<Vec<String>> {
+    let scopes = bit_scopes(obj)?;
+    let mut out = Vec::new();
+    for bit in (0..64).filter(|b| mask & 1 << b != 0) {
+        let mut name = BUILTIN_BITS.get(bit as usize).map(|n| n.to_string());
+        for &s in &scopes {
+            if let Some(v) = raw(&BITS.get().unwrap(), &key(s, bit))? { name = Some(bit_name(&v).into()); break; }
+        }
+        out.push(name.unwrap_or_else(|| format!("0x{:X}", 1u64 << bit)));
+    }
+    Ok(out)
+}
+
+// Symbolic mask such as "VIEWER_BITS|0x400000|read": aggregate names, hex or decimal
+// numbers and bit names as resolved by mask_from_names, joined with '|'
+pub fn parse_mask(obj: u64, s: &str) -> Result<u64> {
+    let mut mask = 0;
+    for t in s.split('|').map(str::trim) {
+        mask |= match t {
+            "ALL_BITS" => ALL_BITS,
+            "ADMIN_BITS" => ADMIN_BITS,
+            "EDITOR_BITS" => EDITOR_BITS,
+            "VIEWER_BITS" => VIEWER_BITS,
+            _ if t.starts_with("0x") || t.starts_with("0X") => u64::from_str_radix(&t[2..], 16).map_err(|_| Error(format!("Unknown: {t}")))?,
+            _ if t.starts_with(|c: char| c.is_ascii_digit()) => t.parse().map_err(|_| Error(format!("Unknown: {t}")))?,
+            _ => mask_from_names(obj, &[t])?,
+        };
+    }
+    Ok(mask)
+}
+
+fn bit_scopes(obj: u64) -> Result<Vec<u64>> {
+    let mut scopes = ancestors(obj)?;
+    if !scopes.contains(&_SYSTEM) { scopes.push(_SYSTEM); }
+    Ok(scopes)
+}
+
+// Bit names; reserved bit names and mask aliases are refused so they keep their meaning in parse_mask
+fn valid_name(n: &str) -> bool {
+    n.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && n.chars().all(|c| c.is_ascii_alphanumeric() || "_-.:".contains(c))
+        && !BUILTIN_BITS.contains(&n)
+        && !["ALL_BITS", "ADMIN_BITS", "EDITOR_BITS", "VIEWER_BITS"].contains(&n)
+}
+
+fn bit_name(v: &[u8]) -> &str { split_bit_raw(v).0 }
+fn split_bit(v: &[u8]) -> (String, String) { let (n, d) = split_bit_raw(v); (n.into(), d.into()) }
+fn split_bit_raw(v: &[u8]) -> (&str, &str) {
+    let s = std::str::from_utf8(v).unwrap_or_default();
+    s.split_once('\0').unwrap_or((s, ""))
+}
+
+// NAMES table - (namespace, name) → id with reverse index IDS (namespace, id) → name.
+// Ids are allocated from a counter in META, written in the same batch as both mappings.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
+pub fn intern(name: &str) -> Result<u64> { intern_in(ENTITY, name) }
+pub fn lookup(name: &str) -> Result<Option<u64>> { lookup_in(ENTITY, name) }
+pub fn name_of(id: u64) -> Result<Option<String>> { name_in(ENTITY, id) }
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
+pub fn intern_role(name: &str) -> Result<u64> { intern_in(ROLE, name) }
+pub fn lookup_role(name: &str) -> Result<Option<u64>> { lookup_in(ROLE, name) }
+pub fn role_name(id: u64) -> Result<Option<String>> { name_in(ROLE, id) }
+
+fn intern_in(ns: u8, name: &str) -> Result<u64> {
+    if let Some(id) = lookup_in(ns, name)? { return Ok(id); }
+    if name.is_empty() { return Err(Error("Invalid".into())); }
+    write(|| {
+        if let Some(id) = lookup_in(ns, name)? { return Ok(id); }
+        let counter = [b"next_id:".as_slice(), &[ns]].concat();
+        let id = get(&META.get().unwrap(), &counter)?.unwrap_or(NAMED_BASE);
+        set(&NAMES.get().unwrap(), &[&[ns], name.as_bytes()].concat(), id);
+        set_raw(&IDS.get().unwrap(), &[&[ns][..], &id.to_be_bytes()].concat(), name.as_bytes());
+        set(&META.get().unwrap(), &counter, id + 1);
+        Ok(id)
+    })
+}
+
+fn lookup_in(ns: u8, name: &str) -> Result<Option<u64>> {
+    if let Some(&(_, _, id)) = BUILTIN_NAMES.iter().find(|(n, s, _)| *n == ns && *s == name) { return Ok(Some(id)); }
+    get(&NAMES.get().unwrap(), &[&[ns], name.as_bytes()].concat())
+}
+
+fn name_in(ns: u8, id: u64) -> Result<Option<String>> {
+    if let Some(&(_, s, _)) = BUILTIN_NAMES.iter().find(|(n, _, i)| *n == ns && *i == id) { return Ok(Some(s.into())); }
+    Ok(raw(&IDS.get().unwrap(), &[&[ns][..], &id.to_be_bytes()].concat())?.map(|v| String::from_utf8_lossy(&v).into()))
+}
+
+// Ids without a name are rendered in decimal
+fn display_in(ns: u8, id: u64) -> Result<String> { Ok(name_in(ns, id)?.unwrap_or_else(|| id.to_string())) }
+
+// Names are interned in the same write as the grant and only once the actor may grant on obj,
+// so a denied call leaves no names behind
+pub fn grant_named(actor: u64, sub: &str, obj: &str, role: &str) -> Result<()> {
+    write(|| {
+        let Some(o) = lookup(obj)? else { return Err(Error("Denied".into())) };
+        auth(actor, o, _GRANT)?;
+        grant(actor, intern(sub)?, o, intern_role(role)?)
+    })
+}
+
+// Interns obj and claims it in one write when the name is new, as set_parent can not claim an
+// interned id in a later call
+pub fn set_parent_named(actor: u64, obj: &str, parent: &str, mask: u64) -> Result<()> {
+    write(|| {
+        let Some(p) = lookup(parent)? else { return Err(Error("Denied".into())) };
+        set_parent(actor, intern(obj)?, p, mask)
+    })
+}
+
+pub fn revoke_named(actor: u64, sub: &str, obj: &str, role: &str) -> Result<()> {
+    match (lookup(sub)?, lookup(obj)?, lookup_role(role)?) {
+        (Some(s), Some(o), Some(r)) => revoke(actor, s, o, r),
+        _ => Ok(()),
+    }
+}
+
+pub fn check_named(sub: &str, obj: &str, req: u64) -> Result<bool> {
+    match (lookup(sub)?, lookup(obj)?) {
+        (Some(s), Some(o)) => check(s, o, req),
+        _ => Ok(req == 0),
+    }
+}
+
+pub fn list_grants_named(actor: u64, sub: &str) -> Result<Vec<(String, String)>> {
+    let Some(s) = lookup(sub)? else { return Ok(Vec::new()) };
+    list_grants(actor, s)?.into_iter().map(|(o, r)| Ok((display_in(ENTITY, o)?, display_in(ROLE, r)?))).collect()
+}
+
+pub fn list_subjects_named(actor: u64, obj: &str) -> Result<Vec<(String, String)>> {
+    let Some(o) = lookup(obj)? else { return Ok(Vec::new()) };
+    list_subjects(actor, o)?.into_iter().map(|(s, r)| Ok((display_in(ENTITY, s)?, display_in(ROLE, r)?))).collect()
+}
+
+// Runs f as one write: every change made by the calls inside it, names interned on the way
+// included, commits together, or none does if f fails
+pub fn transaction<T>(f: impl FnOnce() -> Result<T>) -> Result<T> { write(f) }
+
+// Batch writes - operations run in order, each authorized against the state left by the
+// earlier ones, and are committed together or not at all
+type BatchOp = Box<dyn FnOnce() -> Result<()> + Send>;
+
+pub struct WriteBatch { actor: u64, ops: Vec<BatchOp>, durability: Option<Durability> }
+
+impl WriteBatch {
+    pub fn new(actor: u64) -> Self { Self { actor, ops: Vec::new(), durability: None } }
+    pub fn durability(mut self, d: Durability) -> Self { self.durability = Some(d); self }
+    fn op(mut self, f: impl FnOnce(u64) -> Result<()> + Send + 'static) -> Self {
+        let actor = self.actor;
+        self.ops.push(Box::new(move || f(actor)));
+        self
+    }
+    pub fn len(&self) -> usize { self.ops.len() }
+    pub fn is_empty(&self) -> bool { self.ops.is_empty() }
+    pub fn create(self, obj: u64, role: u64, mask: u64) -> Self { self.op(move |a| create(a, obj, role, mask)) }
+    pub fn update(self, obj: u64, role: u64, mask: u64) -> Self { self.op(move |a| update(a, obj, role, mask)) }
+    pub fn delete(self, obj: u64, role: u64) -> Self { self.op(move |a| delete(a, obj, role)) }
+    pub fn grant(self, sub: u64, obj: u64, role: u64) -> Self { self.op(move |a| grant(a, sub, obj, role)) }
+    pub fn revoke(self, sub: u64, obj: u64, role: u64) -> Self { self.op(move |a| revoke(a, sub, obj, role)) }
+    pub fn inherit(self, sub: u64, obj: u64, role: u64, parent: u64) -> Self { self.op(move |a| inherit(a, sub, obj, role, parent)) }
+    pub fn remove_inherit(self, sub: u64, obj: u64, role: u64) -> Self { self.op(move |a| remove_inherit(a, sub, obj, role)) }
+    pub fn set_parent(self, obj: u64, parent: u64, mask: u64) -> Self { self.op(move |a| set_parent(a, obj, parent, mask)) }
+    pub fn remove_parent(self, obj: u64, parent: u64) -> Self { self.op(move |a| remove_parent(a, obj, parent)) }
+    pub fn add_member(self, group: u64, member: u64) -> Self { self.op(move |a| add_member(a, group, member)) }
+    pub fn remove_member(self, group: u64, member: u64) -> Self { self.op(move |a| remove_member(a, group, member)) }
+    pub fn define_bit(self, scope: u64, bit: u8, name: &str, desc: &str) -> Self {
+        let (name, desc) = (name.to_string(), desc.to_string());
+        self.op(move |a| define_bit(a, scope, bit, &name, &desc))
+    }
+    pub fn undefine_bit(self, scope: u64, bit: u8) -> Self { self.op(move |a| undefine_bit(a, scope, bit)) }
+
+    // On failure nothing is applied and every operation's result is returned
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", name = "batch", skip_all, fields(actor = self.actor, ops = self.ops.len())))]
+    pub fn commit(self) -> std::result::Result<(), Vec<Result<()>>> {
+        let n = self.ops.len();
+        let mut results = Vec::with_capacity(n);
+        let run = || write(|| {
+            for op in self.ops { results.push(write(op)); }
+            if results.iter().all(Result::is_ok) { Ok(()) } else { Err(Error("Batch".into())) }
+        });
+        let out = match self.durability { Some(d) => durable(d, run), None => run() };
+        match out {
+            Ok(()) => Ok(()),
+            Err(_) if results.iter().any(Result::is_err) => Err(results),
+            Err(e) => Err(vec![Err(e); n]),
+        }
+    }
 }
 
 // Bootstrap
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
 pub fn bootstrap() -> Result<(u64, u64)> {
-    let obj = OBJECTS.get().unwrap();
-    if get(obj, &key(_SYSTEM, _OWNER))?.is_some() {
-        return Err(Error("Already bootstrapped".into()));
-    }
-    transact(|b| {
-        b.insert(obj, &key(_SYSTEM, _OWNER), &ALL_BITS.to_be_bytes());
-        b.insert(obj, &key(_SYSTEM, _ADMIN), &ADMIN_BITS.to_be_bytes());
-        b.insert(obj, &key(_SYSTEM, _EDITOR), &EDITOR_BITS.to_be_bytes());
-        b.insert(obj, &key(_SYSTEM, _VIEWER), &VIEWER_BITS.to_be_bytes());
-        b.insert(SUBJECTS.get().unwrap(), &key3(_ROOT, _SYSTEM, _OWNER), &1u64.to_be_bytes());
-        b.insert(SUBJECTS_REV.get().unwrap(), &key3(_SYSTEM, _ROOT, _OWNER), &1u64.to_be_bytes());
-    })?;
-    Ok((_SYSTEM, _ROOT))
+    write(|| {
+        let obj = &OBJECTS.get().unwrap();
+        if get(obj, &key(_SYSTEM, _OWNER))?.is_some() {
+            return Err(Error("Already bootstrapped".into()));
+        }
+        set(obj, &key(_SYSTEM, _OWNER), ALL_BITS);
+        set(obj, &key(_SYSTEM, _ADMIN), ADMIN_BITS);
+        set(obj, &key(_SYSTEM, _EDITOR), EDITOR_BITS);
+        set(obj, &key(_SYSTEM, _VIEWER), VIEWER_BITS);
+        set(&SUBJECTS.get().unwrap(), &key3(_ROOT, _SYSTEM, _OWNER), 1);
+        set(&SUBJECTS_REV.get().unwrap(), &key3(_SYSTEM, _ROOT, _OWNER), 1);
+        Ok((_SYSTEM, _ROOT))
+    })
 }
 
+// Deletes everything in one commit, so a crash leaves either the old store or an empty one
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err(level = "debug")))]
 pub fn clear() -> Result<()> {
-    for p in [OBJECTS.get().unwrap(), SUBJECTS.get().unwrap(), SUBJECTS_REV.get().unwrap(),
-              INHERITS.get().unwrap(), INHERITS_BY_OBJ.get().unwrap(), INHERITS_BY_PARENT.get().unwrap()] {
-        for kv in p.prefix(&[]) { p.remove(&*kv.map_err(err)?.0).map_err(err)?; }
+    write(wipe)?;
+    cache::reset();
+    Ok(())
+}
+
+// Stages the deletion of every key in every partition
+fn wipe() -> Result<()> {
+    for p in parts() {
+        for k in scan(&p, &[], |k, _| k.to_vec())? { del(&p, &k); }
     }
-    KS.get().unwrap().persist(fjall::PersistMode::Buffer).map_err(err)
+    Ok(())
 }
\ No newline at end of file
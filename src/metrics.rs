@@ -0,0 +1,135 @@
+//! Metrics. Check latency split by cache hit and miss, inherit and parent hops per resolution,
+//! denials, writes per partition and cache lookups are counted in a built-in registry that
+//! `render` prints in the Prometheus text format, together with cache and partition sizes read at
+//! render time. Partitions are labelled by base name, so tenant names never appear and the label
+//! set does not grow with tenants. A Recorder set with `set_recorder` receives the same events,
+//! for embedded users with a registry of their own.
+
+use super::*;
+use std::{fmt::Write, sync::{atomic::{AtomicU64, Ordering::Relaxed}, LazyLock, RwLock}, time::{Duration, Instant}};
+
+// Every method defaults to doing nothing, so a recorder implements only what it forwards
+pub trait Recorder: Send + Sync {
+    // One get_mask or check, answered from the cache or resolved
+    fn check(&self, _elapsed: Duration, _cached: bool) {}
+    // Inherit edges and parent edges followed by one uncached get_mask
+    fn hops(&self, _hops: u64) {}
+    // A mutator or query refused with Denied, named by the permission bits it needed
+    fn denied(&self, _op: &str) {}
+    // Keys written or deleted in one partition by one commit, named without the tenant prefix
+    fn written(&self, _partition: &str, _keys: u64) {}
+    fn cache(&self, _hit: bool) {}
+}
+
+static RECORDER: RwLock<Option<Box<dyn Recorder>>> = RwLock::new(None);
+
+// Replaces the recorder; None stops forwarding. The built-in registry keeps counting either way.
+pub fn set_recorder(r: Option<Box<dyn Recorder>>) { *RECORDER.write().unwrap_or_else(|e| e.into_inner()) = r; }
+
+fn forward(f: impl FnOnce(&dyn Recorder)) {
+    if let Some(r) = RECORDER.read().unwrap_or_else(|e| e.into_inner()).as_deref() { f(r) }
+}
+
+// Cumulative buckets are computed at render time; sum is kept in units of 1/scale
+struct Histogram { bounds: &'static [f64], counts: Vec<AtomicU64>, sum: AtomicU64, scale: f64 }
+
+impl Histogram {
+    fn new(bounds: &'static [f64], scale: f64) -> Self {
+        Self { bounds, counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(), sum: AtomicU64::new(0), scale }
+    }
+    fn observe(&self, v: f64) {
+        let i = self.bounds.iter().position(|&b| v <= b).unwrap_or(self.bounds.len());
+        self.counts[i].fetch_add(1, Relaxed);
+        self.sum.fetch_add((v * self.scale) as u64, Relaxed);
+    }
+    // One series; label is empty or `key="value"`
+    fn render(&self, out: &mut String, name: &str, label: &str) {
+        let (sep, braced) = if label.is_empty() { ("", String::new()) } else { (",", format!("{{{label}}}")) };
+        let mut total = 0;
+        for (i, c) in self.counts.iter().enumerate() {
+            total += c.load(Relaxed);
+            let le = self.bounds.get(i).map_or("+Inf".into(), f64::to_string);
+            let _ = writeln!(out, "{name}_bucket{{{label}{sep}le=\"{le}\"}} {total}");
+        }
+        let _ = writeln!(out, "{name}_sum{braced} {}\n{name}_count{braced} {total}", self.sum.load(Relaxed) as f64 / self.scale);
+    }
+}
+
+struct Registry { hit: Histogram, miss: Histogram, hops: Histogram, denied: Mutex<BTreeMap<String, u64>>, written: Mutex<BTreeMap<String, u64>> }
+
+const CHECK_BOUNDS: &[f64] = &[0.000_005, 0.000_01, 0.000_025, 0.000_05, 0.000_1, 0.000_25, 0.000_5, 0.001, 0.005, 0.01];
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(|| Registry {
+    hit: Histogram::new(CHECK_BOUNDS, 1e9),
+    miss: Histogram::new(CHECK_BOUNDS, 1e9),
+    hops: Histogram::new(&[0.0, 1.0, 2.0, 3.0, 5.0, 10.0, 20.0], 1.0),
+    denied: Mutex::new(BTreeMap::new()),
+    written: Mutex::new(BTreeMap::new()),
+});
+
+fn bump(m: &Mutex<BTreeMap<String, u64>>, label: &str, n: u64) {
+    *m.lock().unwrap_or_else(|e| e.into_inner()).entry(label.to_string()).or_default() += n;
+}
+
+// Hooks called by the library
+
+pub(crate) fn check(start: Instant, cached: bool) {
+    let elapsed = start.elapsed();
+    if cached { &REGISTRY.hit } else { &REGISTRY.miss }.observe(elapsed.as_secs_f64());
+    forward(|r| r.check(elapsed, cached));
+}
+
+pub(crate) fn hops(hops: u64) {
+    REGISTRY.hops.observe(hops as f64);
+    forward(|r| r.hops(hops));
+}
+
+pub(crate) fn denied(req: u64) {
+    let op = BUILTIN_BITS.iter().enumerate().filter(|(i, _)| req >> i & 1 == 1).map(|(_, n)| *n).collect::<Vec<_>>().join("|");
+    bump(&REGISTRY.denied, &op, 1);
+    forward(|r| r.denied(&op));
+}
+
+pub(crate) fn written(w: &Staged) {
+    let mut per: BTreeMap<&str, u64> = BTreeMap::new();
+    for (p, _) in w.keys() { *per.entry(tenant::base(p)).or_default() += 1; }
+    for (p, n) in per {
+        bump(&REGISTRY.written, p, n);
+        forward(|r| r.written(p, n));
+    }
+}
+
+pub(crate) fn cache(hit: bool) { forward(|r| r.cache(hit)); }
+
+// Approximate key count and disk bytes of every partition
+pub fn partition_sizes() -> Vec<(String, u64, u64)> {
+    if KS.get().is_none() { return Vec::new(); }
+    parts().iter().map(|p| (tenant::base(&p.name).to_string(), p.approximate_len() as u64, p.disk_space())).collect()
+}
+
+// The built-in registry in the Prometheus text exposition format
+pub fn render() -> String {
+    let mut out = String::new();
+    let help = |out: &mut String, name: &str, help: &str, kind: &str| { let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}"); };
+    help(&mut out, "capbit_check_seconds", "Time to answer get_mask or check, by whether the cache answered", "histogram");
+    REGISTRY.hit.render(&mut out, "capbit_check_seconds", "cache=\"hit\"");
+    REGISTRY.miss.render(&mut out, "capbit_check_seconds", "cache=\"miss\"");
+    help(&mut out, "capbit_resolve_hops", "Inherit and parent edges followed per uncached get_mask", "histogram");
+    REGISTRY.hops.render(&mut out, "capbit_resolve_hops", "");
+    let counters = |out: &mut String, name: &str, text: &str, label: &str, m: &Mutex<BTreeMap<String, u64>>| {
+        help(out, name, text, "counter");
+        for (k, v) in m.lock().unwrap_or_else(|e| e.into_inner()).iter() { let _ = writeln!(out, "{name}{{{label}=\"{k}\"}} {v}"); }
+    };
+    counters(&mut out, "capbit_denied_total", "Operations refused, by the permission bits they needed", "op", &REGISTRY.denied);
+    counters(&mut out, "capbit_writes_total", "Keys written or deleted, by partition", "partition", &REGISTRY.written);
+    let c = cache_stats();
+    let _ = writeln!(out, "# HELP capbit_cache_hits_total Resolution cache hits\n# TYPE capbit_cache_hits_total counter\ncapbit_cache_hits_total {}", c.hits);
+    let _ = writeln!(out, "# HELP capbit_cache_misses_total Resolution cache misses\n# TYPE capbit_cache_misses_total counter\ncapbit_cache_misses_total {}", c.misses);
+    let _ = writeln!(out, "# HELP capbit_cache_entries Masks held by the resolution cache\n# TYPE capbit_cache_entries gauge\ncapbit_cache_entries {}", c.entries);
+    let sizes = partition_sizes();
+    let _ = writeln!(out, "# HELP capbit_partition_keys Approximate keys per partition\n# TYPE capbit_partition_keys gauge");
+    for (p, n, _) in &sizes { let _ = writeln!(out, "capbit_partition_keys{{partition=\"{p}\"}} {n}"); }
+    let _ = writeln!(out, "# HELP capbit_partition_disk_bytes Disk space per partition\n# TYPE capbit_partition_disk_bytes gauge");
+    for (p, _, b) in &sizes { let _ = writeln!(out, "capbit_partition_disk_bytes{{partition=\"{p}\"}} {b}"); }
+    out
+}
@@ -0,0 +1,65 @@
+//! Cursor pagination for the list queries. A page holds at most `limit` items in key order and,
+//! when more follow, a cursor naming the last key returned; passing it back as `after` resumes
+//! right behind it, so items that stay in place are never repeated or skipped across pages.
+
+use super::*;
+use std::{collections::VecDeque, ops::Bound};
+
+// Position behind the last item of a page; opaque, printed and parsed as hex
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor(Vec<u8>);
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for b in &self.0 { write!(f, "{b:02x}")?; }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        if !s.len().is_multiple_of(2) { return Err(Error("Invalid".into())); }
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2).unwrap_or_default(), 16).map_err(|_| Error("Invalid".into()))).collect::<Result<_>>().map(Cursor)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page<T> { pub items: Vec<T>, pub next: Option<Cursor> }
+
+// Up to limit entries under prefix after the cursor, merging staged writes like scan()
+pub(crate) fn paged<T>(p: &PartitionHandle, prefix: &[u8], after: Option<Cursor>, limit: usize, f: impl Fn(&[u8], &[u8]) -> T) -> Result<Page<T>> {
+    if after.as_ref().is_some_and(|c| !c.0.starts_with(prefix)) { return Err(Error("Invalid".into())); }
+    let start = after.map_or(Bound::Included(prefix.to_vec()), |c| Bound::Excluded(c.0));
+    let mut rows = Vec::new();
+    let name = p.name.to_string();
+    let staged = staged(|w| w.range((name.clone(), prefix.to_vec())..).take_while(|((n, k), _)| *n == name && k.starts_with(prefix)).next().is_some()).unwrap_or(false);
+    if staged {
+        for (k, v) in scan(p, prefix, |k, v| (k.to_vec(), v.to_vec()))? {
+            if rows.len() > limit { break; }
+            if match &start { Bound::Excluded(c) => k > *c, _ => true } { rows.push((k, v)); }
+        }
+    } else {
+        for kv in p.range((start, Bound::Unbounded)) {
+            let (k, v) = kv.map_err(err)?;
+            if !k.starts_with(prefix) || rows.len() > limit { break; }
+            rows.push((k.to_vec(), v.to_vec()));
+        }
+    }
+    let next = (rows.len() > limit).then(|| { rows.truncate(limit); Cursor(rows.last().map(|(k, _)| k.clone()).unwrap_or_default()) });
+    Ok(Page { items: rows.iter().map(|(k, v)| f(k, v)).collect(), next })
+}
+
+// Walks a paged query lazily, fetching limit items at a time, e.g.
+// iter_pages(500, |after, limit| list_subjects_page(actor, obj, after, limit))
+pub fn iter_pages<T>(limit: usize, mut f: impl FnMut(Option<Cursor>, usize) -> Result<Page<T>>) -> impl Iterator<Item = Result<T>> {
+    let (mut buf, mut next, mut done) = (VecDeque::new(), None, false);
+    std::iter::from_fn(move || loop {
+        if let Some(t) = buf.pop_front() { return Some(Ok(t)); }
+        if done { return None; }
+        match f(next.take(), limit.max(1)) {
+            Ok(page) => { buf.extend(page.items); done = page.next.is_none(); next = page.next; }
+            Err(e) => { done = true; return Some(Err(e)); }
+        }
+    })
+}
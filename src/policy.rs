@@ -0,0 +1,249 @@
+//! Declarative policy files. A TOML document lists objects with their roles and parent edges,
+//! grants and inherit edges:
+//!
+//! ```toml
+//! [objects.project]
+//! parents = { _system = "ALL_BITS" }
+//! roles = { editor = "EDITOR_BITS|0x400000", viewer = "VIEWER_BITS" }
+//!
+//! [[grants]]
+//! sub = "team"
+//! obj = "project"
+//! role = "editor"
+//!
+//! [[inherits]]
+//! sub = "bob"
+//! obj = "project"
+//! role = "viewer"
+//! parent = "alice"
+//! ```
+//!
+//! Identifiers are names (interned when applied) or decimal ids; masks are numbers or symbolic
+//! expressions as accepted by parse_mask. `plan` diffs the file against the store without
+//! writing; `apply` commits a plan as one WriteBatch. With prune, roles, parent edges, grants
+//! and inherit edges on the file's objects that the file does not list are removed; objects
+//! the file does not declare are never pruned, so dropping an object from the file leaves its
+//! grants in place. To clear one, apply it once with prune as an empty `[objects.name]` table.
+//! Each change is authorized against the state the earlier ones leave, so a fresh object's first
+//! parent edge has to pass the actor the rights to link it under its other parents.
+
+use super::*;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default)] pub objects: BTreeMap<String, ObjectSpec>,
+    #[serde(default)] pub grants: Vec<GrantSpec>,
+    #[serde(default)] pub inherits: Vec<InheritSpec>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectSpec {
+    #[serde(default)] pub parents: BTreeMap<String, MaskSpec>,
+    #[serde(default)] pub roles: BTreeMap<String, MaskSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum MaskSpec { Bits(u64), Expr(String) }
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrantSpec { pub sub: String, pub obj: String, pub role: String }
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InheritSpec { pub sub: String, pub obj: String, pub role: String, pub parent: String }
+
+impl Policy {
+    pub fn parse(s: &str) -> Result<Self> { toml::from_str(s).map_err(|e| Error(format!("Invalid: {}", e.message()))) }
+}
+
+// An identifier from the file: an id, or a name that may not be interned yet
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Ref { Id(u64), Name(String) }
+
+impl Ref {
+    fn parse(s: &str) -> Self { s.parse().map(Ref::Id).unwrap_or_else(|_| Ref::Name(s.into())) }
+    fn find(&self, ns: u8) -> Result<Option<u64>> { match self { Ref::Id(i) => Ok(Some(*i)), Ref::Name(n) => lookup_in(ns, n) } }
+    fn resolve(&self, ns: u8) -> Result<u64> { match self { Ref::Id(i) => Ok(*i), Ref::Name(n) => intern_in(ns, n) } }
+}
+
+impl std::fmt::Display for Ref {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self { Ref::Id(i) => write!(f, "{i}"), Ref::Name(n) => write!(f, "{n}") }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    SetParent { obj: Ref, parent: Ref, mask: u64, old: Option<u64> },
+    Create { obj: Ref, role: Ref, mask: u64 },
+    Update { obj: Ref, role: Ref, mask: u64, old: u64 },
+    Grant { sub: Ref, obj: Ref, role: Ref },
+    Inherit { sub: Ref, obj: Ref, role: Ref, parent: Ref, old: Option<Ref> },
+    RemoveInherit { sub: Ref, obj: Ref, role: Ref, parent: Ref },
+    Revoke { sub: Ref, obj: Ref, role: Ref },
+    Delete { obj: Ref, role: Ref, mask: u64 },
+    RemoveParent { obj: Ref, parent: Ref, mask: u64 },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::SetParent { obj, parent, mask, old: None } => write!(f, "+ parent {obj} → {parent} 0x{mask:X}"),
+            Change::SetParent { obj, parent, mask, old: Some(o) } => write!(f, "~ parent {obj} → {parent} 0x{o:X} → 0x{mask:X}"),
+            Change::Create { obj, role, mask } => write!(f, "+ role {role} on {obj} 0x{mask:X}"),
+            Change::Update { obj, role, mask, old } => write!(f, "~ role {role} on {obj} 0x{old:X} → 0x{mask:X}"),
+            Change::Grant { sub, obj, role } => write!(f, "+ grant {sub} {role} on {obj}"),
+            Change::Inherit { sub, obj, role, parent, old: None } => write!(f, "+ inherit {sub} {role} on {obj} from {parent}"),
+            Change::Inherit { sub, obj, role, parent, old: Some(o) } => write!(f, "~ inherit {sub} {role} on {obj} from {o} → {parent}"),
+            Change::RemoveInherit { sub, obj, role, parent } => write!(f, "- inherit {sub} {role} on {obj} from {parent}"),
+            Change::Revoke { sub, obj, role } => write!(f, "- grant {sub} {role} on {obj}"),
+            Change::Delete { obj, role, mask } => write!(f, "- role {role} on {obj} 0x{mask:X}"),
+            Change::RemoveParent { obj, parent, mask } => write!(f, "- parent {obj} → {parent} 0x{mask:X}"),
+        }
+    }
+}
+
+// Changes in the order they are applied: parent edges first so fresh objects are claimed
+// before their roles are declared, removals last
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Plan { pub changes: Vec<Change> }
+
+impl Plan {
+    pub fn is_empty(&self) -> bool { self.changes.is_empty() }
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for c in &self.changes { writeln!(f, "{c}")?; }
+        Ok(())
+    }
+}
+
+// Diffs the policy against the store; nothing is written, names are not interned
+pub fn plan(policy: &Policy, prune: bool) -> Result<Plan> {
+    let mut changes = Vec::new();
+    let id = |r: &Ref| r.find(ENTITY);
+    // Bit names in a mask resolve against the object, or its first parent while it is new
+    let mask = |m: &MaskSpec, scope: Option<u64>| match m {
+        MaskSpec::Bits(b) => Ok(*b),
+        MaskSpec::Expr(e) => parse_mask(scope.unwrap_or(_SYSTEM), e),
+    };
+    let mut declared = BTreeSet::new();
+    let (mut parents, mut roles, mut deletes, mut unparents) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (name, spec) in &policy.objects {
+        let obj = Ref::parse(name);
+        let o = id(&obj)?;
+        let first_parent = match spec.parents.keys().next() { Some(p) => id(&Ref::parse(p))?, None => None };
+        let scope = o.or(first_parent);
+        if let Some(o) = o { declared.insert(o); }
+        let mut want_parents = BTreeSet::new();
+        for (p, m) in &spec.parents {
+            let parent = Ref::parse(p);
+            let m = mask(m, scope)?;
+            let old = match (o, id(&parent)?) { (Some(o), Some(p)) => get(&PARENTS.get().unwrap(), &key(o, p))?, _ => None };
+            if let Some(p) = id(&parent)? { want_parents.insert(p); }
+            if old != Some(m) { parents.push(Change::SetParent { obj: obj.clone(), parent, mask: m, old }); }
+        }
+        let mut want_roles = BTreeSet::new();
+        for (r, m) in &spec.roles {
+            let role = Ref::parse(r);
+            let m = mask(m, scope)?;
+            if let Some(r) = role.find(ROLE)? { want_roles.insert(r); }
+            let old = match (o, role.find(ROLE)?) { (Some(o), Some(r)) => get(&OBJECTS.get().unwrap(), &key(o, r))?, _ => None };
+            match old {
+                None => roles.push(Change::Create { obj: obj.clone(), role, mask: m }),
+                Some(old) if old != m => roles.push(Change::Update { obj: obj.clone(), role, mask: m, old }),
+                _ => {}
+            }
+        }
+        if let (true, Some(o)) = (prune, o) {
+            for (p, m) in scan(&PARENTS.get().unwrap(), &o.to_be_bytes(), |k, v| (u64_at(k, 1), val(v)))? {
+                if !want_parents.contains(&p) { unparents.push(Change::RemoveParent { obj: obj.clone(), parent: entity_ref(p)?, mask: m }); }
+            }
+            for (r, m) in scan(&OBJECTS.get().unwrap(), &o.to_be_bytes(), |k, v| (u64_at(k, 1), val(v)))? {
+                if !want_roles.contains(&r) { deletes.push(Change::Delete { obj: obj.clone(), role: role_ref(r)?, mask: m }); }
+            }
+        }
+    }
+
+    // A fresh object must be claimed before children are attached under it
+    let mut ordered = Vec::with_capacity(parents.len());
+    while !parents.is_empty() {
+        let pending = |r: &Ref, ps: &[Change]| ps.iter().any(|c| matches!(c, Change::SetParent { obj, .. } if obj == r));
+        let at = (0..parents.len()).find(|&i| match &parents[i] {
+            Change::SetParent { obj, parent, .. } => parent == obj || !pending(parent, &parents),
+            _ => true,
+        }).unwrap_or(0);
+        ordered.push(parents.remove(at));
+    }
+    let parents = ordered;
+
+    let mut grants = Vec::new();
+    let mut want_grants = BTreeSet::new();
+    for g in &policy.grants {
+        let (sub, obj, role) = (Ref::parse(&g.sub), Ref::parse(&g.obj), Ref::parse(&g.role));
+        let held = match (id(&sub)?, id(&obj)?, role.find(ROLE)?) {
+            (Some(s), Some(o), Some(r)) => { want_grants.insert((s, o, r)); get(&SUBJECTS.get().unwrap(), &key3(s, o, r))?.is_some() }
+            _ => false,
+        };
+        if !held { grants.push(Change::Grant { sub, obj, role }); }
+    }
+    let mut inherits = Vec::new();
+    let mut want_inherits = BTreeSet::new();
+    for i in &policy.inherits {
+        let (sub, obj, role, parent) = (Ref::parse(&i.sub), Ref::parse(&i.obj), Ref::parse(&i.role), Ref::parse(&i.parent));
+        let old = match (id(&sub)?, id(&obj)?, role.find(ROLE)?) {
+            (Some(s), Some(o), Some(r)) => { want_inherits.insert((s, o, r)); get(&INHERITS.get().unwrap(), &key3(s, o, r))? }
+            _ => None,
+        };
+        if old.is_some() && old == id(&parent)? { continue; }
+        inherits.push(Change::Inherit { sub, obj, role, parent, old: old.map(entity_ref).transpose()? });
+    }
+
+    let mut revokes = Vec::new();
+    if prune {
+        for &o in &declared {
+            for (s, r) in scan(&SUBJECTS_REV.get().unwrap(), &o.to_be_bytes(), |k, _| (u64_at(k, 1), u64_at(k, 2)))? {
+                if !want_grants.contains(&(s, o, r)) { revokes.push(Change::Revoke { sub: entity_ref(s)?, obj: entity_ref(o)?, role: role_ref(r)? }); }
+            }
+            for (r, p, s) in scan(&INHERITS_BY_OBJ.get().unwrap(), &o.to_be_bytes(), |k, _| (u64_at(k, 1), u64_at(k, 2), u64_at(k, 3)))? {
+                if !want_inherits.contains(&(s, o, r)) {
+                    inherits.push(Change::RemoveInherit { sub: entity_ref(s)?, obj: entity_ref(o)?, role: role_ref(r)?, parent: entity_ref(p)? });
+                }
+            }
+        }
+    }
+    // Removed inherit edges go after additions, revokes after both, then roles and parent edges
+    for c in [parents, roles, grants, inherits, revokes, deletes, unparents] { changes.extend(c); }
+    Ok(Plan { changes })
+}
+
+// Commits every change as one WriteBatch for actor, each authorized as the equivalent call
+pub fn apply(actor: u64, plan: &Plan) -> std::result::Result<(), Vec<Result<()>>> {
+    let mut batch = WriteBatch::new(actor);
+    for c in plan.changes.clone() {
+        let (e, r) = (|x: &Ref| x.resolve(ENTITY), |x: &Ref| x.resolve(ROLE));
+        batch = batch.op(move |a| match &c {
+            Change::SetParent { obj, parent, mask, .. } => set_parent(a, e(obj)?, e(parent)?, *mask),
+            Change::Create { obj, role, mask } => create(a, e(obj)?, r(role)?, *mask),
+            Change::Update { obj, role, mask, .. } => update(a, e(obj)?, r(role)?, *mask),
+            Change::Grant { sub, obj, role } => grant(a, e(sub)?, e(obj)?, r(role)?),
+            Change::Inherit { sub, obj, role, parent, .. } => inherit(a, e(sub)?, e(obj)?, r(role)?, e(parent)?),
+            Change::RemoveInherit { sub, obj, role, .. } => remove_inherit(a, e(sub)?, e(obj)?, r(role)?),
+            Change::Revoke { sub, obj, role } => revoke(a, e(sub)?, e(obj)?, r(role)?),
+            Change::Delete { obj, role, .. } => delete(a, e(obj)?, r(role)?),
+            Change::RemoveParent { obj, parent, .. } => remove_parent(a, e(obj)?, e(parent)?),
+        });
+    }
+    batch.commit()
+}
+
+// Existing ids are shown by name where one is interned
+fn entity_ref(id: u64) -> Result<Ref> { Ok(name_in(ENTITY, id)?.map(Ref::Name).unwrap_or(Ref::Id(id))) }
+fn role_ref(id: u64) -> Result<Ref> { Ok(name_in(ROLE, id)?.map(Ref::Name).unwrap_or(Ref::Id(id))) }
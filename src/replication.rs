@@ -0,0 +1,335 @@
+//! Replication by log shipping. A leader appends every commit to its log as one entry, numbered
+//! from 1 in commit order and written in the same batch as the commit. Followers fetch the
+//! entries after the last one they applied, apply them in order under the same numbers and
+//! refuse every other write, so they serve `check` and `list_*` from a copy that trails the
+//! leader by whatever has not been pulled yet. Roles are per tenant and stored next to the log,
+//! so a restarted leader keeps logging and a restarted follower stays read only. Writes made while
+//! a store is standalone are not logged, so whenever a store becomes a leader with data its log
+//! does not account for, its next entry is a snapshot that resets followers to the whole store. A
+//! log that does not cover the store is refused to followers, which must be seeded from a backup.
+//! Only an empty store, or one that was seeded from its leader, can become a follower.
+//!
+//! `serve` ships a log over TCP to `Follower::pull`; `log_since` and `apply` are the same steps
+//! for any other transport. A connection starts with the shared secret given to both sides and is
+//! closed if it does not match; the secret and the log, API-key hashes included, travel in the
+//! clear, so run it on a private network or through a tunnel. Integers are big-endian:
+//!
+//! ```text
+//! hello    u16 length + secret, answered by u32 0 or an error reply
+//! request  u16 length + follower name, u64 after, u32 limit (at most MAX_PULL)
+//! reply    u32 count, then per entry u64 seq, u32 length + changes
+//!          or u32::MAX, u16 length + error message
+//! changes  optional 0 for a snapshot, which deletes every key first, then per key: u8 length +
+//!          partition, u32 length + key, then 0 for a delete or 1 and u32 length + value
+//! ```
+
+use super::*;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::{cell::Cell, io::{BufReader, BufWriter, ErrorKind as IoErrorKind, Read, Write}, net::{TcpListener, TcpStream, ToSocketAddrs}, sync::atomic::Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role { Standalone, Leader, Follower }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry { pub seq: u64, pub changes: Vec<u8> }
+
+// Entries one pull may return, whatever the follower asks for
+pub const MAX_PULL: usize = 10_000;
+// Log key holding the role; it sorts before every entry, which are numbered from 1
+const ROLE: [u8; 1] = [0];
+// Log key present while applying the log from its start yields the store: every write since entry
+// 1 or the last snapshot was logged. Sorts between ROLE and the entries.
+const COVERED: [u8; 2] = [0, 0];
+
+thread_local! { static APPLYING: Cell<bool> = const { Cell::new(false) }; }
+// Last sequence each follower pulled after, by (tenant, follower name)
+static FOLLOWERS: Mutex<BTreeMap<(Option<String>, String), u64>> = Mutex::new(BTreeMap::new());
+
+// Sets and stores the role of the calling thread's tenant; Standalone (the default) keeps no log.
+// A leader whose log misses some of the store's writes logs a snapshot of the store next.
+pub fn set_role(r: Role) -> Result<()> {
+    let _lock = WRITER.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(t) = tenant::current() else { return Err(Error("Invalid: not initialized".into())) };
+    let (p, mut batch) = (LOG.get().unwrap(), ks().batch());
+    let covered = p.contains_key(COVERED).map_err(err)?;
+    match r {
+        Role::Leader if !covered => {
+            let seq = applied()?;
+            if seq > 0 || !empty()? { batch.insert(&p, (seq + 1).to_be_bytes(), snapshot()?); }
+            batch.insert(&p, COVERED, []);
+        }
+        Role::Follower if role() != Role::Follower && !covered && !empty()? => {
+            return Err(Error("Not empty: a follower starts empty or from a backup of its leader".into()));
+        }
+        _ => {}
+    }
+    batch.insert(&p, ROLE, [r as u8]);
+    batch.commit().map_err(err)?;
+    ks().persist(fjall::PersistMode::SyncAll).map_err(err)?;
+    t.role.store(r as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+// The role stored in a tenant's log partition, read when the tenant is opened
+pub(crate) fn stored_role(log: &PartitionHandle) -> Result<u8> {
+    Ok(log.get(ROLE).map_err(err)?.map_or(0, |v| v[0]))
+}
+
+pub fn role() -> Role {
+    match tenant::current().map_or(0, |t| t.role.load(Ordering::Relaxed)) { 1 => Role::Leader, 2 => Role::Follower, _ => Role::Standalone }
+}
+
+// Sequence number of the last logged entry, 0 while the log is empty
+pub fn applied() -> Result<u64> {
+    Ok(LOG.get().unwrap().range(1u64.to_be_bytes()..).next_back().transpose().map_err(err)?.map_or(0, |(k, _)| u64_at(&k, 0)))
+}
+
+// Up to limit entries numbered above after. Fails if entries right after it were truncated, or if
+// the log does not cover the store, as such a follower can only catch up by starting over from a
+// backup.
+pub fn log_since(after: u64, limit: usize) -> Result<Vec<Entry>> {
+    if !LOG.get().unwrap().contains_key(COVERED).map_err(err)? {
+        return Err(Error("Invalid: the log does not cover the store, seed from a backup".into()));
+    }
+    let first = LOG.get().unwrap().range(1u64.to_be_bytes()..).next().transpose().map_err(err)?.map(|(k, _)| u64_at(&k, 0));
+    if first.is_some_and(|f| f > after + 1) { return Err(Error(format!("Invalid: entries after {after} were truncated, seed from a backup"))); }
+    let mut out = Vec::new();
+    for kv in LOG.get().unwrap().range(after.saturating_add(1).to_be_bytes()..).take(limit) {
+        let (k, v) = kv.map_err(err)?;
+        out.push(Entry { seq: u64_at(&k, 0), changes: v.to_vec() });
+    }
+    Ok(out)
+}
+
+// Applies entries as a follower, each in its own commit; every entry must directly follow the
+// last one applied. Returns the sequence number applied last.
+pub fn apply(entries: &[Entry]) -> Result<u64> {
+    if role() != Role::Follower { return Err(Error("Invalid: not a follower".into())); }
+    struct Done;
+    impl Drop for Done { fn drop(&mut self) { APPLYING.with(|a| a.set(false)); } }
+    APPLYING.with(|a| a.set(true));
+    let _d = Done;
+    for e in entries {
+        write(|| {
+            let next = applied()? + 1;
+            if e.seq != next { return Err(Error(format!("Invalid: expected entry {next}, got {}", e.seq))); }
+            let changes = match e.changes.split_first() {
+                Some((0, rest)) => { wipe()?; rest }
+                _ => &e.changes[..],
+            };
+            for (p, k, v) in decode(changes)? { stage(&p, &k, v); }
+            Ok(())
+        })?;
+    }
+    applied()
+}
+
+// Deletes entries up to through, e.g. the lowest sequence in followers(). The newest entry is
+// kept so numbering carries on. Returns the number deleted.
+pub fn truncate_log(through: u64) -> Result<usize> {
+    let _lock = WRITER.lock().unwrap_or_else(|e| e.into_inner());
+    let (p, keep) = (LOG.get().unwrap(), applied()?);
+    let mut batch = ks().batch();
+    let mut n = 0;
+    for kv in p.range(1u64.to_be_bytes()..=through.min(keep.saturating_sub(1)).to_be_bytes()) {
+        batch.remove(&p, kv.map_err(err)?.0);
+        n += 1;
+    }
+    batch.commit().map_err(err)?;
+    Ok(n)
+}
+
+// Followers of the calling thread's tenant with the sequence they last reported applying
+pub fn followers() -> Vec<(String, u64)> {
+    let t = current_tenant();
+    FOLLOWERS.lock().unwrap_or_else(|e| e.into_inner()).iter().filter(|((s, _), _)| *s == t).map(|((_, f), &seq)| (f.clone(), seq)).collect()
+}
+
+// Called by write() before staging starts
+pub(crate) fn writable() -> Result<()> {
+    if role() == Role::Follower && !APPLYING.with(Cell::get) { return Err(Error("Read only: follower".into())); }
+    Ok(())
+}
+
+// Called by apply() under the write lock; leaders and followers log the batch with it
+pub(crate) fn log(batch: &mut fjall::Batch, w: &Staged) -> Result<()> {
+    let (p, seq) = (LOG.get().unwrap(), applied()? + 1);
+    if role() == Role::Standalone {
+        // The log stops describing the store, so becoming a leader again takes a snapshot
+        if p.contains_key(COVERED).map_err(err)? { batch.remove(&p, COVERED); }
+        return Ok(());
+    }
+    let mut c = Vec::new();
+    for ((p, k), (_, v)) in w { encode(&mut c, tenant::base(p), k, v.as_deref()); }
+    // A follower's entry 1 came from a log that covers the leader's store from empty
+    if seq == 1 { batch.insert(&p, COVERED, []); }
+    batch.insert(&p, seq.to_be_bytes(), c);
+    Ok(())
+}
+
+fn encode(c: &mut Vec<u8>, p: &str, k: &[u8], v: Option<&[u8]>) {
+    c.push(p.len() as u8);
+    c.extend_from_slice(p.as_bytes());
+    c.extend_from_slice(&(k.len() as u32).to_be_bytes());
+    c.extend_from_slice(k);
+    match v {
+        Some(v) => { c.push(1); c.extend_from_slice(&(v.len() as u32).to_be_bytes()); c.extend_from_slice(v); }
+        None => c.push(0),
+    }
+}
+
+fn empty() -> Result<bool> {
+    for p in parts() { if p.first_key_value().map_err(err)?.is_some() { return Ok(false); } }
+    Ok(true)
+}
+
+// Every key of the calling thread's tenant as one entry's changes, replacing whatever a follower holds
+fn snapshot() -> Result<Vec<u8>> {
+    let mut c = vec![0];
+    for (i, name) in PARTS[..LOG.0].iter().enumerate() {
+        for kv in Part(i).get().unwrap().iter() {
+            let (k, v) = kv.map_err(err)?;
+            encode(&mut c, name, &k, Some(&v));
+        }
+    }
+    if c.len() > u32::MAX as usize { return Err(Error("Invalid: store too large to snapshot, seed followers from a backup".into())); }
+    Ok(c)
+}
+
+type Change = (PartitionHandle, Vec<u8>, Option<Vec<u8>>);
+
+fn decode(mut b: &[u8]) -> Result<Vec<Change>> {
+    let bad = || Error("Corrupt: log entry".into());
+    let take = |b: &mut &[u8], n: usize| -> Result<Vec<u8>> {
+        if b.len() < n { return Err(bad()) }
+        let (h, t) = b.split_at(n);
+        *b = t;
+        Ok(h.to_vec())
+    };
+    let mut out = Vec::new();
+    while !b.is_empty() {
+        let n = b.read_u8().map_err(|_| bad())? as usize;
+        let name = take(&mut b, n)?;
+        let i = PARTS[..LOG.0].iter().position(|p| p.as_bytes() == name).ok_or_else(bad)?;
+        let n = b.read_u32::<BigEndian>().map_err(|_| bad())? as usize;
+        let k = take(&mut b, n)?;
+        let v = match b.read_u8().map_err(|_| bad())? {
+            0 => None,
+            1 => { let n = b.read_u32::<BigEndian>().map_err(|_| bad())? as usize; Some(take(&mut b, n)?) }
+            _ => return Err(bad()),
+        };
+        out.push((Part(i).get().unwrap(), k, v));
+    }
+    Ok(out)
+}
+
+// Serves the log of the calling thread's tenant to followers presenting secret, one thread per
+// connection. Blocks.
+pub fn serve(listener: TcpListener, secret: &str) -> Result<()> {
+    if secret.is_empty() || secret.len() > u16::MAX as usize { return Err(Error("Invalid: replication secret".into())); }
+    let scope = current_tenant();
+    for s in listener.incoming() {
+        let (s, scope, secret) = (s.map_err(err)?, scope.clone(), secret.to_string());
+        std::thread::spawn(move || connection(s, scope, secret));
+    }
+    Ok(())
+}
+
+fn connection(s: TcpStream, scope: Option<String>, secret: String) -> std::io::Result<()> {
+    let (mut r, mut w) = (BufReader::new(s.try_clone()?), BufWriter::new(s));
+    let mut hello = vec![0; r.read_u16::<BigEndian>()? as usize];
+    r.read_exact(&mut hello)?;
+    // Compares every byte so the time taken does not reveal how much of the secret matched
+    if hello.len() != secret.len() || hello.iter().zip(secret.as_bytes()).fold(0, |d, (a, b)| d | (a ^ b)) != 0 {
+        return refuse(&mut w, "Unauthorized");
+    }
+    w.write_u32::<BigEndian>(0)?;
+    w.flush()?;
+    loop {
+        let n = match r.read_u16::<BigEndian>() {
+            Ok(n) => n as usize,
+            Err(e) if e.kind() == IoErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut name = vec![0; n];
+        r.read_exact(&mut name)?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let (after, limit) = (r.read_u64::<BigEndian>()?, (r.read_u32::<BigEndian>()? as usize).min(MAX_PULL));
+        let read = || {
+            FOLLOWERS.lock().unwrap_or_else(|e| e.into_inner()).insert((current_tenant(), name.clone()), after);
+            log_since(after, limit)
+        };
+        match scope.as_deref().map_or_else(read, |t| tenant(t, read)) {
+            Ok(entries) => {
+                w.write_u32::<BigEndian>(entries.len() as u32)?;
+                for e in entries {
+                    w.write_u64::<BigEndian>(e.seq)?;
+                    w.write_u32::<BigEndian>(e.changes.len() as u32)?;
+                    w.write_all(&e.changes)?;
+                }
+            }
+            Err(e) => refuse(&mut w, &e.0)?,
+        }
+        w.flush()?;
+    }
+}
+
+// Sends an error reply, cut to the longest message its u16 length can carry
+fn refuse(w: &mut impl Write, msg: &str) -> std::io::Result<()> {
+    let msg = &msg.as_bytes()[..msg.len().min(u16::MAX as usize)];
+    w.write_u32::<BigEndian>(u32::MAX)?;
+    w.write_u16::<BigEndian>(msg.len() as u16)?;
+    w.write_all(msg)?;
+    w.flush()
+}
+
+// A follower's connection to a leader's serve
+pub struct Follower { name: String, r: BufReader<TcpStream>, w: BufWriter<TcpStream> }
+
+impl Follower {
+    pub fn connect(addr: impl ToSocketAddrs, name: &str, secret: &str) -> Result<Self> {
+        if name.len() > u16::MAX as usize { return Err(Error("Invalid: follower name".into())); }
+        if secret.len() > u16::MAX as usize { return Err(Error("Invalid: replication secret".into())); }
+        let s = TcpStream::connect(addr).map_err(err)?;
+        let mut w = BufWriter::new(s.try_clone().map_err(err)?);
+        w.write_u16::<BigEndian>(secret.len() as u16).map_err(err)?;
+        w.write_all(secret.as_bytes()).map_err(err)?;
+        w.flush().map_err(err)?;
+        let mut f = Self { name: name.to_string(), r: BufReader::new(s), w };
+        f.reply()?;
+        Ok(f)
+    }
+
+    // The count heading a reply, or the error sent instead
+    fn reply(&mut self) -> Result<u32> {
+        let n = self.r.read_u32::<BigEndian>().map_err(err)?;
+        if n != u32::MAX { return Ok(n) }
+        let mut msg = vec![0; self.r.read_u16::<BigEndian>().map_err(err)? as usize];
+        self.r.read_exact(&mut msg).map_err(err)?;
+        Err(Error(String::from_utf8_lossy(&msg).into_owned()))
+    }
+
+    // Fetches up to limit entries after the last one applied and applies them, in the calling
+    // thread's tenant. Returns how many were applied.
+    pub fn pull(&mut self, limit: u32) -> Result<usize> {
+        let after = applied()?;
+        self.w.write_u16::<BigEndian>(self.name.len() as u16).map_err(err)?;
+        self.w.write_all(self.name.as_bytes()).map_err(err)?;
+        self.w.write_u64::<BigEndian>(after).map_err(err)?;
+        self.w.write_u32::<BigEndian>(limit).map_err(err)?;
+        self.w.flush().map_err(err)?;
+        // Lengths come from the peer, so nothing is allocated ahead of the bytes actually received
+        let n = self.reply()?;
+        if n as usize > (limit as usize).min(MAX_PULL) { return Err(Error(format!("Corrupt: {n} entries for a pull of {limit}"))); }
+        let mut entries = Vec::with_capacity(n as usize);
+        for _ in 0..n {
+            let seq = self.r.read_u64::<BigEndian>().map_err(err)?;
+            let len = self.r.read_u32::<BigEndian>().map_err(err)? as u64;
+            let mut changes = Vec::new();
+            if (&mut self.r).take(len).read_to_end(&mut changes).map_err(err)? as u64 != len { return Err(Error("Corrupt: truncated entry".into())); }
+            entries.push(Entry { seq, changes });
+        }
+        apply(&entries)?;
+        Ok(entries.len())
+    }
+}
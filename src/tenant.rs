@@ -0,0 +1,105 @@
+//! Tenants. Each tenant is a full set of partitions in the shared keyspace, named
+//! `<tenant>#objects`, `<tenant>#subjects`, ..., so its objects, grants, names, keys and its own
+//! `_SYSTEM`/`_ROOT` never meet another tenant's. Calls made inside `tenant(name, f)` see only
+//! that tenant; calls outside any scope use the default tenant, whose partitions carry no prefix.
+
+use super::*;
+use std::{cell::RefCell, sync::{atomic::{AtomicU64, AtomicU8, Ordering}, Arc}};
+
+pub(crate) struct Tenant { pub(crate) id: u64, pub(crate) name: Option<String>, pub(crate) parts: [PartitionHandle; 16], pub(crate) role: AtomicU8 }
+
+static DEFAULT: OnceLock<Arc<Tenant>> = OnceLock::new();
+// Tenants opened since init. fjall removes a dropped tenant's files once the last handle goes.
+static OPEN: Mutex<BTreeMap<String, Arc<Tenant>>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+thread_local! { static CURRENT: RefCell<Option<Arc<Tenant>>> = const { RefCell::new(None) }; }
+
+// The tenant of the calling thread, or None before init
+pub(crate) fn current() -> Option<Arc<Tenant>> { CURRENT.with(|c| c.borrow().clone()).or_else(|| DEFAULT.get().cloned()) }
+
+pub(crate) fn open_default(ks: &Keyspace) -> Result<()> {
+    let t = open(ks, None)?;
+    let _ = DEFAULT.set(t);
+    Ok(())
+}
+
+fn open(ks: &Keyspace, name: Option<&str>) -> Result<Arc<Tenant>> {
+    let mut parts = Vec::with_capacity(PARTS.len());
+    for base in PARTS {
+        let full = name.map_or(base.to_string(), |n| format!("{n}#{base}"));
+        parts.push(ks.open_partition(&full, PartitionCreateOptions::default()).map_err(err)?);
+    }
+    let id = if name.is_some() { NEXT_ID.fetch_add(1, Ordering::Relaxed) } else { 0 };
+    let role = AtomicU8::new(replication::stored_role(&parts[LOG.0])?);
+    let parts = parts.try_into().unwrap_or_else(|_| unreachable!());
+    Ok(Arc::new(Tenant { id, name: name.map(str::to_string), parts, role }))
+}
+
+fn valid(name: &str) -> Result<()> {
+    let ok = !name.is_empty() && name.len() <= 64 && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+    if ok { Ok(()) } else { Err(Error(format!("Invalid: tenant name {name:?}"))) }
+}
+
+// Partition name without its tenant prefix
+pub(crate) fn base(name: &str) -> &str { name.rsplit('#').next().unwrap_or(name) }
+
+// Name of the tenant of the calling thread; None for the default tenant
+pub fn current_tenant() -> Option<String> { current().and_then(|t| t.name.clone()) }
+
+// Creates the partitions of a new, empty tenant; bootstrap it inside tenant() like a new store
+pub fn create_tenant(name: &str) -> Result<()> {
+    valid(name)?;
+    let mut open_ = OPEN.lock().unwrap_or_else(|e| e.into_inner());
+    if ks().partition_exists(&format!("{name}#meta")) { return Err(Error(format!("Exists: tenant {name}"))); }
+    open_.insert(name.to_string(), open(ks(), Some(name))?);
+    Ok(())
+}
+
+// Runs f against tenant name, e.g. tenant("acme", || grant(root, alice, doc, _VIEWER)).
+// A write can not switch tenants midway.
+pub fn tenant<T>(name: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    valid(name)?;
+    let t = {
+        let mut open_ = OPEN.lock().unwrap_or_else(|e| e.into_inner());
+        match open_.get(name) {
+            Some(t) => t.clone(),
+            // Created before the last restart
+            None if ks().partition_exists(&format!("{name}#meta")) => { let t = open(ks(), Some(name))?; open_.insert(name.to_string(), t.clone()); t }
+            None => return Err(Error(format!("Unknown: tenant {name}"))),
+        }
+    };
+    if staged(|_| ()).is_some() && current().map(|c| c.id) != Some(t.id) {
+        return Err(Error("Invalid: tenant switch inside a write".into()));
+    }
+    struct Restore(Option<Arc<Tenant>>);
+    impl Drop for Restore { fn drop(&mut self) { CURRENT.with(|c| *c.borrow_mut() = self.0.take()); } }
+    let _r = Restore(CURRENT.with(|c| c.replace(Some(t))));
+    f()
+}
+
+// Every tenant in the store, default excluded
+pub fn tenants() -> Vec<String> {
+    let mut out: Vec<String> = ks().list_partitions().iter().filter_map(|p| p.strip_suffix("#meta").map(str::to_string)).collect();
+    out.sort();
+    out
+}
+
+// Consistent single-file archive of one tenant; restore_from yields a store holding just that tenant
+pub fn export_tenant(name: &str, path: &str) -> Result<u64> { tenant(name, || backup_to(path)) }
+
+// Deletes every partition of the tenant, meta last so an interrupted drop can be repeated.
+// Waits for in-flight writes; callers still scoped to the tenant fail on their next read.
+pub fn drop_tenant(name: &str) -> Result<()> {
+    valid(name)?;
+    let _lock = WRITER.lock().unwrap_or_else(|e| e.into_inner());
+    let mut open_ = OPEN.lock().unwrap_or_else(|e| e.into_inner());
+    let mut names: Vec<String> = PARTS.iter().map(|b| format!("{name}#{b}")).filter(|n| ks().partition_exists(n)).collect();
+    names.sort_by_key(|n| n.ends_with("#meta"));
+    if names.is_empty() { return Err(Error(format!("Unknown: tenant {name}"))); }
+    if let Some(t) = open_.remove(name) { cache::forget(t.id); }
+    for n in names {
+        let p = ks().open_partition(&n, PartitionCreateOptions::default()).map_err(err)?;
+        ks().delete_partition(p).map_err(err)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! Change feed. Each commit that touches roles, grants, inherit edges, parent edges or group
+//! membership becomes one Commit of Events, numbered in commit order. Subscribers are called on
+//! the committing thread while the write lock is still held, so they see commits in order and
+//! must only queue what they receive; returning false unsubscribes. Commits name their tenant,
+//! None for the default one.
+
+use super::*;
+use std::sync::{atomic::{AtomicU64, Ordering}, RwLock};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Role { obj: u64, role: u64, mask: Option<u64> },
+    Grant { sub: u64, obj: u64, role: u64, granted: bool },
+    Inherit { sub: u64, obj: u64, role: u64, parent: Option<u64> },
+    Parent { obj: u64, parent: u64, mask: Option<u64> },
+    Member { group: u64, member: u64, added: bool },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit { pub seq: u64, pub tenant: Option<String>, pub events: Vec<Event> }
+
+type Subscriber = Box<dyn Fn(&Commit) -> bool + Send + Sync>;
+static SUBSCRIBERS: RwLock<Vec<Subscriber>> = RwLock::new(Vec::new());
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+pub fn subscribe(f: impl Fn(&Commit) -> bool + Send + Sync + 'static) {
+    SUBSCRIBERS.write().unwrap_or_else(|e| e.into_inner()).push(Box::new(f));
+}
+
+// Events for a staged batch, or None when nobody is listening
+pub(crate) fn events(w: &Staged) -> Option<Vec<Event>> {
+    if SUBSCRIBERS.read().unwrap_or_else(|e| e.into_inner()).is_empty() { return None; }
+    let events: Vec<Event> = w.iter().filter_map(|((p, k), (_, v))| {
+        let v = v.as_deref().map(val);
+        Some(match tenant::base(p) {
+            "objects" => Event::Role { obj: u64_at(k, 0), role: u64_at(k, 1), mask: v },
+            "subjects" => Event::Grant { sub: u64_at(k, 0), obj: u64_at(k, 1), role: u64_at(k, 2), granted: v.is_some() },
+            "inherits" => Event::Inherit { sub: u64_at(k, 0), obj: u64_at(k, 1), role: u64_at(k, 2), parent: v },
+            "parents" => Event::Parent { obj: u64_at(k, 0), parent: u64_at(k, 1), mask: v },
+            "groups" => Event::Member { member: u64_at(k, 0), group: u64_at(k, 1), added: v.is_some() },
+            _ => return None,
+        })
+    }).collect();
+    (!events.is_empty()).then_some(events)
+}
+
+// Called by apply() under the write lock once the batch is in the store
+pub(crate) fn publish(events: Option<Vec<Event>>) {
+    let Some(events) = events else { return };
+    let commit = Commit { seq: SEQ.fetch_add(1, Ordering::Relaxed) + 1, tenant: current_tenant(), events };
+    SUBSCRIBERS.write().unwrap_or_else(|e| e.into_inner()).retain(|f| f(&commit));
+}
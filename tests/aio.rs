@@ -0,0 +1,26 @@
+#![cfg(feature = "async")]
+
+use capbit::{aio::Handle, *};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_async_handle() {
+    init("target/test_db_aio").unwrap();
+    let h = Handle::new(4);
+    h.clear().await.unwrap();
+    let (sys, root) = h.bootstrap().await.unwrap();
+    h.grant(root, 10, sys, _VIEWER).await.unwrap();
+    let checks: Vec<_> = (0..64).map(|i| {
+        let h = h.clone();
+        tokio::spawn(async move { h.check(10 + i % 2, sys, VIEWER_BITS).await.unwrap() })
+    }).collect();
+    let mut allowed = 0;
+    for c in checks { if c.await.unwrap() { allowed += 1; } }
+    assert_eq!(allowed, 32);
+    assert_eq!(h.available(), 4);
+    assert!(h.grant(10, 11, sys, _VIEWER).await.is_err());
+    h.commit(WriteBatch::new(root).grant(11, sys, _EDITOR)).await.unwrap().unwrap();
+    assert_eq!(h.list_subjects(root, sys).await.unwrap(), vec![(_ROOT, _OWNER), (10, _VIEWER), (11, _EDITOR)]);
+    let synced = sync_count();
+    h.durability(Durability::SyncAll).revoke(root, 11, sys, _EDITOR).await.unwrap();
+    assert!(sync_count() > synced);
+}
@@ -0,0 +1,72 @@
+use capbit::*;
+use std::{process::Command, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread};
+
+// The store is process-global, so the restored copy is opened by a child run of this binary
+const RESTORED: &str = "CAPBIT_RESTORED";
+const NAMES: [&str; 5] = ["project", "doc", "alice", "bob", "team"];
+
+fn ids() -> Vec<u64> {
+    [_SYSTEM, _ROOT].into_iter().chain(NAMES.iter().map(|n| lookup(n).unwrap().unwrap())).collect()
+}
+
+fn masks() -> Vec<u64> {
+    let ids = ids();
+    ids.iter().flat_map(|&s| ids.iter().map(move |&o| get_mask(s, o).unwrap())).collect()
+}
+
+#[test] fn test_backup_restore() {
+    init("target/test_db_backup").unwrap();
+    clear().unwrap();
+    let (sys, root) = bootstrap().unwrap();
+    set_parent_named(root, "project", "_system", ALL_BITS).unwrap();
+    set_parent_named(root, "doc", "project", VIEWER_BITS).unwrap();
+    set_parent_named(root, "team", "_system", ALL_BITS).unwrap();
+    let [project, _, alice, bob, team] = NAMES.map(|n| intern(n).unwrap());
+    create(root, project, _EDITOR, EDITOR_BITS).unwrap();
+    create(root, project, _VIEWER, VIEWER_BITS).unwrap();
+    grant(root, team, project, _EDITOR).unwrap();
+    add_member(root, team, alice).unwrap();
+    inherit(root, bob, project, _VIEWER, alice).unwrap();
+    let before = masks();
+
+    // Writes keep landing on unrelated subjects while the backup runs
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = { let stop = stop.clone(); thread::spawn(move || {
+        let mut i = 0;
+        while !stop.load(Ordering::Relaxed) { grant(root, 1000 + i % 50, sys, _VIEWER).unwrap(); i += 1; }
+    }) };
+    let (archive, dir) = ("target/test_backup.capbit", "target/test_db_restored");
+    let _ = std::fs::remove_dir_all(dir);
+    let n = backup_to(archive).unwrap();
+    stop.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+
+    assert_eq!(restore_from(archive, dir).unwrap(), n);
+    assert_eq!(restore_from(archive, dir).unwrap_err().0, "Not empty");
+    // A backup that fails, here renaming onto the restored store's directory, removes its .tmp
+    assert!(backup_to(dir).is_err());
+    assert!(!std::path::Path::new(&format!("{dir}.tmp")).exists());
+    let out = Command::new(std::env::current_exe().unwrap())
+        .args(["restored_masks", "--exact", "--ignored", "--nocapture"]).env(RESTORED, dir).output().unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let restored = stdout.lines().find_map(|l| l.split_once("masks:")).map(|(_, m)| m).unwrap();
+    assert_eq!(restored, format!("{before:?}"));
+
+    // A flipped byte or a foreign file is rejected before anything is written
+    let mut bytes = std::fs::read(archive).unwrap();
+    let mid = bytes.len() / 2;
+    bytes[mid] ^= 1;
+    std::fs::write(archive, &bytes).unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+    assert_eq!(restore_from(archive, dir).unwrap_err().0, "Corrupt");
+    std::fs::write(archive, b"not a backup at all").unwrap();
+    assert_eq!(restore_from(archive, dir).unwrap_err().0, "Invalid");
+    assert!(std::fs::read_dir(dir).map_or(true, |mut d| d.next().is_none()));
+}
+
+#[test] #[ignore] fn restored_masks() {
+    let Ok(dir) = std::env::var(RESTORED) else { return };
+    init(&dir).unwrap();
+    println!("masks:{:?}", masks());
+}
@@ -0,0 +1,58 @@
+#![cfg(all(feature = "client", feature = "server", unix))]
+
+use axum::{body::Body, http::{Request, StatusCode}};
+use capbit::{aio::Handle, client::{open, Api, Client, Embedded}, http::{router, serve_unix}, *};
+use tower::ServiceExt;
+
+#[tokio::test(flavor = "multi_thread")] async fn test_sidecar() {
+    init("target/test_db_client").unwrap();
+    clear().unwrap();
+    let (sys, root) = bootstrap().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let sock = dir.path().join("capbit.sock");
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(serve_unix(tokio::net::UnixListener::bind(&sock).unwrap(), router(Handle::new(8), false), async { stopped.await.ok(); }));
+
+    let target = format!("unix:{}", sock.display());
+    tokio::task::spawn_blocking(move || {
+        let (remote, local): (Box<dyn Api>, Box<dyn Api>) = (open(&target).unwrap(), Box::new(Embedded));
+        let (alice, doc) = (100, 200);
+        remote.set_parent(root, doc, sys, ALL_BITS).unwrap();
+        remote.create(root, doc, 9, VIEWER_BITS).unwrap();
+        assert_eq!(remote.create(root, doc, 9, 1).unwrap_err().kind(), ErrorKind::Conflict);
+        remote.update(root, doc, 9, VIEWER_BITS | 1).unwrap();
+        assert_eq!(remote.get_object(root, doc, 9).unwrap(), Some(VIEWER_BITS | 1));
+        assert_eq!(remote.get_object(root, doc, 10).unwrap(), None);
+        remote.grant(root, alice, doc, 9).unwrap();
+        remote.define_bit(root, doc, 22, "publish", "may publish").unwrap();
+
+        // Same answers either way
+        assert!(remote.check(alice, doc, VIEWER_BITS).unwrap());
+        assert_eq!(remote.check_many(alice, &[(doc, 1), (doc, ALL_BITS)]).unwrap(), local.check_many(alice, &[(doc, 1), (doc, ALL_BITS)]).unwrap());
+        assert_eq!(remote.get_mask(alice, doc).unwrap(), local.get_mask(alice, doc).unwrap());
+        assert_eq!(remote.list_subjects(root, doc).unwrap(), local.list_subjects(root, doc).unwrap());
+        assert_eq!(remote.list_parents(root, doc).unwrap(), vec![(sys, ALL_BITS)]);
+        assert_eq!(remote.list_bits(root, doc).unwrap(), local.list_bits(root, doc).unwrap());
+
+        // Errors keep their message and kind
+        assert_eq!(remote.grant(alice, 101, doc, 9).unwrap_err().0, "Denied");
+        remote.revoke(root, alice, doc, 9).unwrap();
+        assert!(!local.check(alice, doc, VIEWER_BITS).unwrap());
+    }).await.unwrap();
+
+    // A 404 that is not the API's own not-found reply is an error, not a missing role
+    let other = dir.path().join("other.sock");
+    let (stop_other, other_stopped) = tokio::sync::oneshot::channel::<()>();
+    let other_server = tokio::spawn(serve_unix(tokio::net::UnixListener::bind(&other).unwrap(), axum::Router::new(), async { other_stopped.await.ok(); }));
+    let err = tokio::task::spawn_blocking(move || Client::new(other).get_object(root, 200, 9)).await.unwrap().unwrap_err();
+    assert_eq!(err.0, "HTTP 404");
+    stop_other.send(()).unwrap();
+    other_server.await.unwrap().unwrap();
+
+    // The actor header only counts on the socket
+    let req = Request::builder().uri("/subjects/2/grants").header("x-capbit-actor", "2").body(Body::empty()).unwrap();
+    assert_eq!(router(Handle::new(8), false).oneshot(req).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
@@ -0,0 +1,63 @@
+use capbit::*;
+use std::sync::{Barrier, Mutex, MutexGuard};
+use std::thread;
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn setup() -> (MutexGuard<'static, ()>, u64, u64) {
+    let l = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    init("target/test_db_concurrency").unwrap();
+    clear().unwrap();
+    let (sys, root) = bootstrap().unwrap();
+    (l, sys, root)
+}
+
+// Runs f on n threads released together, returning each thread's result
+fn race<T: Send + 'static>(n: usize, f: impl Fn(usize) -> T + Send + Sync + 'static) -> Vec<T> {
+    let go = std::sync::Arc::new(Barrier::new(n));
+    let f = std::sync::Arc::new(f);
+    let threads: Vec<_> = (0..n).map(|i| {
+        let (go, f) = (go.clone(), f.clone());
+        thread::spawn(move || { go.wait(); f(i) })
+    }).collect();
+    threads.into_iter().map(|t| t.join().unwrap()).collect()
+}
+
+#[test] fn test_concurrent_create() {
+    let (_l, sys, root) = setup();
+    for round in 0..50 {
+        let role = 1000 + round;
+        let out = race(16, move |i| create(root, sys, role, 1 << (i % 8)));
+        assert_eq!(out.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(out.iter().filter_map(|r| r.as_ref().err()).all(|e| e.0 == "Exists"));
+    }
+}
+
+// Two admins revoking each other: with check and write in one step, the loser is already
+// powerless by the time it writes, so exactly one revoke can succeed
+#[test] fn test_mutual_revoke() {
+    let (_l, sys, root) = setup();
+    for round in 0..100 {
+        let (a, b) = (100 + 2 * round, 101 + 2 * round);
+        grant(root, a, sys, _ADMIN).unwrap();
+        grant(root, b, sys, _ADMIN).unwrap();
+        let out = race(2, move |i| if i == 0 { revoke(a, b, sys, _ADMIN) } else { revoke(b, a, sys, _ADMIN) });
+        assert_eq!(out.iter().filter(|r| r.is_ok()).count(), 1, "round {round}");
+        assert_ne!(check_subject(a, sys, _ADMIN).unwrap(), check_subject(b, sys, _ADMIN).unwrap());
+    }
+}
+
+#[test] fn test_concurrent_grant_revoke() {
+    let (_l, sys, root) = setup();
+    race(8, move |i| {
+        for j in 0..200 {
+            let sub = 10 + (j % 4) as u64;
+            if (i + j) % 2 == 0 { grant(root, sub, sys, _VIEWER).unwrap() } else { revoke(root, sub, sys, _VIEWER).unwrap() }
+        }
+    });
+    for sub in 10..14 {
+        let fwd = list_roles_for(root, sub, sys).unwrap().contains(&_VIEWER);
+        let rev = list_subjects(root, sys).unwrap().iter().any(|&(s, r)| s == sub && r == _VIEWER);
+        assert_eq!(fwd, rev);
+    }
+}
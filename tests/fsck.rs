@@ -0,0 +1,42 @@
+use capbit::*;
+
+fn k(ids: &[u64]) -> Vec<u8> { ids.iter().flat_map(|i| i.to_be_bytes()).collect() }
+
+#[test] fn test_repair() {
+    // Lay down partitions the way a crash mid-write could leave them, then open with capbit
+    let path = "target/test_db_fsck";
+    let _ = std::fs::remove_dir_all(path);
+    {
+        let ks = fjall::Config::new(path).open().unwrap();
+        let p = |n: &str| ks.open_partition(n, Default::default()).unwrap();
+        let one = 1u64.to_be_bytes();
+        p("objects").insert(k(&[_SYSTEM, _OWNER]), ALL_BITS.to_be_bytes()).unwrap();
+        p("subjects").insert(k(&[_ROOT, _SYSTEM, _OWNER]), one).unwrap();
+        p("subjects_rev").insert(k(&[_SYSTEM, _ROOT, _OWNER]), one).unwrap();
+        p("subjects").insert(k(&[10, 100, _OWNER]), one).unwrap();
+        p("subjects_rev").insert(k(&[100, 11, _OWNER]), one).unwrap();
+        p("parents").insert(k(&[100, _SYSTEM]), ALL_BITS.to_be_bytes()).unwrap();
+        p("children").insert(k(&[_SYSTEM, 100]), VIEWER_BITS.to_be_bytes()).unwrap();
+        p("parents").insert(k(&[200, 201]), ALL_BITS.to_be_bytes()).unwrap();
+        p("parents").insert(k(&[201, 200]), ALL_BITS.to_be_bytes()).unwrap();
+        p("members").insert(k(&[20, 10]), one).unwrap();
+        ks.persist(fjall::PersistMode::SyncAll).unwrap();
+    }
+    init(path).unwrap();
+
+    let report = verify(false).unwrap();
+    let index = |i: &Issue| match i { Issue::Missing { index, .. } | Issue::Orphaned { index, .. } | Issue::Mismatch { index, .. } => *index, _ => "" };
+    let mut found: Vec<(&str, String)> = report.issues.iter().map(|i| (index(i), format!("{i:?}").split(' ').next().unwrap().to_string())).collect();
+    found.sort();
+    assert_eq!(found, [("", "Cycle".into()), ("", "UndeclaredRole".into()), ("children", "Mismatch".into()), ("children", "Missing".into()), ("children", "Missing".into()),
+                       ("members", "Orphaned".into()), ("subjects_rev", "Missing".into()), ("subjects_rev", "Orphaned".into())]);
+    assert!(report.issues.contains(&Issue::Cycle { index: "parents", ids: vec![200, 201, 200] }));
+    assert_eq!(report.repaired, 0);
+    assert_eq!(list_subjects(_ROOT, 100).unwrap(), vec![(11, _OWNER)]);
+
+    let report = verify(true).unwrap();
+    assert_eq!(report.repaired, 6);
+    assert_eq!(list_subjects(_ROOT, 100).unwrap(), vec![(10, _OWNER)]);
+    assert_eq!(list_children(_ROOT, _SYSTEM).unwrap(), vec![(100, ALL_BITS)]);
+    assert!(verify(false).unwrap().issues.iter().all(|i| !i.repairable()));
+}
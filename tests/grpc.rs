@@ -0,0 +1,93 @@
+#![cfg(feature = "grpc")]
+
+use capbit::{aio::Handle, grpc::{self, pb::{self, capbit_client::CapbitClient, event::Kind}, service}, *};
+use tokio_stream::{wrappers::TcpListenerStream, StreamExt};
+use tonic::{transport::Channel, Code, Request};
+
+fn auth<T>(key: &str, msg: T) -> Request<T> {
+    let mut req = Request::new(msg);
+    req.metadata_mut().insert("authorization", format!("Bearer {key}").parse().unwrap());
+    req
+}
+
+#[tokio::test] async fn test_grpc() {
+    init("target/test_db_grpc").unwrap();
+    clear().unwrap();
+    let (sys, root) = bootstrap().unwrap();
+    let (alice, bob, doc) = (100, 101, 200);
+    set_parent(root, doc, sys, ALL_BITS).unwrap();
+    create(root, doc, _VIEWER, VIEWER_BITS).unwrap();
+    let (_, root_key) = keys::issue(root, root).unwrap();
+    let (_, alice_key) = keys::issue(root, alice).unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(tonic::transport::Server::builder().add_service(service(Handle::new(8))).serve_with_incoming(TcpListenerStream::new(listener)));
+    let mut c: CapbitClient<Channel> = CapbitClient::connect(format!("http://{addr}")).await.unwrap();
+
+    let mut events = c.watch(auth(&root_key, pb::WatchRequest {})).await.unwrap().into_inner();
+    assert_eq!(c.watch(auth(&alice_key, pb::WatchRequest {})).await.unwrap_err().code(), Code::PermissionDenied);
+
+    c.grant(auth(&root_key, pb::GrantRequest { sub: alice, obj: doc, role: _VIEWER })).await.unwrap();
+    c.inherit(auth(&root_key, pb::InheritRequest { sub: bob, obj: doc, role: _VIEWER, parent: alice })).await.unwrap();
+    let check = |key: &str, sub| auth(key, pb::CheckRequest { sub, obj: doc, mask: VIEWER_BITS });
+    assert!(c.check(check(&alice_key, alice)).await.unwrap().into_inner().allowed);
+    let items = vec![pb::CheckItem { obj: doc, mask: VIEWER_BITS }, pb::CheckItem { obj: doc, mask: EDITOR_BITS }, pb::CheckItem { obj: sys, mask: 1 }];
+    assert_eq!(c.check_many(auth(&alice_key, pb::CheckManyRequest { sub: alice, items })).await.unwrap().into_inner().allowed, vec![true, false, false]);
+    assert_eq!(c.get_mask(auth(&alice_key, pb::GetMaskRequest { sub: alice, obj: doc })).await.unwrap().into_inner().mask, VIEWER_BITS);
+
+    let pairs = |p: pb::Pairs| p.items.into_iter().map(|p| (p.a, p.b)).collect::<Vec<_>>();
+    assert_eq!(pairs(c.list_grants(auth(&root_key, pb::SubjectRequest { sub: alice })).await.unwrap().into_inner()), vec![(doc, _VIEWER)]);
+    assert_eq!(pairs(c.list_roles(auth(&root_key, pb::ObjectRequest { obj: doc })).await.unwrap().into_inner()), vec![(_VIEWER, VIEWER_BITS)]);
+    assert_eq!(pairs(c.list_inherits(auth(&root_key, pb::SubjectObjectRequest { sub: bob, obj: doc })).await.unwrap().into_inner()), vec![(_VIEWER, alice)]);
+    let triples = |p: pb::Triples| p.items.into_iter().map(|t| (t.a, t.b, t.c)).collect::<Vec<_>>();
+    assert_eq!(triples(c.list_inherits_on_obj(auth(&root_key, pb::ObjectRequest { obj: doc })).await.unwrap().into_inner()), vec![(_VIEWER, alice, bob)]);
+    assert_eq!(pairs(c.list_inherits_on_obj_role(auth(&root_key, pb::ObjectRoleRequest { obj: doc, role: _VIEWER })).await.unwrap().into_inner()), vec![(alice, bob)]);
+    assert_eq!(triples(c.list_inherits_from_parent(auth(&root_key, pb::ParentRequest { parent: alice })).await.unwrap().into_inner()), vec![(doc, _VIEWER, bob)]);
+    assert_eq!(pairs(c.list_inherits_from_parent_on_obj(auth(&root_key, pb::ParentObjectRequest { parent: alice, obj: doc })).await.unwrap().into_inner()), vec![(_VIEWER, bob)]);
+    assert_eq!(c.list_roles_for(auth(&root_key, pb::SubjectObjectRequest { sub: alice, obj: doc })).await.unwrap().into_inner().ids, vec![_VIEWER]);
+
+    // Errors come back as status codes
+    let denied = c.grant(auth(&alice_key, pb::GrantRequest { sub: bob, obj: doc, role: _VIEWER })).await.unwrap_err();
+    assert_eq!((denied.code(), denied.message()), (Code::PermissionDenied, "Denied"));
+    assert_eq!(c.check(check("cbk_wrong", alice)).await.unwrap_err().code(), Code::Unauthenticated);
+    assert_eq!(c.check(Request::new(pb::CheckRequest { sub: alice, obj: doc, mask: 1 })).await.unwrap_err().code(), Code::Unauthenticated);
+    // Probing another subject needs the list permissions
+    assert_eq!(c.check(check(&alice_key, bob)).await.unwrap_err().code(), Code::PermissionDenied);
+    let items = vec![pb::CheckItem { obj: doc, mask: VIEWER_BITS }];
+    assert_eq!(c.check_many(auth(&alice_key, pb::CheckManyRequest { sub: bob, items })).await.unwrap_err().code(), Code::PermissionDenied);
+    assert_eq!(c.get_mask(auth(&alice_key, pb::GetMaskRequest { sub: bob, obj: doc })).await.unwrap_err().code(), Code::PermissionDenied);
+    assert_eq!(c.get_mask(auth(&root_key, pb::GetMaskRequest { sub: alice, obj: doc })).await.unwrap().into_inner().mask, VIEWER_BITS);
+
+    c.revoke(auth(&root_key, pb::GrantRequest { sub: alice, obj: doc, role: _VIEWER })).await.unwrap();
+    assert!(!c.check(check(&alice_key, alice)).await.unwrap().into_inner().allowed);
+
+    // Watch saw the three successful writes, in commit order
+    let mut seen = Vec::new();
+    for _ in 0..3 { seen.push(events.next().await.unwrap().unwrap()); }
+    assert!(seen.windows(2).all(|w| w[1].seq > w[0].seq));
+    let kinds: Vec<Kind> = seen.into_iter().map(|c| c.events.into_iter().next().unwrap().kind.unwrap()).collect();
+    assert_eq!(kinds, vec![
+        Kind::Grant(pb::GrantEvent { sub: alice, obj: doc, role: _VIEWER, granted: true }),
+        Kind::Inherit(pb::InheritEvent { sub: bob, obj: doc, role: _VIEWER, parent: Some(alice) }),
+        Kind::Grant(pb::GrantEvent { sub: alice, obj: doc, role: _VIEWER, granted: false }),
+    ]);
+
+    // A watcher whose admin rights are revoked stops receiving commits from the revoke on
+    let carol = 102;
+    grant(root, carol, sys, _ADMIN).unwrap();
+    let (_, carol_key) = keys::issue(root, carol).unwrap();
+    let mut demoted = c.watch(auth(&carol_key, pb::WatchRequest {})).await.unwrap().into_inner();
+    grant(root, 900, doc, _VIEWER).unwrap();
+    revoke(root, carol, sys, _ADMIN).unwrap();
+    grant(root, 901, doc, _VIEWER).unwrap();
+    assert_eq!(demoted.next().await.unwrap().unwrap().events[0].kind, Some(Kind::Grant(pb::GrantEvent { sub: 900, obj: doc, role: _VIEWER, granted: true })));
+    assert!(demoted.next().await.is_none());
+
+    // A watcher that falls a full buffer behind is dropped and its stream ends
+    let mut slow = c.watch(auth(&root_key, pb::WatchRequest {})).await.unwrap().into_inner();
+    for sub in 0..=grpc::WATCH_BUFFER as u64 { grant(root, 1000 + sub, doc, _VIEWER).unwrap(); }
+    let mut n = 0;
+    while slow.next().await.is_some() { n += 1; }
+    assert_eq!(n, grpc::WATCH_BUFFER);
+}
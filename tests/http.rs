@@ -0,0 +1,125 @@
+#![cfg(feature = "server")]
+
+use axum::{body::{to_bytes, Body}, http::{Request, StatusCode}, Router};
+use capbit::{aio::Handle, http::{openapi, router, routes}, *};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+async fn call(app: &Router, method: &str, uri: &str, key: &str, body: Option<Value>) -> (StatusCode, Value) {
+    call_in(app, None, method, uri, key, body).await
+}
+
+async fn call_in(app: &Router, tenant: Option<&str>, method: &str, uri: &str, key: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let mut req = Request::builder().method(method).uri(uri).header("authorization", format!("Bearer {key}")).header("content-type", "application/json");
+    if let Some(t) = tenant { req = req.header("x-capbit-tenant", t); }
+    let res = app.clone().oneshot(req.body(body.map_or(Body::empty(), |b| Body::from(b.to_string()))).unwrap()).await.unwrap();
+    let status = res.status();
+    let bytes = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+#[tokio::test] async fn test_rest() {
+    init("target/test_db_http").unwrap();
+    clear().unwrap();
+    let app = router(Handle::new(8), true);
+
+    let (s, b) = call(&app, "POST", "/admin/bootstrap", "", None).await;
+    assert_eq!(s, StatusCode::CREATED);
+    let root = b["key"].as_str().unwrap().to_string();
+    assert_eq!(call(&app, "POST", "/admin/bootstrap", "", None).await.0, StatusCode::CONFLICT);
+    let (_, alice) = keys::issue(_ROOT, 100).unwrap();
+
+    // Roles: PUT creates, PUT again updates, GET reads back the number
+    assert_eq!(call(&app, "PUT", "/objects/1/roles/9", &root, Some(json!({"mask": 24}))).await.0, StatusCode::CREATED);
+    assert_eq!(call(&app, "PUT", "/objects/1/roles/9", &root, Some(json!({"mask": 792}))).await.0, StatusCode::NO_CONTENT);
+    let names = json!(["get_role", "check_role", "get_mask", "check_mask"]);
+    assert_eq!(call(&app, "GET", "/objects/1/roles/9", &root, None).await.1, json!({"mask": 792, "names": names}));
+    assert_eq!(call(&app, "GET", "/objects/1/roles/77", &root, None).await.0, StatusCode::NOT_FOUND);
+
+    // Grants and typed lists
+    assert_eq!(call(&app, "PUT", "/objects/1/subjects/100/roles/9", &root, None).await.0, StatusCode::NO_CONTENT);
+    assert_eq!(call(&app, "GET", "/subjects/100/grants", &root, None).await.1, json!([[1, 9]]));
+    assert_eq!(call(&app, "GET", "/objects/1/subjects/100/roles", &root, None).await.1, json!([9]));
+    assert_eq!(call(&app, "POST", "/check", &alice, Some(json!({"sub": 100, "obj": 1, "mask": 8}))).await.1, json!({"allowed": true}));
+    let items = json!([{"obj": 1, "mask": 8}, {"obj": 1, "mask": ALL_BITS}]);
+    assert_eq!(call(&app, "POST", "/check/many", &alice, Some(json!({"sub": 100, "items": items}))).await.1, json!({"allowed": [true, false]}));
+    assert_eq!(call(&app, "GET", "/subjects/100/objects/1/mask", &alice, None).await.1, json!({"mask": 792, "names": names}));
+    // Registered bits are named too
+    assert_eq!(call(&app, "PUT", "/scopes/1/bits/22", &root, Some(json!({"name": "publish"}))).await.0, StatusCode::NO_CONTENT);
+    assert_eq!(call(&app, "PUT", "/objects/1/roles/10", &root, Some(json!({"mask": (1u64 << 22) | 8}))).await.0, StatusCode::CREATED);
+    assert_eq!(call(&app, "GET", "/objects/1/roles/10", &root, None).await.1["names"], json!(["get_role", "publish"]));
+    assert_eq!(call(&app, "GET", "/subjects/100/objects/1/explain", &alice, None).await.1["sources"][0]["role"], json!(9));
+    // Other subjects' rights need the list permissions
+    assert_eq!(call(&app, "GET", &format!("/subjects/{_ROOT}/objects/1/explain"), &alice, None).await.0, StatusCode::FORBIDDEN);
+    assert_eq!(call(&app, "GET", &format!("/subjects/{_ROOT}/objects/1/mask"), &alice, None).await.0, StatusCode::FORBIDDEN);
+    assert_eq!(call(&app, "POST", "/check", &alice, Some(json!({"sub": _ROOT, "obj": 1, "mask": 8}))).await.0, StatusCode::FORBIDDEN);
+    assert_eq!(call(&app, "POST", "/check/many", &alice, Some(json!({"sub": _ROOT, "items": [{"obj": 1, "mask": 8}]}))).await.0, StatusCode::FORBIDDEN);
+    assert_eq!(call(&app, "GET", "/subjects/100/objects/1/explain", &root, None).await.0, StatusCode::OK);
+
+    // Lists page with ?limit= and resume from the x-next-cursor header
+    for sub in 101..104 { assert_eq!(call(&app, "PUT", &format!("/objects/1/subjects/{sub}/roles/9"), &root, None).await.0, StatusCode::NO_CONTENT); }
+    let req = |uri: String| Request::builder().uri(uri).header("authorization", format!("Bearer {root}")).body(Body::empty()).unwrap();
+    let res = app.clone().oneshot(req("/objects/1/subjects?limit=3".into())).await.unwrap();
+    let next = res.headers()["x-next-cursor"].to_str().unwrap().to_string();
+    assert_eq!(serde_json::from_slice::<Value>(&to_bytes(res.into_body(), usize::MAX).await.unwrap()).unwrap(), json!([[2, 1], [100, 9], [101, 9]]));
+    let res = app.clone().oneshot(req(format!("/objects/1/subjects?limit=3&after={next}"))).await.unwrap();
+    assert!(!res.headers().contains_key("x-next-cursor"));
+    assert_eq!(serde_json::from_slice::<Value>(&to_bytes(res.into_body(), usize::MAX).await.unwrap()).unwrap(), json!([[102, 9], [103, 9]]));
+    assert_eq!(call(&app, "GET", "/objects/1/subjects?after=zz", &root, None).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Status codes follow the error kind
+    assert_eq!(call(&app, "PUT", "/objects/1/subjects/101/roles/9", &alice, None).await.0, StatusCode::FORBIDDEN);
+    assert_eq!(call(&app, "GET", "/subjects/100/grants", "cbk_wrong", None).await, (StatusCode::UNAUTHORIZED, json!({"error": "Unauthorized"})));
+    assert_eq!(call(&app, "PUT", "/objects/1/parents/1", &root, Some(json!({"mask": 1}))).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(call(&app, "PUT", "/objects/1/roles/9", &root, Some(json!({"mask": "x"}))).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(call(&app, "POST", "/admin/clear", &alice, None).await.0, StatusCode::FORBIDDEN);
+
+    tenant_header(&app, &root, &alice).await;
+
+    assert_eq!(call(&app, "DELETE", "/objects/1/subjects/100/roles/9", &root, None).await.0, StatusCode::NO_CONTENT);
+    assert_eq!(call(&app, "GET", "/subjects/100/grants", &root, None).await.1, json!([]));
+    assert_eq!(call(&app, "POST", "/admin/clear", &root, None).await.0, StatusCode::NO_CONTENT);
+    assert_eq!(call(&app, "GET", "/subjects/100/grants", &root, None).await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(call(&router(Handle::new(8), false), "POST", "/admin/bootstrap", "", None).await.0, StatusCode::NOT_FOUND);
+    #[cfg(feature = "metrics")]
+    assert_eq!(call(&app, "GET", "/metrics", "", None).await.0, StatusCode::OK);
+}
+
+// Run from test_rest, as creating tenants needs its default-tenant keys
+async fn tenant_header(app: &Router, default_root: &str, alice: &str) {
+    if tenants().contains(&"acme".to_string()) { drop_tenant("acme").unwrap(); }
+    let acme = Some("acme");
+
+    // Bootstrapping with the header creates the tenant, and only the default tenant's root may
+    assert_eq!(call_in(app, acme, "POST", "/admin/bootstrap", "", None).await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(call_in(app, acme, "POST", "/admin/bootstrap", alice, None).await.0, StatusCode::FORBIDDEN);
+    assert!(!tenants().contains(&"acme".to_string()));
+    let (s, b) = call_in(app, acme, "POST", "/admin/bootstrap", default_root, None).await;
+    assert_eq!(s, StatusCode::CREATED);
+    let root = b["key"].as_str().unwrap().to_string();
+    assert_eq!(call_in(app, acme, "PUT", "/objects/1/roles/9", &root, Some(json!({"mask": 24}))).await.0, StatusCode::CREATED);
+    assert_eq!(call_in(app, acme, "GET", "/objects/1/roles/9", &root, None).await.1["mask"], json!(24));
+
+    // The key belongs to acme only
+    assert_eq!(call(app, "GET", "/objects/1/roles/9", &root, None).await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(call_in(app, Some("nobody"), "GET", "/objects/1/roles/9", &root, None).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(call_in(app, Some("a#b"), "GET", "/objects/1/roles/9", &root, None).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+    drop_tenant("acme").unwrap();
+}
+
+#[tokio::test] async fn test_openapi_covers_routes() {
+    let spec = serde_json::to_value(openapi()).unwrap();
+    let documented: Vec<(String, String)> = spec["paths"].as_object().unwrap().iter()
+        .flat_map(|(p, ops)| ops.as_object().unwrap().keys().map(move |m| (m.to_uppercase(), p.clone()))).collect();
+    let served: Vec<(String, String)> = routes().into_iter().map(|(m, p)| {
+        let p = p.split('/').map(|s| s.strip_prefix(':').map_or(s.to_string(), |v| format!("{{{v}}}"))).collect::<Vec<_>>().join("/");
+        (m.to_string(), p)
+    }).collect();
+    for r in &served { assert!(documented.contains(r), "{r:?} is served but not in the OpenAPI document"); }
+    for r in &documented { assert!(served.contains(r), "{r:?} is documented but not served"); }
+    assert!(spec["components"]["schemas"]["Explanation"].is_object());
+    assert_eq!(spec["paths"]["/objects/{obj}/roles/{role}"]["put"]["responses"]["403"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/ErrorBody");
+
+    init("target/test_db_http").unwrap();
+    assert_eq!(call(&router(Handle::new(8), false), "GET", "/openapi.json", "", None).await, (StatusCode::OK, spec));
+}
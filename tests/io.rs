@@ -0,0 +1,82 @@
+#![cfg(feature = "io")]
+
+use capbit::{io::*, *};
+
+fn seed(root: u64) -> Vec<u64> {
+    set_parent_named(root, "project", "_system", ALL_BITS).unwrap();
+    set_parent_named(root, "doc", "project", VIEWER_BITS).unwrap();
+    set_parent_named(root, "team", "_system", ALL_BITS).unwrap();
+    let (project, doc) = (intern("project").unwrap(), intern("doc").unwrap());
+    let (alice, bob, team) = (intern("alice").unwrap(), intern("bob").unwrap(), intern("team").unwrap());
+    create(root, project, _EDITOR, EDITOR_BITS).unwrap();
+    create(root, project, _VIEWER, VIEWER_BITS).unwrap();
+    grant(root, team, project, _EDITOR).unwrap();
+    add_member(root, team, alice).unwrap();
+    inherit(root, bob, project, _VIEWER, alice).unwrap();
+    define_bit(root, _SYSTEM, 22, "read", "Read documents").unwrap();
+    vec![_SYSTEM, _ROOT, project, doc, alice, bob, team]
+}
+
+fn masks(ids: &[u64]) -> Vec<u64> {
+    ids.iter().flat_map(|&s| ids.iter().map(move |&o| get_mask(s, o).unwrap())).collect()
+}
+
+#[test] fn test_export_import() {
+    init("target/test_db_io").unwrap();
+    clear().unwrap();
+    let (_, root) = bootstrap().unwrap();
+    let ids = seed(root);
+    let before = masks(&ids);
+    let mut dump = Vec::new();
+    let n = export(&mut dump).unwrap();
+    assert!(String::from_utf8_lossy(&dump).starts_with(r#"{"t":"header","format":"capbit","version":1}"#));
+
+    // Replace restores the exact state, reverse indexes and the id counter included
+    clear().unwrap();
+    let report = import(&dump[..], ImportMode::Replace).unwrap();
+    assert_eq!(report, ImportReport { records: n, conflicts: vec![] });
+    assert_eq!(masks(&ids), before);
+    assert_eq!(list_members(root, ids[6]).unwrap(), vec![ids[4]]);
+    assert_eq!(list_inherits_from_parent(root, ids[4]).unwrap(), vec![(ids[2], _VIEWER, ids[5])]);
+    assert_eq!(names_from_mask(ids[3], 1 << 22).unwrap(), vec!["read"]);
+    assert!(intern("carol").unwrap() > *ids.iter().max().unwrap());
+    let mut again = Vec::new();
+    export(&mut again).unwrap();
+    assert_eq!(String::from_utf8_lossy(&again).lines().count(), n + 2);
+
+    // Dry run reports conflicts and writes nothing; merge applies the file's values
+    let edited = String::from_utf8(dump).unwrap()
+        .replace(&format!(r#""obj":{},"role":{_EDITOR},"mask":{EDITOR_BITS}"#, ids[2]), &format!(r#""obj":{},"role":{_EDITOR},"mask":{VIEWER_BITS}"#, ids[2]));
+    let report = import(edited.as_bytes(), ImportMode::DryRun).unwrap();
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].current, Record::Object { obj: ids[2], role: _EDITOR, mask: EDITOR_BITS });
+    assert_eq!(get_mask(ids[4], ids[2]).unwrap(), EDITOR_BITS);
+    import(edited.as_bytes(), ImportMode::Merge).unwrap();
+    assert_eq!(get_mask(ids[4], ids[2]).unwrap(), VIEWER_BITS);
+    assert!(intern("carol").is_ok());
+
+    // Replacing keeps API keys, which exports do not carry
+    #[cfg(feature = "keys")]
+    {
+        let (_, token) = keys::issue(root, ids[4]).unwrap();
+        import(edited.as_bytes(), ImportMode::Replace).unwrap();
+        assert_eq!(keys::authenticate(&token).unwrap(), ids[4]);
+    }
+
+    // Records that close a cycle abort the import, whichever graph it is in
+    for cyclic in [format!(r#"{{"t":"parent","obj":{_SYSTEM},"parent":{},"mask":1}}"#, ids[3]),
+                   format!(r#"{{"t":"member","group":{},"member":{}}}"#, ids[4], ids[6]),
+                   format!(r#"{{"t":"inherit","sub":{},"obj":{},"role":{_EDITOR},"parent":{}}}"#, ids[4], ids[2], ids[5])] {
+        let err = import(format!("{edited}{cyclic}\n").as_bytes(), ImportMode::Merge).unwrap_err();
+        assert_eq!((err.kind(), err.0.split(':').next()), (ErrorKind::Invalid, Some("Cycle")));
+    }
+    assert!(get_parent(root, _SYSTEM, ids[3]).unwrap().is_none());
+
+    // A bad line aborts the whole import
+    let bad = format!("{edited}{{\"t\":\"grant\",\"sub\":9}}\n");
+    let lines = bad.lines().count();
+    assert_eq!(import(bad.as_bytes(), ImportMode::Replace).unwrap_err().0.split(':').next(), Some("Invalid"));
+    assert!(import(bad.as_bytes(), ImportMode::Replace).unwrap_err().0.contains(&format!("line {lines}")));
+    assert!(import(&b"{\"t\":\"grant\",\"sub\":1,\"obj\":1,\"role\":1}\n"[..], ImportMode::Merge).is_err());
+    assert_eq!(get_mask(ids[4], ids[2]).unwrap(), VIEWER_BITS);
+}
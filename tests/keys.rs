@@ -0,0 +1,51 @@
+#![cfg(feature = "keys")]
+
+use capbit::*;
+
+#[test] fn test_keys() {
+    init("target/test_db_keys").unwrap();
+    clear().unwrap();
+    let (sys, root) = bootstrap().unwrap();
+    let alice = intern("alice").unwrap();
+    grant(root, alice, sys, _VIEWER).unwrap();
+
+    let (id, token) = keys::issue(root, alice).unwrap();
+    assert!(token.starts_with("cbk_") && token.len() == 68);
+    assert_eq!(keys::authenticate(&token).unwrap(), alice);
+    assert_eq!(keys::authenticate("cbk_guess").unwrap_err().0, "Unauthorized");
+
+    // Subjects manage their own keys, only admins manage other subjects' keys
+    let (own, own_token) = keys::issue(alice, alice).unwrap();
+    assert_eq!(keys::list(alice, alice).unwrap().len(), 2);
+    assert!(keys::list(alice, alice).unwrap().contains(&own));
+    assert_eq!(keys::issue(alice, root).unwrap_err().0, "Denied");
+    assert_eq!(keys::list(alice, root).unwrap_err().0, "Denied");
+    assert!(keys::list(root, root).unwrap().is_empty());
+
+    // Admins can not mint keys for other subjects, even ones they outrank on _SYSTEM, as those may
+    // hold more than the admin elsewhere; they can still revoke them
+    let (admin, owner) = (intern("admin").unwrap(), intern("owner").unwrap());
+    grant(root, admin, sys, _ADMIN).unwrap();
+    grant(root, owner, sys, _OWNER).unwrap();
+    assert_eq!(keys::issue(admin, root).unwrap_err().0, "Denied");
+    assert_eq!(keys::issue(admin, owner).unwrap_err().0, "Denied");
+    set_parent_named(root, "doc", "_system", ALL_BITS).unwrap();
+    let (peer, doc) = (intern("peer").unwrap(), intern("doc").unwrap());
+    grant(root, peer, sys, _VIEWER).unwrap();
+    create(root, doc, _OWNER, ALL_BITS).unwrap();
+    grant(root, peer, doc, _OWNER).unwrap();
+    set_parent(root, doc, sys, VIEWER_BITS).unwrap();
+    assert!(get_mask(peer, doc).unwrap() & !get_mask(admin, doc).unwrap() != 0);
+    assert_eq!(keys::issue(admin, peer).unwrap_err().0, "Denied");
+    let (minted, _) = keys::issue(root, peer).unwrap();
+    keys::revoke(admin, minted).unwrap();
+
+    keys::revoke(alice, id).unwrap();
+    assert_eq!(keys::authenticate(&token).unwrap_err().0, "Unauthorized");
+    assert_eq!(keys::revoke(root, id).unwrap_err().0, format!("Unknown: {id:016x}"));
+    assert_eq!(keys::list(root, alice).unwrap(), vec![own]);
+
+    // Keys go with the rest of the store
+    clear().unwrap();
+    assert_eq!(keys::authenticate(&own_token).unwrap_err().0, "Unauthorized");
+}
@@ -1,17 +1,19 @@
 use capbit::*;
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard};
 
 static LOCK: Mutex<()> = Mutex::new(());
 
-fn setup() -> (u64, u64) {
-    let _l = LOCK.lock();
+// Tests share one keyspace, so the guard is held for the whole test
+fn setup() -> (MutexGuard<'static, ()>, u64, u64) {
+    let l = LOCK.lock().unwrap_or_else(|e| e.into_inner());
     init("target/test_db").unwrap();
     clear().unwrap();
-    bootstrap().unwrap()
+    let (sys, root) = bootstrap().unwrap();
+    (l, sys, root)
 }
 
 #[test] fn test_bootstrap() {
-    let (sys, root) = setup();
+    let (_l, sys, root) = setup();
     assert_eq!(sys, _SYSTEM);
     assert_eq!(root, _ROOT);
     assert!(check(root, sys, ALL_BITS).unwrap());
@@ -19,7 +21,7 @@ fn setup() -> (u64, u64) {
 }
 
 #[test] fn test_grant_revoke() {
-    let (sys, root) = setup();
+    let (_l, sys, root) = setup();
     grant(root, 10, sys, _VIEWER).unwrap();
     assert!(check_subject(10, sys, _VIEWER).unwrap());
     assert!(check(10, sys, VIEWER_BITS).unwrap());
@@ -29,7 +31,7 @@ fn setup() -> (u64, u64) {
 }
 
 #[test] fn test_objects_crud() {
-    let (sys, root) = setup();
+    let (_l, sys, root) = setup();
     create(root, sys, 100, 0xFF).unwrap();
     assert_eq!(get_object(root, sys, 100).unwrap(), Some(0xFF));
     assert!(check_object(root, sys, 100).unwrap());
@@ -40,7 +42,7 @@ fn setup() -> (u64, u64) {
 }
 
 #[test] fn test_inheritance() {
-    let (sys, root) = setup();
+    let (_l, sys, root) = setup();
     grant(root, 20, sys, _ADMIN).unwrap();
     inherit(root, 10, sys, _ADMIN, 20).unwrap();
     grant(root, 10, sys, _ADMIN).unwrap();
@@ -52,7 +54,7 @@ fn setup() -> (u64, u64) {
 }
 
 #[test] fn test_permissions() {
-    let (sys, root) = setup();
+    let (_l, sys, root) = setup();
     assert!(grant(99, 10, sys, _VIEWER).is_err());
     grant(root, 99, sys, _VIEWER).unwrap();
     assert!(grant(99, 10, sys, _VIEWER).is_err());
@@ -60,3 +62,357 @@ fn setup() -> (u64, u64) {
     grant(99, 10, sys, _VIEWER).unwrap();
     assert!(check(10, sys, VIEWER_BITS).unwrap());
 }
+
+#[test] fn test_object_hierarchy() {
+    let (_l, sys, root) = setup();
+    let (org, project, repo, doc, alice) = (100, 101, 102, 103, 10);
+    assert!(create(root, org, _EDITOR, EDITOR_BITS).is_err());
+    set_parent(root, org, sys, ALL_BITS).unwrap();
+    create(root, org, _EDITOR, EDITOR_BITS | 1 << 30).unwrap();
+    grant(root, alice, org, _EDITOR).unwrap();
+    set_parent(root, project, org, ALL_BITS | 1 << 30).unwrap();
+    assert!(!check(alice, repo, 1 << 30).unwrap());
+    set_parent(root, repo, project, ALL_BITS | 1 << 30).unwrap();
+    set_parent(root, doc, project, VIEWER_BITS).unwrap();
+    assert_eq!(get_mask(alice, repo).unwrap(), EDITOR_BITS | 1 << 30);
+    assert_eq!(get_mask(alice, doc).unwrap(), EDITOR_BITS & VIEWER_BITS);
+    assert_eq!(get_mask(root, doc).unwrap(), VIEWER_BITS);
+    assert_eq!(list_parents(root, repo).unwrap(), vec![(project, ALL_BITS | 1 << 30)]);
+    assert_eq!(list_children(root, project).unwrap(), vec![(repo, ALL_BITS | 1 << 30), (doc, VIEWER_BITS)]);
+    assert!(set_parent(root, org, repo, ALL_BITS).is_err());
+    assert!(set_parent(root, org, org, ALL_BITS).is_err());
+    assert!(set_parent(alice, sys, org, ALL_BITS).is_err());
+    assert!(set_parent(alice, 104, org, ALL_BITS).is_err());
+    remove_parent(root, repo, project).unwrap();
+    assert_eq!(get_mask(alice, repo).unwrap(), 0);
+    assert_eq!(get_mask(root, repo).unwrap(), 0);
+    // Holding the object is not enough to attach it under a parent the actor can not create in
+    grant(root, alice, project, _OWNER).unwrap();
+    assert_eq!(set_parent(alice, project, sys, ALL_BITS).unwrap_err().0, "Denied");
+    assert!(!list_children(root, sys).unwrap().contains(&(project, ALL_BITS)));
+}
+
+#[test] fn test_groups() {
+    let (_l, sys, root) = setup();
+    let (doc, eng, staff, alice, bob) = (100, 200, 201, 10, 11);
+    for o in [doc, eng, staff] { set_parent(root, o, sys, ALL_BITS).unwrap(); }
+    create(root, doc, _EDITOR, EDITOR_BITS).unwrap();
+    grant(root, staff, doc, _EDITOR).unwrap();
+    add_member(root, eng, alice).unwrap();
+    assert!(!check(alice, doc, EDITOR_BITS).unwrap());
+    add_member(root, staff, eng).unwrap();
+    assert!(check(alice, doc, EDITOR_BITS).unwrap());
+    assert!(!check(bob, doc, EDITOR_BITS).unwrap());
+    assert!(check_member(eng, alice).unwrap());
+    assert_eq!(list_members(root, staff).unwrap(), vec![eng]);
+    assert_eq!(list_groups(root, alice).unwrap(), vec![eng]);
+    assert!(add_member(root, eng, staff).is_err());
+    assert!(add_member(root, eng, eng).is_err());
+    assert!(add_member(alice, eng, bob).is_err());
+    remove_member(root, staff, eng).unwrap();
+    assert_eq!(get_mask(alice, doc).unwrap(), 0);
+}
+
+#[test] fn test_group_claim_escalation() {
+    let (_l, sys, root) = setup();
+    let (project, admins, alice, bob) = (100, 300, 10, 11);
+    set_parent(root, project, sys, ALL_BITS).unwrap();
+    create(root, project, 9, 1 << 10 | 1 << 14).unwrap();  // create_object | grant
+    grant(root, alice, project, 9).unwrap();
+    // A group with grants and members but no roles, grantees or parents of its own
+    set_parent(root, admins, sys, ALL_BITS).unwrap();
+    grant(root, admins, sys, _ADMIN).unwrap();
+    add_member(root, admins, bob).unwrap();
+    remove_parent(root, admins, sys).unwrap();
+
+    assert_eq!(set_parent(alice, admins, project, ALL_BITS).unwrap_err().0, "Denied");
+    assert_eq!(add_member(alice, admins, alice).unwrap_err().0, "Denied");
+    assert!(!check(alice, sys, ADMIN_BITS).unwrap());
+
+    // Even with _GRANT on the group itself, membership needs the right to grant what it holds
+    let staff = 302;
+    set_parent(root, staff, project, ALL_BITS).unwrap();
+    grant(root, staff, sys, _ADMIN).unwrap();
+    assert_eq!(add_member(alice, staff, alice).unwrap_err().0, "Denied");
+    assert!(!check(alice, sys, ADMIN_BITS).unwrap());
+
+    // A fresh group alice claims herself confers nothing and stays hers to fill
+    set_parent(alice, 301, project, ALL_BITS).unwrap();
+    add_member(alice, 301, bob).unwrap();
+
+    // Moving an object under a parent of her own may pass on no more than she holds on it
+    let (doc, mover) = (303, 12);
+    set_parent(root, doc, sys, ALL_BITS).unwrap();
+    create(root, doc, 8, 1 << 18).unwrap();  // set_inherit
+    grant(root, mover, doc, 8).unwrap();
+    grant(root, mover, project, 9).unwrap();
+    let before = get_mask(mover, doc).unwrap();
+    assert_eq!(set_parent(mover, doc, project, ALL_BITS).unwrap_err().0, "Denied");
+    assert_eq!(list_parents(root, doc).unwrap(), vec![(sys, ALL_BITS)]);
+    set_parent(mover, doc, project, before).unwrap();
+    assert_eq!(list_parents(root, doc).unwrap(), vec![(sys, ALL_BITS), (project, before)]);
+    assert_eq!(get_mask(mover, doc).unwrap(), before);
+
+    // Interned ids come in sequence, so one can only be claimed in the write that interns it
+    let unclaimed = intern("doc:unclaimed").unwrap();
+    assert_eq!(set_parent(alice, unclaimed, project, ALL_BITS).unwrap_err().0, "Denied");
+    assert_eq!(set_parent(alice, unclaimed + 1, project, ALL_BITS).unwrap_err().0, "Denied");
+    set_parent_named(root, "doc:claimed", "_system", ALL_BITS).unwrap();
+    assert_eq!(list_parents(root, lookup("doc:claimed").unwrap().unwrap()).unwrap(), vec![(sys, ALL_BITS)]);
+}
+
+#[test] fn test_bit_registry() {
+    let (_l, sys, root) = setup();
+    let (docs, doc, server) = (100, 101, 102);
+    for o in [docs, server] { set_parent(root, o, sys, ALL_BITS).unwrap(); }
+    set_parent(root, doc, docs, ALL_BITS).unwrap();
+    define_bit(root, sys, 22, "read", "Read content").unwrap();
+    define_bit(root, docs, 23, "write", "Edit a document").unwrap();
+    define_bit(root, server, 23, "restart", "Restart the server").unwrap();
+    assert!(define_bit(root, docs, 3, "oops", "").is_err());
+    assert!(define_bit(root, docs, 24, "write", "").is_err());
+    assert!(define_bit(root, docs, 24, "bad name", "").is_err());
+    // Reserved names keep their meaning
+    assert_eq!(define_bit(root, docs, 24, "grant", "").unwrap_err().0, "Invalid");
+    assert_eq!(define_bit(root, docs, 24, "ADMIN_BITS", "").unwrap_err().0, "Invalid");
+    assert_eq!(parse_mask(doc, "grant").unwrap(), 1 << 14);
+    assert_eq!(mask_from_names(doc, &["read", "write"]).unwrap(), 1 << 22 | 1 << 23);
+    assert_eq!(mask_from_names(server, &["restart", "grant"]).unwrap(), 1 << 23 | 1 << 14);
+    assert!(mask_from_names(server, &["write"]).is_err());
+    assert_eq!(names_from_mask(doc, 1 << 23 | 1 << 40 | 1).unwrap(), ["create_role", "write", "0x10000000000"]);
+    assert_eq!(list_bits(root, docs).unwrap(), vec![(23, "write".into(), "Edit a document".into())]);
+    define_bit(root, docs, 23, "edit", "").unwrap();
+    assert!(mask_from_names(doc, &["write"]).is_err());
+    undefine_bit(root, docs, 23).unwrap();
+    assert!(list_bits(root, docs).unwrap().is_empty());
+}
+
+#[test] fn test_named_ids() {
+    let (_l, _, root) = setup();
+    set_parent_named(root, "doc:report-q3", "_system", ALL_BITS).unwrap();
+    let report = lookup("doc:report-q3").unwrap().unwrap();
+    assert!(report >= NAMED_BASE);
+    assert_eq!(intern("doc:report-q3").unwrap(), report);
+    assert_eq!(lookup("_root").unwrap(), Some(root));
+    assert_eq!(lookup("user:bob@x.com").unwrap(), None);
+    create(root, report, _EDITOR, EDITOR_BITS).unwrap();
+    grant_named(root, "user:alice@x.com", "doc:report-q3", "editor").unwrap();
+    let alice = lookup("user:alice@x.com").unwrap().unwrap();
+    assert_ne!(alice, report);
+    assert_eq!(name_of(alice).unwrap().as_deref(), Some("user:alice@x.com"));
+    assert!(check(alice, report, EDITOR_BITS).unwrap());
+    assert!(check_named("user:alice@x.com", "doc:report-q3", EDITOR_BITS).unwrap());
+    assert!(!check_named("user:bob@x.com", "doc:report-q3", EDITOR_BITS).unwrap());
+    assert_eq!(list_grants_named(root, "user:alice@x.com").unwrap(), vec![("doc:report-q3".into(), "editor".into())]);
+    assert_eq!(list_subjects_named(root, "doc:report-q3").unwrap(), vec![("user:alice@x.com".into(), "editor".into())]);
+    revoke_named(root, "user:alice@x.com", "doc:report-q3", "editor").unwrap();
+    // A denied call interns nothing and allocates no id
+    assert_eq!(grant_named(alice, "user:mallory@x.com", "doc:report-q3", "spy").unwrap_err().0, "Denied");
+    assert_eq!(grant_named(alice, "user:mallory@x.com", "doc:missing", "editor").unwrap_err().0, "Denied");
+    assert_eq!(lookup("user:mallory@x.com").unwrap(), None);
+    assert_eq!(lookup("doc:missing").unwrap(), None);
+    assert_eq!(lookup_role("spy").unwrap(), None);
+    assert_eq!(intern("user:carol@x.com").unwrap(), alice + 1);
+    assert!(!check_named("user:alice@x.com", "doc:report-q3", EDITOR_BITS).unwrap());
+    assert_eq!(intern_role("commenter").unwrap(), NAMED_BASE);
+    assert_eq!(role_name(_EDITOR).unwrap().as_deref(), Some("editor"));
+}
+
+#[test] fn test_write_batch() {
+    let (_l, sys, root) = setup();
+    let (project, doc, alice, bob) = (100, 101, 10, 11);
+    WriteBatch::new(root)
+        .set_parent(project, sys, ALL_BITS)
+        .create(project, _EDITOR, EDITOR_BITS)
+        .create(project, _ADMIN, ADMIN_BITS)
+        .grant(alice, project, _ADMIN)
+        .set_parent(doc, project, ALL_BITS)
+        .grant(bob, project, _EDITOR)
+        .commit().unwrap();
+    assert!(check(alice, project, ADMIN_BITS).unwrap());
+    assert!(check(bob, doc, EDITOR_BITS).unwrap());
+
+    let results = WriteBatch::new(alice)
+        .grant(12, project, _EDITOR)
+        .revoke(alice, project, _ADMIN)
+        .grant(13, project, _EDITOR)
+        .commit().unwrap_err();
+    assert!(results[0].is_ok() && results[1].is_ok());
+    assert_eq!(results[2].as_ref().unwrap_err().0, "Denied");
+    assert!(!check_subject(12, project, _EDITOR).unwrap());
+    assert!(check_subject(alice, project, _ADMIN).unwrap());
+    assert!(WriteBatch::new(root).commit().is_ok());
+}
+
+#[test] fn test_check_many() {
+    let (_l, sys, root) = setup();
+    let (folder, team, alice) = (100, 200, 10);
+    let docs: Vec<u64> = (1000..1020).collect();
+    let mut b = WriteBatch::new(root).set_parent(folder, sys, ALL_BITS).set_parent(team, sys, ALL_BITS)
+        .create(folder, _VIEWER, VIEWER_BITS).grant(team, folder, _VIEWER).add_member(team, alice);
+    for &d in &docs { b = b.set_parent(d, folder, if d % 2 == 0 { VIEWER_BITS } else { 0 }); }
+    b.commit().unwrap();
+    let mut objs = docs.clone();
+    objs.extend([sys, folder, 999, 1000]);
+    let masks = get_masks(alice, &objs).unwrap();
+    for (o, m) in objs.iter().zip(&masks) { assert_eq!(*m, get_mask(alice, *o).unwrap()); }
+    let reqs: Vec<(u64, u64)> = objs.iter().map(|&o| (o, VIEWER_BITS)).collect();
+    let allowed = check_many(alice, &reqs).unwrap();
+    assert_eq!(allowed.iter().filter(|a| **a).count(), 12);
+    let visible = filter_authorized(alice, VIEWER_BITS, docs.iter().copied()).unwrap();
+    assert_eq!(visible, docs.iter().copied().filter(|d| d % 2 == 0).collect::<Vec<_>>());
+}
+
+#[test] fn test_cache_invalidation() {
+    let (_l, sys, root) = setup();
+    let (folder, doc, team, alice, bob) = (100, 101, 200, 10, 11);
+    WriteBatch::new(root).set_parent(folder, sys, ALL_BITS).set_parent(doc, folder, ALL_BITS).set_parent(team, sys, ALL_BITS)
+        .create(folder, _EDITOR, EDITOR_BITS).commit().unwrap();
+    set_cache_capacity(64);
+    let before = cache_stats();
+    assert_eq!(get_mask(alice, doc).unwrap(), 0);
+    assert_eq!(get_mask(alice, doc).unwrap(), 0);
+    assert_eq!(cache_stats().hits, before.hits + 1);
+    grant(root, team, folder, _EDITOR).unwrap();
+    add_member(root, team, alice).unwrap();
+    assert_eq!(get_mask(alice, doc).unwrap(), EDITOR_BITS);
+    grant(root, bob, folder, _VIEWER).unwrap();
+    inherit(root, bob, folder, _VIEWER, alice).unwrap();
+    assert_eq!(get_mask(bob, doc).unwrap(), _VIEWER);
+    grant(root, alice, folder, _EDITOR).unwrap();
+    assert_eq!(get_mask(bob, doc).unwrap(), _VIEWER | EDITOR_BITS);
+    update(root, folder, _EDITOR, VIEWER_BITS).unwrap();
+    assert_eq!(get_mask(alice, doc).unwrap(), VIEWER_BITS);
+    remove_parent(root, doc, folder).unwrap();
+    assert_eq!(get_mask(alice, doc).unwrap(), 0);
+    revoke(root, alice, folder, _EDITOR).unwrap();
+    assert_eq!(get_mask(alice, folder).unwrap(), VIEWER_BITS);
+    remove_member(root, team, alice).unwrap();
+    assert_eq!(get_mask(alice, folder).unwrap(), 0);
+    set_cache_capacity(1);
+    get_mask(alice, sys).unwrap();
+    get_mask(bob, sys).unwrap();
+    assert_eq!(cache_stats().entries, 1);
+    set_cache_capacity(0);
+    assert_eq!(cache_stats().entries, 0);
+}
+
+#[test] fn test_durability() {
+    let (_l, sys, root) = setup();
+    assert_eq!(durability(), Durability::Buffer);
+    durable(Durability::SyncAll, || {
+        assert_eq!(durability(), Durability::SyncAll);
+        grant(root, 10, sys, _ADMIN)
+    }).unwrap();
+    assert!(sync_count() > 0);
+    assert_eq!(durability(), Durability::Buffer);
+    WriteBatch::new(root).durability(Durability::SyncData).revoke(10, sys, _ADMIN).commit().unwrap();
+
+    set_group_commit(Some(std::time::Duration::from_millis(2)));
+    set_durability(Durability::SyncData);
+    let synced = sync_count();
+    std::thread::scope(|s| {
+        for t in 0..8u64 {
+            s.spawn(move || for i in 0..5 { grant(root, 100 + t * 10 + i, sys, _VIEWER).unwrap(); });
+        }
+    });
+    // 40 commits share far fewer fsyncs
+    assert!(sync_count() - synced < 40, "{} fsyncs", sync_count() - synced);
+    set_durability(Durability::Buffer);
+    set_group_commit(None);
+    assert_eq!(list_subjects(root, sys).unwrap().len(), 41);
+}
+
+#[test] fn test_verify() {
+    let (_l, sys, root) = setup();
+    grant(root, 10, sys, _VIEWER).unwrap();
+    grant(root, 11, sys, _EDITOR).unwrap();
+    inherit(root, 10, sys, _VIEWER, 11).unwrap();
+    inherit(root, 10, sys, _VIEWER, 12).unwrap();
+    assert_eq!(list_inherits_from_parent(root, 11).unwrap(), vec![]);
+    assert_eq!(verify(false).unwrap(), VerifyReport::default());
+
+    inherit(root, 13, sys, _VIEWER, 10).unwrap();
+    inherit(root, 12, sys, _VIEWER, 13).unwrap();
+    grant(root, 14, sys, 99).unwrap();
+    let issues = verify(false).unwrap().issues;
+    assert!(issues.iter().all(|i| !i.repairable()));
+    assert!(issues.contains(&Issue::DanglingInherit { sub: 13, obj: sys, role: _VIEWER, parent: 10 }));
+    assert!(issues.contains(&Issue::UndeclaredRole { sub: 14, obj: sys, role: 99 }));
+    assert!(issues.contains(&Issue::InheritCycle { obj: sys, subs: vec![10, 12, 13, 10] }));
+    clear().unwrap();
+    assert_eq!(verify(false).unwrap(), VerifyReport::default());
+}
+
+#[test] fn test_explain() {
+    let (_l, sys, root) = setup();
+    let (project, doc, team, alice, bob) = (500, 501, 502, 10, 11);
+    set_parent(root, project, sys, ALL_BITS).unwrap();
+    set_parent(root, doc, project, VIEWER_BITS | 1 << 22).unwrap();
+    set_parent(root, team, sys, ALL_BITS).unwrap();
+    create(root, project, _EDITOR, EDITOR_BITS | 1 << 22).unwrap();
+    create(root, project, _VIEWER, VIEWER_BITS).unwrap();
+    grant(root, team, project, _EDITOR).unwrap();
+    add_member(root, team, alice).unwrap();
+    grant(root, bob, project, _VIEWER).unwrap();
+    inherit(root, bob, project, _VIEWER, team).unwrap();
+
+    let e = explain(alice, doc).unwrap();
+    assert_eq!(e.mask, (EDITOR_BITS | 1 << 22) & (VIEWER_BITS | 1 << 22));
+    assert_eq!(e.sources, vec![Source { principal: team, holder: team, obj: project, role: _EDITOR, mask: e.mask, path: vec![doc, project] }]);
+    let e = explain(bob, project).unwrap();
+    assert_eq!(e.sources.iter().map(|s| (s.holder, s.role)).collect::<Vec<_>>(), vec![(bob, _VIEWER), (team, _EDITOR)]);
+    for s in [root, team, alice, bob, 99] {
+        for o in [sys, project, doc, team] { assert_eq!(explain(s, o).unwrap().mask, get_mask(s, o).unwrap()); }
+    }
+
+    define_bit(root, sys, 22, "publish", "").unwrap();
+    assert_eq!(parse_mask(doc, "VIEWER_BITS|0x800000|publish").unwrap(), VIEWER_BITS | 1 << 23 | 1 << 22);
+    assert_eq!(parse_mask(doc, " grant | 16 ").unwrap(), 1 << 14 | 16);
+    assert_eq!(parse_mask(doc, "VIEWER_BITS|nope").unwrap_err().0, "Unknown: nope");
+    assert!(parse_mask(doc, "0xZZ").is_err());
+}
+
+#[test] fn test_watch() {
+    let (_l, sys, root) = setup();
+    let seen = std::sync::Arc::new(Mutex::new(Vec::new()));
+    let sink = seen.clone();
+    // Unsubscribes at the first commit after the test drops its handle
+    watch::subscribe(move |c| { sink.lock().unwrap().push(c.clone()); std::sync::Arc::strong_count(&sink) > 1 });
+
+    create(root, sys, 9, 0x30).unwrap();
+    WriteBatch::new(root).grant(10, sys, 9).set_parent(20, sys, ALL_BITS).add_member(20, 10).commit().unwrap();
+    assert!(grant(10, 11, sys, 9).is_err());
+    revoke(root, 10, sys, 9).unwrap();
+    let commits = seen.lock().unwrap().clone();
+    assert_eq!(commits.len(), 3);
+    assert!(commits.windows(2).all(|w| w[1].seq == w[0].seq + 1));
+    assert_eq!(commits[0].events, vec![watch::Event::Role { obj: sys, role: 9, mask: Some(0x30) }]);
+    assert_eq!(commits[1].events.len(), 3);
+    assert!(commits[1].events.contains(&watch::Event::Member { group: 20, member: 10, added: true }));
+    assert_eq!(commits[2].events, vec![watch::Event::Grant { sub: 10, obj: sys, role: 9, granted: false }]);
+}
+
+#[test] fn test_pagination() {
+    let (_l, sys, root) = setup();
+    for sub in 10..17 { grant(root, sub, sys, _VIEWER).unwrap(); }
+    let all = list_subjects(root, sys).unwrap();
+    let first = list_subjects_page(root, sys, None, 3).unwrap();
+    assert_eq!(first.items, all[..3]);
+    let cursor: Cursor = first.next.unwrap().to_string().parse().unwrap();
+    let second = list_subjects_page(root, sys, Some(cursor), 3).unwrap();
+    assert_eq!(second.items, all[3..6]);
+
+    // A grant landing before the cursor shifts nothing on later pages
+    grant(root, 1, sys, _VIEWER).unwrap();
+    let rest = list_subjects_page(root, sys, second.next, 3).unwrap();
+    assert_eq!((rest.items, rest.next), (all[6..].to_vec(), None));
+
+    let walked: Vec<_> = iter_pages(2, |after, limit| list_subjects_page(root, sys, after, limit)).collect::<Result<_>>().unwrap();
+    assert_eq!(walked, list_subjects(root, sys).unwrap());
+    assert!(list_subjects_page(10, sys, None, 2).is_err());
+    assert!(iter_pages(2, |after, limit| list_subjects_page(10, sys, after, limit)).next().unwrap().is_err());
+    // Cursors only resume the list they came from
+    assert_eq!(list_subjects_page(root, sys, Some("00".parse().unwrap()), 2).unwrap_err().0, "Invalid");
+    assert!("xyz".parse::<Cursor>().is_err());
+}
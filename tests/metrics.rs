@@ -0,0 +1,61 @@
+#![cfg(feature = "metrics")]
+
+use capbit::{metrics::{render, set_recorder, Recorder}, *};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Seen { checks: Vec<bool>, hops: Vec<u64>, denied: Vec<String>, written: Vec<(String, u64)>, cache: Vec<bool> }
+
+struct Forward(Arc<Mutex<Seen>>);
+
+impl Recorder for Forward {
+    fn check(&self, _: std::time::Duration, cached: bool) { self.0.lock().unwrap().checks.push(cached); }
+    fn hops(&self, hops: u64) { self.0.lock().unwrap().hops.push(hops); }
+    fn denied(&self, op: &str) { self.0.lock().unwrap().denied.push(op.into()); }
+    fn written(&self, partition: &str, keys: u64) { self.0.lock().unwrap().written.push((partition.into(), keys)); }
+    fn cache(&self, hit: bool) { self.0.lock().unwrap().cache.push(hit); }
+}
+
+#[test] fn test_metrics() {
+    init("target/test_db_metrics").unwrap();
+    clear().unwrap();
+    for t in tenants() { drop_tenant(&t).unwrap(); }
+    let (sys, root) = bootstrap().unwrap();
+    let (alice, bob, doc) = (100, 101, 200);
+    set_parent(root, doc, sys, ALL_BITS).unwrap();
+    grant(root, alice, sys, _VIEWER).unwrap();
+    grant(root, bob, sys, _VIEWER).unwrap();
+    inherit(root, bob, sys, _VIEWER, alice).unwrap();
+
+    let seen = Arc::new(Mutex::new(Seen::default()));
+    set_recorder(Some(Box::new(Forward(seen.clone()))));
+    set_cache_capacity(16);
+    assert!(check(bob, doc, VIEWER_BITS).unwrap());
+    assert!(check(bob, doc, VIEWER_BITS).unwrap());
+    assert_eq!(grant(alice, bob, doc, _EDITOR).unwrap_err().0, "Denied");
+    grant(root, alice, doc, _EDITOR).unwrap();
+    create_tenant("metered").unwrap();
+    tenant("metered", bootstrap).unwrap();
+    set_recorder(None);
+    set_cache_capacity(0);
+
+    let s = seen.lock().unwrap();
+    // bob → alice by inherit, then doc → _system by parent
+    assert_eq!(s.hops[0], 2);
+    assert_eq!(s.cache[..2], [false, true]);
+    assert_eq!(s.denied, vec!["grant"]);
+    assert!(s.written.contains(&("subjects".into(), 1)) && s.written.contains(&("subjects_rev".into(), 1)));
+    assert!(s.checks.len() >= 4 && s.checks[..2] == [false, true]);
+    // Tenant writes count under the base partition name
+    assert!(s.written.iter().all(|(p, _)| !p.contains('#')));
+
+    let text = render();
+    assert!(text.contains("# TYPE capbit_check_seconds histogram"));
+    assert!(text.contains("capbit_check_seconds_count{cache=\"hit\"} ") && text.contains("capbit_check_seconds_bucket{cache=\"miss\",le=\"+Inf\"}"));
+    assert!(!text.contains("metered"));
+    assert!(text.contains("capbit_denied_total{op=\"grant\"} 1"));
+    assert!(text.contains("capbit_resolve_hops_bucket{le=\"2\"}"));
+    assert!(text.contains("capbit_partition_keys{partition=\"subjects\"}"));
+    assert!(text.lines().any(|l| l.starts_with("capbit_writes_total{partition=\"subjects\"} ")));
+    drop_tenant("metered").unwrap();
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "policy")]
+
+use capbit::{policy::*, *};
+
+const POLICY: &str = r#"
+[objects.project]
+parents = { _system = "ALL_BITS" }
+roles = { editor = "EDITOR_BITS", viewer = "VIEWER_BITS" }
+
+[objects.doc]
+parents = { project = "VIEWER_BITS" }
+
+[[grants]]
+sub = "alice"
+obj = "project"
+role = "editor"
+
+[[grants]]
+sub = "bob"
+obj = "project"
+role = "viewer"
+
+[[inherits]]
+sub = "bob"
+obj = "project"
+role = "viewer"
+parent = "alice"
+"#;
+
+#[test] fn test_plan_apply() {
+    init("target/test_db_policy").unwrap();
+    clear().unwrap();
+    let (_, root) = bootstrap().unwrap();
+    let policy = Policy::parse(POLICY).unwrap();
+
+    let p = plan(&policy, false).unwrap();
+    assert_eq!(p.to_string().lines().next(), Some("+ parent project → _system 0x3FFFFF"));
+    assert_eq!(p.changes.len(), 7);
+    assert!(lookup("project").unwrap().is_none());
+    apply(root, &p).unwrap();
+    assert!(plan(&policy, true).unwrap().is_empty());
+    let [project, doc, alice, bob] = ["project", "doc", "alice", "bob"].map(|n| lookup(n).unwrap().unwrap());
+    assert_eq!(get_mask(alice, project).unwrap(), EDITOR_BITS);
+    assert_eq!(get_mask(alice, doc).unwrap(), EDITOR_BITS & VIEWER_BITS);
+    assert_eq!(get_mask(bob, project).unwrap(), VIEWER_BITS | EDITOR_BITS);
+
+    // Drift outside the file is left alone unless pruning
+    grant(root, 77, project, _VIEWER).unwrap();
+    create(root, project, 9, 1).unwrap();
+    let edited = Policy::parse(&POLICY.replace(r#"editor = "EDITOR_BITS""#, r#"editor = "EDITOR_BITS|0x400000""#)
+        .replace("[[inherits]]\nsub = \"bob\"\nobj = \"project\"\nrole = \"viewer\"\nparent = \"alice\"\n", "")).unwrap();
+    assert_eq!(plan(&edited, false).unwrap().to_string(), "~ role editor on project 0x366 → 0x400366\n");
+    let pruned = plan(&edited, true).unwrap().to_string();
+    assert_eq!(pruned, "~ role editor on project 0x366 → 0x400366\n- inherit bob viewer on project from alice\n\
+                        - grant 77 viewer on project\n- role 9 on project 0x1\n");
+
+    // A plan applies completely or not at all
+    assert!(apply(bob, &plan(&edited, true).unwrap()).is_err());
+    assert_eq!(get_mask(77, project).unwrap(), VIEWER_BITS);
+    apply(root, &plan(&edited, true).unwrap()).unwrap();
+    assert!(plan(&edited, true).unwrap().is_empty());
+    assert_eq!(get_mask(77, project).unwrap(), 0);
+    assert_eq!(get_mask(bob, project).unwrap(), VIEWER_BITS);
+
+    // Multi-parent objects and moves converge: applying a plan leaves nothing to apply again
+    let team = "[objects.team]\nparents = { _system = \"ALL_BITS\" }\n";
+    let shared = Policy::parse(&format!("{team}[objects.sheet]\nparents = {{ project = \"ALL_BITS\", team = \"EDITOR_BITS\" }}")).unwrap();
+    let moved = Policy::parse(&format!("{team}[objects.sheet]\nparents = {{ team = \"VIEWER_BITS\" }}")).unwrap();
+    for policy in [&shared, &moved] {
+        apply(root, &plan(policy, true).unwrap()).unwrap();
+        assert!(plan(policy, true).unwrap().is_empty());
+        apply(root, &plan(policy, true).unwrap()).unwrap();
+        assert!(plan(policy, true).unwrap().is_empty());
+    }
+    // Only the narrowed edge under team is left
+    assert_eq!(get_mask(root, lookup("sheet").unwrap().unwrap()).unwrap(), VIEWER_BITS);
+
+    assert!(Policy::parse("[objects.x]\nroles = { a = 1 }\nowner = 2").unwrap_err().0.starts_with("Invalid"));
+    assert_eq!(plan(&Policy::parse("[objects.x]\nroles = { a = \"nope\" }").unwrap(), false).unwrap_err().0, "Unknown: nope");
+}
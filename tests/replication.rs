@@ -0,0 +1,112 @@
+use capbit::{replication::*, *};
+use std::net::TcpListener;
+
+// Leader and follower are two tenants of one process, linked over local TCP
+#[test] fn test_replication() {
+    init("target/test_db_replication").unwrap();
+    for t in tenants() { drop_tenant(&t).unwrap(); }
+    create_tenant("leader").unwrap();
+    create_tenant("follower").unwrap();
+    set_cache_capacity(100);
+    let (alice, doc) = (100, 200);
+
+    let (sys, root) = tenant("leader", || {
+        set_role(Role::Leader)?;
+        let ids = bootstrap()?;
+        set_parent(ids.1, doc, ids.0, ALL_BITS)?;
+        grant(ids.1, alice, ids.0, _VIEWER)?;
+        Ok(ids)
+    }).unwrap();
+    assert_eq!(tenant("leader", applied).unwrap(), 3);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    assert!(serve(TcpListener::bind("127.0.0.1:0").unwrap(), "").is_err());
+    std::thread::spawn(move || tenant("leader", || serve(listener, "s3cret")));
+
+    tenant("follower", || {
+        set_role(Role::Follower)?;
+        assert_eq!(Follower::connect(addr, "eu-west", "guess").err().unwrap().0, "Unauthorized");
+        let mut f = Follower::connect(addr, "eu-west", "s3cret")?;
+        assert_eq!(f.pull(2)?, 2);
+        assert_eq!(f.pull(100)?, 1);
+        assert_eq!(f.pull(100)?, 0);
+        assert_eq!(applied()?, 3);
+
+        // Reads are served, writes refused
+        assert!(check(alice, doc, VIEWER_BITS)?);
+        assert_eq!(list_grants(root, alice)?, vec![(sys, _VIEWER)]);
+        let e = grant(root, alice, doc, _EDITOR).unwrap_err();
+        assert_eq!((e.0.as_str(), e.kind()), ("Read only: follower", ErrorKind::Denied));
+        assert!(clear().is_err());
+        // A report writes nothing, so it runs on a follower; a repair does not
+        assert_eq!(verify(false)?, VerifyReport::default());
+        assert_eq!(verify(true).unwrap_err().kind(), ErrorKind::Denied);
+
+        // Later commits arrive in order and invalidate cached masks
+        tenant("leader", || revoke(root, alice, sys, _VIEWER))?;
+        assert_eq!(f.pull(100)?, 1);
+        assert!(!check(alice, doc, VIEWER_BITS)?);
+
+        // Entries must follow on directly
+        let skipped = Entry { seq: 9, changes: Vec::new() };
+        assert_eq!(apply(&[skipped]).unwrap_err().kind(), ErrorKind::Invalid);
+        Ok(())
+    }).unwrap();
+
+    tenant("leader", || {
+        assert_eq!(followers(), vec![("eu-west".to_string(), 3)]);
+        assert!(apply(&[]).is_err());
+        assert_eq!(truncate_log(10)?, 3);
+        assert_eq!(log_since(3, 10)?.len(), 1);
+        // A follower behind the trimmed entries has to start over from a backup
+        assert_eq!(log_since(0, 10).unwrap_err().kind(), ErrorKind::Invalid);
+        grant(root, alice, sys, _VIEWER)?;
+        assert_eq!(applied()?, 5);
+        Ok(())
+    }).unwrap();
+
+    // A store written before it became a leader ships a snapshot of that data as entry 1
+    create_tenant("seeded").unwrap();
+    create_tenant("replica").unwrap();
+    let (sys, root) = tenant("seeded", || {
+        let ids = bootstrap()?;
+        grant(ids.1, alice, ids.0, _VIEWER)?;
+        assert_eq!(log_since(0, 10).unwrap_err().kind(), ErrorKind::Invalid);
+        set_role(Role::Leader)?;
+        assert_eq!(applied()?, 1);
+        set_parent(ids.1, doc, ids.0, ALL_BITS)?;
+        Ok(ids)
+    }).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || tenant("seeded", || serve(listener, "s3cret")));
+    tenant("replica", || {
+        set_role(Role::Follower)?;
+        let mut f = Follower::connect(addr, "us-east", "s3cret")?;
+        assert_eq!(f.pull(100)?, 2);
+        assert!(check(root, sys, ALL_BITS)?);
+        assert!(check(alice, doc, VIEWER_BITS)?);
+
+        // Writes made while the leader stood alone are not logged; becoming a leader again ships
+        // a snapshot that also carries the deletes
+        tenant("seeded", || {
+            set_role(Role::Standalone)?;
+            revoke(root, alice, sys, _VIEWER)?;
+            assert_eq!(log_since(2, 10).unwrap_err().kind(), ErrorKind::Invalid);
+            set_role(Role::Leader)?;
+            assert_eq!(applied()?, 3);
+            set_role(Role::Leader)?;
+            applied()
+        })?;
+        assert_eq!(f.pull(100)?, 1);
+        assert!(!check(alice, doc, VIEWER_BITS)?);
+        assert!(check(root, sys, ALL_BITS)?);
+        Ok(())
+    }).unwrap();
+
+    // A store with data of its own can not start following
+    let e = tenant("seeded", || { set_role(Role::Standalone)?; grant(root, alice, sys, _VIEWER)?; set_role(Role::Follower) }).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Conflict);
+    set_cache_capacity(0);
+}
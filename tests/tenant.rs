@@ -0,0 +1,54 @@
+use capbit::*;
+use std::sync::{Arc, Mutex};
+
+#[test] fn test_tenants() {
+    init("target/test_db_tenant").unwrap();
+    clear().unwrap();
+    for t in tenants() { drop_tenant(&t).unwrap(); }
+    set_cache_capacity(100);
+    let (alice, doc) = (100, 200);
+
+    create_tenant("acme").unwrap();
+    create_tenant("globex").unwrap();
+    assert_eq!(create_tenant("acme").unwrap_err().kind(), ErrorKind::Conflict);
+    assert_eq!(create_tenant("a#b").unwrap_err().kind(), ErrorKind::Invalid);
+    assert_eq!(tenant("nobody", bootstrap).unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(tenants(), ["acme", "globex"]);
+
+    // Every tenant bootstraps its own _SYSTEM/_ROOT
+    let (sys, root) = tenant("acme", bootstrap).unwrap();
+    assert_eq!(tenant("globex", bootstrap).unwrap(), (sys, root));
+    assert!(tenant("acme", bootstrap).is_err());
+    bootstrap().unwrap();
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let s = seen.clone();
+    watch::subscribe(move |c| { s.lock().unwrap().push(c.tenant.clone()); true });
+    tenant("acme", || {
+        assert_eq!(current_tenant().as_deref(), Some("acme"));
+        set_parent(root, doc, sys, ALL_BITS)?;
+        grant(root, alice, sys, _VIEWER)
+    }).unwrap();
+    assert_eq!(current_tenant(), None);
+    assert_eq!(seen.lock().unwrap().last().unwrap().as_deref(), Some("acme"));
+
+    // Same ids, separate data and separate cache entries
+    assert!(tenant("acme", || check(alice, doc, VIEWER_BITS)).unwrap());
+    assert!(!tenant("globex", || check(alice, doc, VIEWER_BITS)).unwrap());
+    assert!(!check(alice, doc, VIEWER_BITS).unwrap());
+    assert_eq!(tenant("globex", || list_subjects(root, sys)).unwrap(), vec![(root, _OWNER)]);
+    assert_eq!(tenant("acme", || list_grants(root, alice)).unwrap(), vec![(sys, _VIEWER)]);
+
+    // Export, then drop in one call
+    let path = "target/test_tenant_acme.bak";
+    assert!(export_tenant("acme", path).unwrap() > 0);
+    drop_tenant("acme").unwrap();
+    assert_eq!(tenants(), ["globex"]);
+    assert_eq!(tenant("acme", || check(alice, doc, VIEWER_BITS)).unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(drop_tenant("acme").unwrap_err().kind(), ErrorKind::NotFound);
+
+    // Recreated empty, with nothing left in the cache
+    create_tenant("acme").unwrap();
+    assert!(!tenant("acme", || check(alice, doc, VIEWER_BITS)).unwrap());
+    set_cache_capacity(0);
+}
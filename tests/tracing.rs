@@ -0,0 +1,80 @@
+#![cfg(feature = "tracing")]
+
+use capbit::*;
+use std::sync::{Arc, Mutex};
+use tracing::{field::{Field, Visit}, span, Event, Metadata, Subscriber};
+
+// Spans by name with every field recorded on them, and events as field lists
+#[derive(Default)]
+struct Log { spans: Vec<(String, Vec<String>)>, events: Vec<Vec<String>> }
+
+// Tracks entered spans so the library's Span::current() finds them
+struct Capture { log: Arc<Mutex<Log>>, meta: Mutex<Vec<&'static Metadata<'static>>>, stack: Mutex<Vec<u64>> }
+
+struct Fields<'a>(&'a mut Vec<String>);
+impl Visit for Fields<'_> {
+    fn record_debug(&mut self, f: &Field, v: &dyn std::fmt::Debug) { self.0.push(format!("{}={v:?}", f.name())); }
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool { true }
+    fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+        let mut fields = Vec::new();
+        attrs.record(&mut Fields(&mut fields));
+        let mut log = self.log.lock().unwrap();
+        log.spans.push((attrs.metadata().name().into(), fields));
+        self.meta.lock().unwrap().push(attrs.metadata());
+        span::Id::from_u64(log.spans.len() as u64)
+    }
+    fn record(&self, id: &span::Id, values: &span::Record<'_>) {
+        let mut log = self.log.lock().unwrap();
+        values.record(&mut Fields(&mut log.spans[id.into_u64() as usize - 1].1));
+    }
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+    fn event(&self, e: &Event<'_>) {
+        let mut fields = Vec::new();
+        e.record(&mut Fields(&mut fields));
+        self.log.lock().unwrap().events.push(fields);
+    }
+    fn enter(&self, id: &span::Id) { self.stack.lock().unwrap().push(id.into_u64()); }
+    fn exit(&self, _: &span::Id) { self.stack.lock().unwrap().pop(); }
+    fn current_span(&self) -> tracing_core::span::Current {
+        match self.stack.lock().unwrap().last() {
+            Some(&id) => tracing_core::span::Current::new(span::Id::from_u64(id), self.meta.lock().unwrap()[id as usize - 1]),
+            None => tracing_core::span::Current::none(),
+        }
+    }
+}
+
+#[test] fn test_tracing() {
+    init("target/test_db_tracing").unwrap();
+    clear().unwrap();
+    let (sys, root) = bootstrap().unwrap();
+    let (alice, bob, doc) = (100, 101, 200);
+    set_parent(root, doc, sys, ALL_BITS).unwrap();
+    grant(root, alice, sys, _VIEWER).unwrap();
+    grant(root, bob, sys, _VIEWER).unwrap();
+    inherit(root, bob, sys, _VIEWER, alice).unwrap();
+
+    let log = Arc::new(Mutex::new(Log::default()));
+    tracing::subscriber::with_default(Capture { log: log.clone(), meta: Mutex::default(), stack: Mutex::default() }, || {
+        assert!(check(bob, doc, VIEWER_BITS).unwrap());
+        assert!(grant(alice, bob, doc, _EDITOR).is_err());
+        grant(root, alice, doc, _EDITOR).unwrap();
+    });
+
+    let log = log.lock().unwrap();
+    let span = |name: &str| log.spans.iter().filter(|(n, _)| n == name).map(|(_, f)| f.clone()).collect::<Vec<_>>();
+    assert_eq!(span("check")[0][..3], ["sub=101", "obj=200", "req=792"]);
+    // bob → alice by inherit, then doc → _system by parent
+    assert!(span("get_mask")[0].contains(&"hops=2".to_string()));
+    let grants = span("grant");
+    assert_eq!(grants.len(), 2);
+    assert_eq!(grants[1][..4], ["actor=2", "sub=100", "obj=200", "role=3"]);
+    let auth: Vec<_> = log.events.iter().filter(|e| e.contains(&"message=auth".to_string())).collect();
+    assert!(auth[0].contains(&"actor=100".to_string()) && auth[0].contains(&"allowed=false".to_string()));
+    assert!(auth[1].contains(&"allowed=true".to_string()));
+    assert!(log.events.iter().any(|e| e.contains(&"error=Denied".to_string())));
+    assert_eq!(span("commit").len(), 1);
+    assert!(span("commit")[0][0].starts_with("keys="));
+}